@@ -0,0 +1,119 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use crate::emulator::Chip8;
+
+/// Returns the size in bytes of an instruction (F000 NNNN is 4 bytes long on XO-CHIP)
+pub fn instr_size(instr: u16) -> u16 {
+    if instr == 0xF000 { 4 } else { 2 }
+}
+
+/// Returns true if the instruction conditionally skips the next one
+pub fn is_skip(instr: u16) -> bool {
+    matches!(Chip8::decode_to_nibbles(instr),
+        (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0) |
+        (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1))
+}
+
+/// Static view of the code reachable from the entry point of a ROM
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub base: u16, // Address the ROM is loaded at
+    pub rom: Vec<u8>,
+    pub instructions: BTreeMap<u16, u16>, // Reachable instruction address -> Opcode
+    pub edges: Vec<(u16, u16)>, // Jumps, calls and skips (from, to)
+    pub subroutines: BTreeSet<u16>, // Call targets
+    pub dynamic_jumps: Vec<u16>, // BNNN, which can't be followed
+    pub i_loads: BTreeMap<u16, u16>, // ANNN address -> NNN
+}
+
+impl Analysis {
+    /// Follows every path from the first instruction of a ROM loaded at `base`
+    pub fn new(rom: &[u8], base: u16) -> Self {
+        let mut analysis = Self {
+            base,
+            rom: rom.to_vec(),
+            instructions: BTreeMap::new(),
+            edges: Vec::new(),
+            subroutines: BTreeSet::new(),
+            dynamic_jumps: Vec::new(),
+            i_loads: BTreeMap::new(),
+        };
+
+        let mut pending = vec![base];
+        while let Some(addr) = pending.pop() {
+            if analysis.instructions.contains_key(&addr) { continue; }
+            let instr = match analysis.read(addr) {
+                Some(i) => i,
+                None => continue // Outside of the ROM
+            };
+            analysis.instructions.insert(addr, instr);
+
+            if instr & 0xF000 == 0x2000 { analysis.subroutines.insert(instr & 0xFFF); }
+            if instr & 0xF000 == 0xB000 { analysis.dynamic_jumps.push(addr); }
+            if instr & 0xF000 == 0xA000 { analysis.i_loads.insert(addr, instr & 0xFFF); }
+            for (to, is_edge) in analysis.successors(addr, instr, true) {
+                if is_edge { analysis.edges.push((addr, to)); }
+                pending.push(to);
+            }
+        }
+        analysis
+    }
+
+    /// Reads the two bytes at an address of the ROM
+    pub fn read(&self, addr: u16) -> Option<u16> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        match (self.rom.get(offset), self.rom.get(offset + 1)) {
+            (Some(&b1), Some(&b2)) => Some(((b1 as u16) << 8) | b2 as u16),
+            (_, _) => None
+        }
+    }
+
+    /// Returns the address following the end of the ROM
+    pub fn end(&self) -> u16 {
+        self.base + self.rom.len() as u16
+    }
+
+    /// Returns the addresses execution can continue at after an instruction,
+    /// each flagged as a jump, call or skip (true) or a plain fall through (false).
+    /// Calls are assumed to return, their target is only included if `into_calls` is set
+    pub fn successors(&self, addr: u16, instr: u16, into_calls: bool) -> Vec<(u16, bool)> {
+        let next = addr + instr_size(instr);
+        match Chip8::decode_to_nibbles(instr) {
+            (0x0, 0x0, 0xE, 0xE) | (0x0, 0x0, 0xF, 0xD) => vec![], // RTS, exit
+            (0x1, _, _, _) => vec![(instr & 0xFFF, true)],
+            (0x2, _, _, _) if into_calls => vec![(instr & 0xFFF, true), (next, false)],
+            (0xB, _, _, _) => vec![], // Depends on V0
+            _ if is_skip(instr) => {
+                let skipped = self.read(next).map(instr_size).unwrap_or(2);
+                vec![(next, false), (next + skipped, true)]
+            },
+            _ => vec![(next, false)]
+        }
+    }
+
+    /// Returns the addresses reachable from an entry point without entering calls
+    pub fn local_reach(&self, entry: u16) -> Vec<u16> {
+        let mut seen = HashSet::new();
+        let mut pending = vec![entry];
+        while let Some(addr) = pending.pop() {
+            if !seen.insert(addr) { continue; }
+            if let Some(&instr) = self.instructions.get(&addr) {
+                pending.extend(self.successors(addr, instr, false).into_iter().map(|s| s.0));
+            }
+        }
+        let mut reach: Vec<u16> = seen.into_iter().filter(|a| self.instructions.contains_key(a)).collect();
+        reach.sort();
+        reach
+    }
+
+    /// Returns the instruction covering an address without starting at it, if any
+    pub fn instruction_around(&self, addr: u16) -> Option<u16> {
+        let (&start, &instr) = self.instructions.range(..addr).next_back()?;
+        if start + instr_size(instr) > addr { Some(start) } else { None }
+    }
+
+    /// Returns true if a ROM byte belongs to a reachable instruction
+    pub fn is_code(&self, addr: u16) -> bool {
+        self.instructions.contains_key(&addr) || self.instruction_around(addr).is_some()
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::disassembler::{opcode_class, OpcodeClass};
+use crate::emulator::{Bus, Chip8, NoObserver};
+use crate::headless::{Input, Runner, Step};
+
+// Only one instruction in this many is timed, reading the clock around every step would
+// cost about as much as the step itself. Prime so that it doesn't follow the loops of a rom
+const SAMPLE_EVERY: u64 = 61;
+
+/// Time spent on one opcode class
+#[derive(Debug, Clone, Copy)]
+pub struct ClassTiming {
+    pub class: OpcodeClass,
+    pub count: u64,
+    pub sampled: u64, // Instructions timed
+    pub time: Duration, // Spent on the instructions timed
+}
+
+impl ClassTiming {
+    /// Returns the average time of an instruction in nanoseconds, None if none was timed
+    pub fn per_instr(&self) -> Option<f64> {
+        (self.sampled > 0).then(|| self.time.as_nanos() as f64 / self.sampled as f64)
+    }
+
+    /// Returns the time estimated for all the instructions of the class
+    pub fn estimated(&self) -> Duration {
+        Duration::from_nanos((self.per_instr().unwrap_or(0.0) * self.count as f64) as u64)
+    }
+}
+
+/// Result of a benchmark run
+#[derive(Debug, Clone)]
+pub struct BenchReport {
+    pub instructions: u64, // Instructions executed
+    pub frames: u64, // 60Hz frames emulated
+    pub elapsed: Duration,
+    pub classes: Vec<ClassTiming>,
+    pub failed: Option<Step>, // Instruction the run stopped on, if any
+    pub display: DisplayTiming,
+}
+
+/// Time spent reading the display at the end of frames where it changed, as a frontend would
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DisplayTiming {
+    pub frames: u64, // Frames where the display changed
+    pub copy: Duration, // Copying it out as booleans
+    pub scan: Duration, // Listing the pixels turned on from the borrowed framebuffer
+}
+
+impl BenchReport {
+    /// Returns the number of instructions executed per second
+    pub fn instructions_per_sec(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Returns the number of 60Hz frames emulated per second
+    pub fn frames_per_sec(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Runs the emulator unthrottled and headless for a number of instructions.
+/// Input is a deterministic key sweep so runs can be compared with each other
+pub fn run<B: Bus>(emu: Chip8<NoObserver, B>, instructions: u64) -> BenchReport {
+    let mut runner = Runner::new(emu, Input::default());
+    let mut classes = OpcodeClass::ALL.map(|class| ClassTiming { class, count: 0, sampled: 0, time: Duration::ZERO });
+    let mut failed = None;
+    let mut display = DisplayTiming::default();
+    let mut frame = runner.get_frame();
+
+    let start = Instant::now();
+    while runner.get_cycle() < instructions {
+        let sample = runner.get_cycle() % SAMPLE_EVERY == 0;
+        let before = sample.then(Instant::now);
+        let step = runner.step();
+        let time = before.map(|b| b.elapsed());
+
+        let step = match step {
+            Ok(step) => step,
+            Err(step) => { failed = Some(step); break; }
+        };
+        let timing = &mut classes[opcode_class(step.instr) as usize]; // ALL follows declaration order
+        timing.count += 1;
+        if let Some(time) = time {
+            timing.sampled += 1;
+            timing.time += time;
+        }
+
+        if runner.get_frame() != frame { // End of a frame
+            frame = runner.get_frame();
+            if runner.get_emu().framebuffer().is_dirty() {
+                let before = Instant::now();
+                std::hint::black_box(runner.get_emu().get_display());
+                display.copy += before.elapsed();
+                let before = Instant::now();
+                std::hint::black_box(runner.get_emu().framebuffer().pixels().count());
+                display.scan += before.elapsed();
+                display.frames += 1;
+                runner.get_emu_mut().take_dirty();
+            }
+        }
+    }
+    let elapsed = start.elapsed() - display.copy - display.scan;
+
+    BenchReport {
+        instructions: runner.get_cycle(),
+        frames: runner.get_frame(),
+        elapsed,
+        classes: classes.into_iter().filter(|c| c.count > 0).collect(),
+        failed,
+        display,
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Instructions:\t{}", self.instructions)?;
+        writeln!(f, "Frames:\t\t{}", self.frames)?;
+        writeln!(f, "Elapsed:\t{:.3} s", self.elapsed.as_secs_f64())?;
+        writeln!(f, "Throughput:\t{:.0} instructions/s", self.instructions_per_sec())?;
+        writeln!(f, "Frame rate:\t{:.0} frames/s ({:.1}x realtime)", self.frames_per_sec(), self.frames_per_sec() / 60.0)?;
+        if let Some(step) = self.failed {
+            writeln!(f, "Stopped on:\t{}", step)?;
+        }
+        if self.display.frames > 0 {
+            let per_frame = |time: Duration| time.as_nanos() as f64 / self.display.frames as f64;
+            writeln!(f, "Display:\t{} frames changed, {:.0} ns/frame to copy, {:.0} ns/frame to scan",
+                self.display.frames, per_frame(self.display.copy), per_frame(self.display.scan))?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Class\t\tCount\t\tTime (ms)\tns/instr\tShare\t(1 in {} instructions timed)", SAMPLE_EVERY)?;
+        let total: Duration = self.classes.iter().map(|c| c.estimated()).sum();
+        for c in &self.classes {
+            let ns = c.per_instr().map_or("-".to_string(), |ns| format!("{:.1}", ns));
+            writeln!(f, "{:<8}\t{:<8}\t{:<8.3}\t{:<8}\t{:.1}%",
+                c.class.name(),
+                c.count,
+                c.estimated().as_secs_f64() * 1000.0,
+                ns,
+                c.estimated().as_secs_f64() / total.as_secs_f64().max(f64::EPSILON) * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::emulator::Quirks;
+use crate::octo;
+
+/// Settings embedded in a cartridge, named as in Octo
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tickrate: Option<u32>, // Instructions per 60Hz frame
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fill_color: Option<String>, // "#RRGGBB"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shift_quirks: Option<bool>, // Shift VX in place
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_store_quirks: Option<bool>, // Leave I unchanged on FX55 / FX65
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub jump_quirks: Option<bool>, // BXNN
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logic_quirks: Option<bool>, // Reset VF on logic operations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clip_quirks: Option<bool>, // Clip sprites instead of wrapping them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, u8>, // Action ("up", "a"...) -> CHIP-8 key, as in the ROM database
+}
+
+impl Options {
+    /// Returns options describing quirks
+    pub fn from_quirks(quirks: Quirks) -> Self {
+        Self {
+            shift_quirks: Some(!quirks.shift_vy),
+            load_store_quirks: Some(!quirks.load_store_increment_i),
+            jump_quirks: Some(quirks.jump_vx),
+            logic_quirks: Some(quirks.vf_reset),
+            clip_quirks: Some(!quirks.wrap_sprites),
+            ..Self::default()
+        }
+    }
+
+    /// Sets the (background, foreground) colors
+    pub fn with_palette(mut self, background: [u8; 3], foreground: [u8; 3]) -> Self {
+        let hex = |c: [u8; 3]| format!("#{:02X}{:02X}{:02X}", c[0], c[1], c[2]);
+        self.background_color = Some(hex(background));
+        self.fill_color = Some(hex(foreground));
+        self
+    }
+
+    /// Returns the quirks described by the options, starting from a base for the missing ones
+    pub fn quirks(&self, base: Quirks) -> Quirks {
+        Quirks {
+            shift_vy: self.shift_quirks.map(|q| !q).unwrap_or(base.shift_vy),
+            load_store_increment_i: self.load_store_quirks.map(|q| !q).unwrap_or(base.load_store_increment_i),
+            jump_vx: self.jump_quirks.unwrap_or(base.jump_vx),
+            vf_reset: self.logic_quirks.unwrap_or(base.vf_reset),
+            wrap_sprites: self.clip_quirks.map(|q| !q).unwrap_or(base.wrap_sprites),
+        }
+    }
+
+    /// Returns the (background, foreground) colors
+    pub fn palette(&self) -> Option<([u8; 3], [u8; 3])> {
+        Some((parse_color(self.background_color.as_ref()?)?, parse_color(self.fill_color.as_ref()?)?))
+    }
+}
+
+// Parses a "#RRGGBB" color
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 { return None; }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Payload {
+    #[serde(default)]
+    options: Options,
+    program: String, // Octo source
+}
+
+/// An Octo cartridge: a program along with the options it was published with
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    pub program: Vec<u8>,
+    pub options: Options,
+}
+
+/// Returns true if the bytes look like a GIF image rather than a ROM
+pub fn is_cartridge(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")
+}
+
+/// Decodes an Octo cartridge GIF.
+/// The payload is hidden in the two low bits of each pixel's palette index, frames
+/// following each other, four pixels per byte (high bits first). It starts with its
+/// length as a 32 bit big endian number followed by `{"options": {...}, "program": "..."}`
+pub fn parse(bytes: &[u8]) -> Result<Cartridge, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(bytes).map_err(|e| format!("Invalid GIF: {}", e))?;
+
+    let mut data = Vec::<u8>::new();
+    let (mut byte, mut pairs) = (0u8, 0);
+    while let Some(frame) = decoder.read_next_frame().map_err(|e| format!("Invalid GIF: {}", e))? {
+        for pixel in frame.buffer.iter() {
+            byte = (byte << 2) | (pixel & 0b11);
+            pairs += 1;
+            if pairs == 4 {
+                data.push(byte);
+                pairs = 0;
+            }
+        }
+    }
+
+    let size = match data.get(0..4) {
+        Some(s) => u32::from_be_bytes([s[0], s[1], s[2], s[3]]) as usize,
+        None => return Err("No payload in the GIF".to_string())
+    };
+    let json = data.get(4..4 + size).ok_or("Payload is truncated, not an Octo cartridge ?")?;
+    let json = std::str::from_utf8(json).map_err(|_| "Payload isn't text, not an Octo cartridge ?")?;
+    let payload: Payload = serde_json::from_str(json).map_err(|e| format!("Invalid payload: {}", e))?;
+
+    Ok(Cartridge {
+        program: octo::assemble(&payload.program).map_err(|e| format!("Couldn't assemble the program: {}", e))?,
+        options: payload.options,
+    })
+}
+
+// Width of the encoded image, a row holds WIDTH / 4 payload bytes
+const WIDTH: u16 = 128;
+
+/// Encodes a cartridge GIF, the reverse of `parse`.
+/// The program is written as Octo source made of byte literals, so that Octo can edit it
+pub fn encode(cartridge: &Cartridge) -> Result<Vec<u8>, String> {
+    let payload = Payload { options: cartridge.options.clone(), program: disassemble_bytes(&cartridge.program) };
+    let json = serde_json::to_string(&payload).map_err(|e| e.to_string())?;
+    encode_payload(&json)
+}
+
+// Hides a JSON payload in a GIF
+fn encode_payload(json: &str) -> Result<Vec<u8>, String> {
+    let mut data = (json.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(json.as_bytes());
+
+    let mut pixels: Vec<u8> = data.iter().flat_map(|b| [b >> 6, b >> 4, b >> 2, *b].map(|p| p & 0b11)).collect();
+    let height = pixels.len().div_ceil(WIDTH as usize);
+    if height > u16::MAX as usize { return Err("Program is too large for a cartridge".to_string()); }
+    pixels.resize(height * WIDTH as usize, 0);
+
+    // Only the indices carry the payload, the colors just make a readable image
+    let palette = [0x99, 0x66, 0x00, 0xFF, 0xCC, 0x00, 0xFF, 0x66, 0x00, 0x66, 0x22, 0x00];
+    let mut gif = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut gif, WIDTH, height as u16, &palette).map_err(|e| e.to_string())?;
+        let frame = gif::Frame::from_indexed_pixels(WIDTH, height as u16, &pixels, None);
+        encoder.write_frame(&frame).map_err(|e| e.to_string())?;
+    }
+    Ok(gif)
+}
+
+/// Writes a program as Octo source made of byte literals, 16 per line
+pub fn disassemble_bytes(program: &[u8]) -> String {
+    let mut source = String::from(": main\n");
+    for line in program.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|b| format!("0x{:02X}", b)).collect();
+        source.push_str(&bytes.join(" "));
+        source.push('\n');
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn octo_source_cartridge() {
+        let program = "# Moves a dot across the screen\n\
+            : main\n\
+            \tv0 := 32  v1 := 16  i := dot\n\
+            \tloop\n\
+            \t\tsprite v0 v1 1  v0 += 1  sprite v0 v1 1\n\
+            \t\tif v0 == 63 then v0 := 0\n\
+            \tagain\n\
+            : dot 0x80\n";
+        let json = serde_json::json!({
+            "options": { "tickrate": 20, "fillColor": "#FFCC00", "backgroundColor": "#996600", "shiftQuirks": true, "keys": { "left": 7 } },
+            "program": program,
+        });
+        let cartridge = parse(&encode_payload(&json.to_string()).unwrap()).unwrap();
+        assert_eq!(cartridge.program, [
+            0x60, 0x20, 0x61, 0x10, 0xA2, 0x12,
+            0xD0, 0x11, 0x70, 0x01, 0xD0, 0x11, 0x40, 0x3F, 0x60, 0x00, 0x12, 0x06,
+            0x80,
+        ]);
+        assert_eq!(cartridge.options.tickrate, Some(20));
+        assert_eq!(cartridge.options.palette(), Some(([0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00])));
+        assert!(!cartridge.options.quirks(Quirks { shift_vy: true, ..Quirks::default() }).shift_vy);
+        assert_eq!(cartridge.options.keys["left"], 7);
+    }
+
+    #[test]
+    fn encoded_cartridge_round_trip() {
+        let mut options = Options::from_quirks(Quirks::default()).with_palette([0; 3], [0xFF; 3]);
+        options.keys.insert("a".to_string(), 5);
+        let cartridge = Cartridge { program: vec![0x00, 0xE0, 0x12, 0x00, 0xFF], options };
+        let decoded = parse(&encode(&cartridge).unwrap()).unwrap();
+        assert_eq!(decoded.program, cartridge.program);
+        assert_eq!(decoded.options.palette(), Some(([0; 3], [0xFF; 3])));
+        assert_eq!(decoded.options.keys, cartridge.options.keys);
+
+        // Octo leaves the keys out
+        assert!(!serde_json::to_string(&Options::default()).unwrap().contains("keys"));
+    }
+}
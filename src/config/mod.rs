@@ -0,0 +1,87 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Settings to run roms with, any of which can be left unset
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pixel_size: Option<u32>, // Window pixels per CHIP-8 pixel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub foreground: Option<String>, // "RRGGBB"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u32>, // Instructions per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quirks: Option<String>, // Comma separated, replaces the platform's
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, String>, // Keyboard key (SDL name) -> CHIP-8 key (hex digit)
+}
+
+impl Settings {
+    /// Returns these settings overridden by the ones set in `over`.
+    /// Choosing a platform without quirks drops the quirks chosen below, as they replace the platform's
+    pub fn merge(mut self, over: &Settings) -> Settings {
+        if over.platform.is_some() && over.quirks.is_none() {
+            self.quirks = None;
+        }
+        self.pixel_size = over.pixel_size.or(self.pixel_size);
+        self.foreground = over.foreground.clone().or(self.foreground);
+        self.background = over.background.clone().or(self.background);
+        self.speed = over.speed.or(self.speed);
+        self.platform = over.platform.clone().or(self.platform);
+        self.quirks = over.quirks.clone().or(self.quirks);
+        self.keys.extend(over.keys.clone());
+        self
+    }
+
+    /// Returns the settings as TOML
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap() // Values all come before the keys table
+    }
+}
+
+/// User configuration: defaults, then overrides for some roms
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub defaults: Settings,
+    #[serde(default)]
+    pub roms: BTreeMap<String, Settings>, // Rom SHA-1 or file name -> Overrides
+}
+
+impl Config {
+    /// Returns the default location of the configuration file
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("chip8emu").join("config.toml"))
+    }
+
+    /// Reads a configuration such as
+    /// ```toml
+    /// [defaults]
+    /// pixel_size = 12
+    /// foreground = "FFFFFF"
+    ///
+    /// [roms."breakout.ch8"]
+    /// speed = 1200
+    ///
+    /// [roms.237756a4014fb3aa82a29246a7cdd534f8dc2dbb.keys]
+    /// Left = "4"
+    /// ```
+    pub fn from_toml(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    /// Returns the overrides for a rom, by file name then by hash
+    pub fn overrides_for(&self, sha1: &str, file_name: &str) -> Settings {
+        [file_name, sha1].iter()
+            .filter_map(|key| self.roms.get(*key))
+            .fold(Settings::default(), |settings, over| settings.merge(over))
+    }
+}
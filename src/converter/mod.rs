@@ -0,0 +1,153 @@
+use crate::cartridge::{self, Cartridge};
+use crate::loader::Rom;
+
+/// File formats a rom can be converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    IntelHex,
+    HexDump, // xxd style
+    Rust, // Byte array constant
+    C, // Byte array definition
+    Cartridge, // Octo cartridge GIF
+}
+
+impl Target {
+    pub const ALL: [Target; 5] = [Target::IntelHex, Target::HexDump, Target::Rust, Target::C, Target::Cartridge];
+
+    /// Returns the name used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::IntelHex => "ihex",
+            Target::HexDump => "dump",
+            Target::Rust => "rust",
+            Target::C => "c",
+            Target::Cartridge => "octo",
+        }
+    }
+
+    /// Returns the target with a given name
+    pub fn from_name(name: &str) -> Option<Target> {
+        Target::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// Guesses the target from an output file extension
+    pub fn from_extension(extension: &str) -> Option<Target> {
+        match extension.to_ascii_lowercase().as_str() {
+            "hex" | "ihex" | "ihx" => Some(Target::IntelHex),
+            "txt" | "dump" => Some(Target::HexDump),
+            "rs" => Some(Target::Rust),
+            "c" | "h" => Some(Target::C),
+            "gif" => Some(Target::Cartridge),
+            _ => None
+        }
+    }
+}
+
+/// Converts a rom, `name` is used for the array in source files and `options` for cartridges
+pub fn convert(rom: &Rom, target: Target, name: &str, options: cartridge::Options) -> Result<Vec<u8>, String> {
+    match target {
+        Target::IntelHex => Ok(intel_hex(rom).into_bytes()),
+        Target::HexDump => Ok(hex_dump(&rom.data).into_bytes()),
+        Target::Rust => Ok(rust_source(rom, name).into_bytes()),
+        Target::C => Ok(c_source(rom, name).into_bytes()),
+        Target::Cartridge => cartridge::encode(&Cartridge { program: rom.data.clone(), options }),
+    }
+}
+
+/// Writes Intel HEX records at the rom's load address
+pub fn intel_hex(rom: &Rom) -> String {
+    let mut out = String::new();
+    for (n, chunk) in rom.data.chunks(16).enumerate() {
+        let address = rom.address + n * 16;
+        let mut record = vec![chunk.len() as u8, (address >> 8) as u8, address as u8, 0x00];
+        record.extend_from_slice(chunk);
+        let checksum = record.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+        record.push(checksum);
+        out.push(':');
+        out.extend(record.iter().map(|b| format!("{:02X}", b)));
+        out.push('\n');
+    }
+    out.push_str(":00000001FF\n"); // End of file
+    out
+}
+
+/// Writes a dump like `xxd`: offset, 16 bytes in pairs and their ascii
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (n, chunk) in data.chunks(16).enumerate() {
+        let groups: Vec<String> = chunk.chunks(2).map(|g| g.iter().map(|b| format!("{:02x}", b)).collect()).collect();
+        let ascii: String = chunk.iter().map(|b| if b.is_ascii_graphic() || *b == b' ' { *b as char } else { '.' }).collect();
+        out.push_str(&format!("{:08x}: {:<39}  {}\n", n * 16, groups.join(" "), ascii));
+    }
+    out
+}
+
+// Lists bytes as 0x literals, 12 per indented line
+fn byte_lines(data: &[u8]) -> String {
+    data.chunks(12)
+        .map(|line| format!("    {},\n", line.iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(", ")))
+        .collect()
+}
+
+/// Writes a Rust constant holding the rom
+pub fn rust_source(rom: &Rom, name: &str) -> String {
+    let name = identifier(name).to_ascii_uppercase();
+    format!("/// Loaded at 0x{:03X}\npub const {}: [u8; {}] = [\n{}];\n", rom.address, name, rom.data.len(), byte_lines(&rom.data))
+}
+
+/// Writes a C array holding the rom
+pub fn c_source(rom: &Rom, name: &str) -> String {
+    let name = identifier(name).to_ascii_lowercase();
+    format!("/* Loaded at 0x{:03X} */\nconst unsigned char {}[{}] = {{\n{}}};\n", rom.address, name, rom.data.len(), byte_lines(&rom.data))
+}
+
+// Turns a file name into a valid identifier
+fn identifier(name: &str) -> String {
+    let name: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    match name.chars().next() {
+        Some(c) if !c.is_ascii_digit() => name,
+        _ => format!("rom_{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::{Format, Loader};
+
+    const IBM_LOGO: &[u8] = include_bytes!("../../roms/ibmlogo.ch8");
+
+    fn ibm_logo(address: usize) -> Rom {
+        Rom { data: IBM_LOGO.to_vec(), address }
+    }
+
+    #[test]
+    fn intel_hex_round_trip() {
+        let hex = intel_hex(&ibm_logo(0x200));
+        assert_eq!(Format::detect(hex.as_bytes()), Format::IntelHex);
+        let rom = Loader::new().load(hex.as_bytes()).unwrap();
+        assert_eq!((rom.data.as_slice(), rom.address), (IBM_LOGO, 0x200));
+
+        // The load address is kept
+        let rom = Loader::new().load(intel_hex(&ibm_logo(0x600)).as_bytes()).unwrap();
+        assert_eq!((rom.data.as_slice(), rom.address), (IBM_LOGO, 0x600));
+    }
+
+    #[test]
+    fn hex_dump_round_trip() {
+        let dump = hex_dump(IBM_LOGO);
+        assert_eq!(Format::detect(dump.as_bytes()), Format::HexDump);
+        assert_eq!(Loader::new().load(dump.as_bytes()).unwrap().data, IBM_LOGO);
+
+        // Odd lengths and bytes printed as spaces in the ascii column
+        let data = b"  CHIP-8  \x00\x01\xFF";
+        assert_eq!(Loader::new().load(hex_dump(data).as_bytes()).unwrap().data, data);
+    }
+
+    #[test]
+    fn source_arrays() {
+        let rom = Rom { data: vec![0x00, 0xE0], address: 0x200 };
+        assert_eq!(rust_source(&rom, "ibm-logo"), "/// Loaded at 0x200\npub const IBM_LOGO: [u8; 2] = [\n    0x00, 0xE0,\n];\n");
+        assert_eq!(c_source(&rom, "15puzzle"), "/* Loaded at 0x200 */\nconst unsigned char rom_15puzzle[2] = {\n    0x00, 0xE0,\n};\n");
+    }
+}
@@ -0,0 +1,231 @@
+use std::fmt;
+
+use crate::disassembler::disassemble;
+use crate::emulator::{Observer, MEMORY_SIZE};
+
+/// How a byte of memory has been accessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Executed,
+    Read, // Read as data through I
+    Written,
+}
+
+impl Access {
+    // Bit used in the byte flags
+    fn bit(&self) -> u8 {
+        match self {
+            Access::Executed => 0b001,
+            Access::Read => 0b010,
+            Access::Written => 0b100,
+        }
+    }
+}
+
+/// Marks each byte of memory as executed, read as data or written
+pub struct Coverage {
+    flags: Vec<u8>,
+    hits: Vec<u64>, // Number of accesses per byte, for the heatmap
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    /// Returns an empty coverage map of the 4 KiB address space
+    pub fn new() -> Self {
+        Self {
+            flags: vec![0; MEMORY_SIZE],
+            hits: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    /// Marks a byte as accessed
+    pub fn mark(&mut self, addr: usize, access: Access) {
+        if let (Some(f), Some(h)) = (self.flags.get_mut(addr), self.hits.get_mut(addr)) {
+            *f |= access.bit();
+            *h += 1;
+        }
+    }
+
+    /// Returns true if the byte has been accessed in a given way
+    pub fn has(&self, addr: usize, access: Access) -> bool {
+        self.flags.get(addr).map(|f| f & access.bit() > 0).unwrap_or(false)
+    }
+
+    /// Returns the number of bytes within a range that have been accessed in a given way
+    pub fn count(&self, start: usize, end: usize, access: Access) -> usize {
+        (start..end.min(MEMORY_SIZE)).filter(|&a| self.has(a, access)).count()
+    }
+
+    /// Returns an annotated listing of a memory range. Executed words are
+    /// disassembled, other bytes are grouped into data lines
+    pub fn listing<'a>(&'a self, memory: &'a [u8], start: usize, end: usize) -> Listing<'a> {
+        Listing { coverage: self, memory, start, end: end.min(memory.len()) }
+    }
+
+    /// Returns a terminal heatmap of the whole address space (one character per byte, 64 per row)
+    pub fn heatmap(&self) -> Heatmap<'_> {
+        Heatmap { coverage: self }
+    }
+
+    // Three letter access summary of a byte ("xrw", "--w"...)
+    fn flag_string(&self, addr: usize) -> String {
+        [(Access::Executed, 'x'), (Access::Read, 'r'), (Access::Written, 'w')].iter()
+            .map(|(a, c)| if self.has(addr, *a) { *c } else { '-' })
+            .collect()
+    }
+}
+
+/// Attach to an emulator with `Chip8::with_observer` to record its accesses
+impl Observer for Coverage {
+    fn on_fetch(&mut self, pc: u16, _instr: u16) {
+        self.mark(pc as usize, Access::Executed);
+        self.mark((pc as usize + 1) % MEMORY_SIZE, Access::Executed); // Wraps around with the address policy
+    }
+
+    fn on_mem_read(&mut self, addr: u16, _val: u8) {
+        self.mark(addr as usize, Access::Read);
+    }
+
+    fn on_mem_write(&mut self, addr: u16, _val: u8) {
+        self.mark(addr as usize, Access::Written);
+    }
+}
+
+/// Annotated listing of a memory range
+pub struct Listing<'a> {
+    coverage: &'a Coverage,
+    memory: &'a [u8],
+    start: usize,
+    end: usize,
+}
+
+impl fmt::Display for Listing<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = self.coverage;
+        let mut addr = self.start;
+        while addr < self.end {
+            if c.has(addr, Access::Executed) && addr + 1 < self.end {
+                let instr = ((self.memory[addr] as u16) << 8) | self.memory[addr + 1] as u16;
+                writeln!(f, "0x{:04X}\t{}\t{:<8}\t0x{:04X} -> {}", addr, c.flag_string(addr), c.hits[addr], instr, disassemble(instr))?;
+                addr += 2;
+            } else {
+                // Group up to 8 bytes sharing the same flags
+                let flags = c.flag_string(addr);
+                let mut bytes = Vec::<String>::new();
+                let line = addr;
+                while addr < self.end && bytes.len() < 8 && !c.has(addr, Access::Executed) && c.flag_string(addr) == flags {
+                    bytes.push(format!("0x{:02X}", self.memory[addr]));
+                    addr += 1;
+                }
+                if bytes.is_empty() { // Executed byte without a successor
+                    bytes.push(format!("0x{:02X}", self.memory[addr]));
+                    addr += 1;
+                }
+                writeln!(f, "0x{:04X}\t{}\t\t\t.db {}", line, flags, bytes.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Heatmap of the address space using ANSI colors
+pub struct Heatmap<'a> {
+    coverage: &'a Coverage,
+}
+
+impl fmt::Display for Heatmap<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = self.coverage;
+        let max = c.hits.iter().copied().max().unwrap_or(0).max(1) as f64;
+        writeln!(f, "Legend: \x1b[32mexecuted\x1b[0m \x1b[34mread\x1b[0m \x1b[31mwritten\x1b[0m \x1b[35mread and written\x1b[0m, shade = access count")?;
+        for row in 0..MEMORY_SIZE / 64 {
+            write!(f, "0x{:03X} ", row * 64)?;
+            for addr in row * 64..(row + 1) * 64 {
+                let color = match (c.has(addr, Access::Executed), c.has(addr, Access::Read), c.has(addr, Access::Written)) {
+                    (false, false, false) => { write!(f, "\x1b[90m·\x1b[0m")?; continue; },
+                    (true, _, _) => 32,
+                    (false, true, true) => 35,
+                    (false, true, false) => 34,
+                    (false, false, true) => 31,
+                };
+                // Logarithmic shade, as a few loops usually dominate
+                let heat = (c.hits[addr] as f64).ln_1p() / max.ln_1p();
+                let shade = ['░', '▒', '▓', '█'][((heat * 3.0).round() as usize).min(3)];
+                write!(f, "\x1b[{}m{}\x1b[0m", color, shade)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{AddressPolicy, Chip8};
+
+    // Runs a program with coverage, returns the emulator
+    fn run(program: &[u16], steps: usize) -> Chip8<Coverage> {
+        let program = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let mut emu = Chip8::new().load_program(program).unwrap().with_observer(Coverage::new());
+        for _ in 0..steps {
+            let instr = emu.fetch().unwrap();
+            emu.exec(instr).unwrap();
+        }
+        emu
+    }
+
+    #[test]
+    fn accesses() {
+        // LD I, 0x20A, LDR V0, STR V0 then loop, data at 0x20A
+        let emu = run(&[0xA20A, 0xF065, 0xF055, 0x1206, 0x0000, 0x4200], 5);
+        let c = emu.observer();
+        assert_eq!(c.count(0x200, 0x208, Access::Executed), 8);
+        assert!(!c.has(0x208, Access::Executed));
+        assert!(c.has(0x20A, Access::Read) && c.has(0x20A, Access::Written));
+        assert!(!c.has(0x20B, Access::Read));
+        assert_eq!(c.count(0, MEMORY_SIZE, Access::Read), 1);
+        assert_eq!(c.hits[0x206], 2);
+    }
+
+    #[test]
+    fn listing() {
+        let emu = run(&[0xA206, 0xF065, 0x1204, 0x4200], 4);
+        let listing = emu.observer().listing(&emu.get_memory(), 0x200, 0x208).to_string();
+        assert_eq!(listing.lines().collect::<Vec<_>>(), [
+            format!("0x0200\tx--\t1       \t0xA206 -> {}", disassemble(0xA206)),
+            format!("0x0202\tx--\t1       \t0xF065 -> {}", disassemble(0xF065)),
+            format!("0x0204\tx--\t2       \t0x1204 -> {}", disassemble(0x1204)),
+            "0x0206\t-r-\t\t\t.db 0x42".to_string(),
+            "0x0207\t---\t\t\t.db 0x00".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn heatmap() {
+        let emu = run(&[0x1200], 3);
+        let heatmap = emu.observer().heatmap().to_string();
+        assert_eq!(heatmap.lines().count(), 1 + MEMORY_SIZE / 64);
+        let row = heatmap.lines().nth(1 + 0x200 / 64).unwrap();
+        assert!(row.starts_with("0x200 \x1b[32m█\x1b[0m\x1b[32m█"));
+    }
+
+    #[test]
+    fn fetch_at_the_end_of_memory_wraps() {
+        // JP 0x200 across the end of memory
+        let mut emu = Chip8::new().set_address_policy(AddressPolicy::Wrap)
+            .load_program_at(vec![0x00], 0x000).unwrap()
+            .load_program_at(vec![0x12], 0xFFF).unwrap()
+            .with_observer(Coverage::new());
+        let instr = emu.fetch().unwrap();
+        emu.exec(instr).unwrap();
+        let c = emu.observer();
+        assert!(c.has(0xFFF, Access::Executed) && c.has(0x000, Access::Executed));
+        assert_eq!(emu.get_pc(), 0x200);
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::emulator::Quirks;
+use crate::platform::Platform;
+
+/// Database shipped with the emulator, in the community chip-8-database `programs.json` format
+const BUNDLED: &str = include_str!("programs.json");
+
+/// Returns the lowercase hex SHA-1 of a ROM, the key of the database
+pub fn sha1_hex(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>, // Instructions per frame
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, HashMap<String, bool>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>, // "#RRGGBB", background first
+}
+
+/// What the database knows about a ROM
+#[derive(Debug, Clone)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Option<Platform>,
+    pub quirks: Option<Quirks>,
+    pub tickrate: Option<u32>, // Instructions per 60Hz frame
+    pub palette: Option<([u8; 3], [u8; 3])>, // Background, foreground
+    pub keys: HashMap<String, u8>, // Action ("up", "a"...) -> CHIP-8 key
+}
+
+// Maps a database platform id to a platform
+fn platform_from_id(id: &str) -> Option<Platform> {
+    match id {
+        "originalChip8" | "hybridVIP" | "modernChip8" | "chip8x" => Some(Platform::Chip8),
+        "chip48" | "superchip1" | "superchip" => Some(Platform::SuperChip),
+        "xochip" => Some(Platform::XoChip),
+        _ => None
+    }
+}
+
+// Parses a "#RRGGBB" color
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 { return None; }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+/// ROM metadata keyed by SHA-1
+#[derive(Debug, Clone, Default)]
+pub struct Database {
+    programs: Vec<Program>,
+}
+
+impl Database {
+    /// Returns the database shipped with the emulator
+    pub fn bundled() -> Self {
+        Self::from_json(BUNDLED).expect("Bundled database is invalid")
+    }
+
+    /// Parses a database in the chip-8-database `programs.json` format
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let programs = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        Ok(Self { programs })
+    }
+
+    /// Adds the entries of another database, taking precedence over the current ones
+    pub fn with_overrides(mut self, other: Database) -> Self {
+        let mut programs = other.programs;
+        programs.append(&mut self.programs);
+        self.programs = programs;
+        self
+    }
+
+    /// Looks up a ROM by its SHA-1
+    pub fn lookup(&self, rom: &[u8]) -> Option<RomInfo> {
+        let hash = sha1_hex(rom);
+        self.programs.iter().find_map(|p| {
+            let rom = p.roms.get(&hash)?;
+            let (platform_id, platform) = rom.platforms.iter()
+                .find_map(|id| platform_from_id(id).map(|p| (id.as_str(), p)))
+                .unzip();
+
+            // Start from the platform quirks and apply the ROM specific ones
+            let quirks = platform.map(|platform: Platform| {
+                let mut quirks = platform.quirks();
+                let overrides = platform_id.and_then(|id| rom.quirky_platforms.get(id));
+                for (name, &val) in overrides.into_iter().flatten() {
+                    match name.as_str() {
+                        "shift" => quirks.shift_vy = !val, // The database flags the SUPER-CHIP behaviour
+                        "memoryLeaveIUnchanged" => quirks.load_store_increment_i = !val,
+                        "memoryIncrementByX" => quirks.load_store_increment_i = true,
+                        "jump" => quirks.jump_vx = val,
+                        "logic" => quirks.vf_reset = val,
+                        "wrap" => quirks.wrap_sprites = val,
+                        _ => ()
+                    }
+                }
+                quirks
+            });
+
+            let palette = rom.colors.as_ref().and_then(|c| match c.pixels.as_slice() {
+                [bg, fg, ..] => Some((parse_color(bg)?, parse_color(fg)?)),
+                _ => None
+            });
+
+            Some(RomInfo {
+                title: p.title.clone(),
+                authors: p.authors.clone(),
+                platform,
+                quirks,
+                tickrate: rom.tickrate,
+                palette,
+                keys: rom.keys.clone(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTRY: &str = r##"[{
+        "title": "Test",
+        "authors": ["Someone"],
+        "roms": {
+            "1ba58656810b67fd131eb9af3e3987863bf26c90": {
+                "platforms": ["superchip", "xochip"],
+                "tickrate": 30,
+                "colors": { "pixels": ["#000000", "#FFAA00"] },
+                "quirkyPlatforms": { "superchip": { "shift": false, "jump": false, "wrap": true, "memoryIncrementByX": true } }
+            }
+        }
+    }]"##;
+
+    #[test]
+    fn bundled_lookup() {
+        let db = Database::bundled();
+        let info = db.lookup(include_bytes!("../../roms/breakout.ch8")).unwrap();
+        assert_eq!(info.title, "Breakout");
+        assert_eq!(info.platform, Some(Platform::Chip8));
+        assert_eq!(info.quirks, Some(Platform::Chip8.quirks()));
+        assert_eq!((info.keys["left"], info.keys["right"]), (4, 6));
+        assert!(db.lookup(&[0x12, 0x00]).is_none());
+    }
+
+    #[test]
+    fn quirks_are_mapped() {
+        let info = Database::from_json(ENTRY).unwrap().lookup(include_bytes!("../../roms/ibmlogo.ch8")).unwrap();
+        assert_eq!(info.platform, Some(Platform::SuperChip)); // The first known platform
+        assert_eq!(info.quirks, Some(Quirks {
+            shift_vy: true,
+            load_store_increment_i: true,
+            jump_vx: false,
+            vf_reset: false,
+            wrap_sprites: true,
+        }));
+        assert_eq!(info.tickrate, Some(30));
+        assert_eq!(info.palette, Some(([0, 0, 0], [0xFF, 0xAA, 0x00])));
+        assert_eq!(info.authors, ["Someone"]);
+    }
+
+    #[test]
+    fn overrides_take_precedence() {
+        let db = Database::bundled().with_overrides(Database::from_json(ENTRY).unwrap());
+        assert_eq!(db.lookup(include_bytes!("../../roms/ibmlogo.ch8")).unwrap().title, "Test");
+    }
+
+    #[test]
+    fn unknown_platforms_and_bad_colors() {
+        let json = ENTRY.replace("\"superchip\", \"xochip\"", "\"megachip8\"").replace("#FFAA00", "orange");
+        let info = Database::from_json(&json).unwrap().lookup(include_bytes!("../../roms/ibmlogo.ch8")).unwrap();
+        assert_eq!((info.platform, info.quirks, info.palette), (None, None, None));
+        assert!(Database::from_json("{").is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::fmt;
+
+use crate::analysis::Analysis;
+use crate::disassembler::disassemble;
+use crate::emulator::{Chip8, Quirks, MEMORY_SIZE};
+use crate::platform::Platform;
+
+/// Best guess of the platform a ROM was written for
+#[derive(Debug, Clone)]
+pub struct Detection {
+    pub platform: Platform,
+    pub quirks: Quirks,
+    pub confidence: f64, // From 0 to 1
+    pub reasons: Vec<String>, // Evidence behind the guess
+}
+
+impl fmt::Display for Detection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let quirks = self.quirks.enabled();
+        writeln!(f, "Platform:\t{} ({:.0}% confidence)", self.platform.name(), self.confidence * 100.0)?;
+        writeln!(f, "Quirks:\t\t{}", if quirks.is_empty() { "none".to_string() } else { quirks.join(", ") })?;
+        for r in &self.reasons {
+            writeln!(f, "\t\t{}", r)?;
+        }
+        Ok(())
+    }
+}
+
+// Opcodes only found on a platform, with a description
+fn extension(instr: u16) -> Option<(Platform, &'static str)> {
+    match Chip8::decode_to_nibbles(instr) {
+        (0x0, 0x0, 0xD, _) => Some((Platform::XoChip, "scroll up")),
+        (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => Some((Platform::XoChip, "register range store / load")),
+        (0xF, 0x0, 0x0, 0x0) => Some((Platform::XoChip, "long I load")),
+        (0xF, 0x0, 0x0, 0x2) => Some((Platform::XoChip, "audio pattern")),
+        (0xF, _, 0x0, 0x1) => Some((Platform::XoChip, "plane selection")),
+        (0xF, _, 0x3, 0xA) => Some((Platform::XoChip, "audio pitch")),
+        (0x0, 0x0, 0xC, _) | (0x0, 0x0, 0xF, 0xB) | (0x0, 0x0, 0xF, 0xC) => Some((Platform::SuperChip, "scrolling")),
+        (0x0, 0x0, 0xF, 0xE) | (0x0, 0x0, 0xF, 0xF) => Some((Platform::SuperChip, "resolution switch")),
+        (0x0, 0x0, 0xF, 0xD) => Some((Platform::SuperChip, "exit")),
+        (0xD, _, _, 0x0) => Some((Platform::SuperChip, "16x16 sprite")),
+        (0xF, _, 0x3, 0x0) => Some((Platform::SuperChip, "big font")),
+        (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => Some((Platform::SuperChip, "flag registers")),
+        _ => None
+    }
+}
+
+/// Guesses the platform and quirks of a ROM loaded at `base` from its reachable opcodes
+pub fn detect(rom: &[u8], base: u16) -> Detection {
+    let a = Analysis::new(rom, base);
+    let mut reasons = Vec::<String>::new();
+    let mut hits = [0usize; 3]; // Per platform, in Platform::ALL order
+
+    for (&addr, &instr) in &a.instructions {
+        if let Some((platform, what)) = extension(instr) {
+            let index = Platform::ALL.iter().position(|p| *p == platform).unwrap();
+            if hits[index] == 0 { // First hit of each platform
+                reasons.push(format!("0x{:04X}: {} ({}) needs {}", addr, disassemble(instr), what, platform.name()));
+            }
+            hits[index] += 1;
+        }
+    }
+
+    // XO-CHIP has 64 KiB of memory
+    if rom.len() > MEMORY_SIZE - base as usize {
+        reasons.push(format!("{} bytes don't fit in 4 KiB", rom.len()));
+        hits[2] += 1;
+    }
+
+    let (platform, mut confidence) = if hits[2] > 0 {
+        (Platform::XoChip, 0.8 + 0.05 * hits[2].min(4) as f64)
+    } else if hits[1] > 0 {
+        (Platform::SuperChip, 0.8 + 0.05 * hits[1].min(4) as f64)
+    } else {
+        reasons.push("only CHIP-8 opcodes are reachable".to_string());
+        (Platform::Chip8, 0.6)
+    };
+
+    // Machine code calls only make sense on the COSMAC VIP
+    if a.instructions.values().any(|&i| i & 0xF000 == 0 && i != 0x00E0 && i != 0x00EE && extension(i).is_none()) {
+        reasons.push("calls machine code (0NNN), only the COSMAC VIP runs it".to_string());
+        confidence = if platform == Platform::Chip8 { 0.9 } else { confidence - 0.2 };
+    }
+
+    let mut quirks = platform.quirks();
+    // Quirks that can't change the outcome are turned off rather than guessed
+    let shifts = a.instructions.values().filter(|&&i| i & 0xF00F == 0x8006 || i & 0xF00F == 0x800E);
+    if shifts.clone().count() > 0 && shifts.clone().all(|&i| (i >> 8) & 0xF == (i >> 4) & 0xF) {
+        reasons.push("shifts only use VX == VY, shift quirk doesn't matter".to_string());
+        quirks.shift_vy = false;
+    }
+    let jumps: Vec<u16> = a.dynamic_jumps.iter().map(|addr| a.instructions[addr]).collect();
+    if !jumps.is_empty() && platform != Platform::SuperChip && jumps.iter().all(|&i| i & 0x0F00 != 0) {
+        // BXNN with X != 0 reads like SUPER-CHIP code jumping through VX
+        reasons.push("computed jumps all name a register other than V0, assuming BXNN".to_string());
+        quirks.jump_vx = true;
+        confidence -= 0.1;
+    }
+
+    Detection {
+        platform,
+        quirks,
+        confidence: confidence.clamp(0.0, 1.0),
+        reasons,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(instrs: &[u16]) -> Vec<u8> {
+        instrs.iter().flat_map(|i| i.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn bundled_roms_are_chip8() {
+        for rom in [&include_bytes!("../../roms/ibmlogo.ch8")[..], include_bytes!("../../roms/breakout.ch8")] {
+            let d = detect(rom, 0x200);
+            assert_eq!(d.platform, Platform::Chip8);
+            assert!(d.confidence >= 0.6);
+        }
+    }
+
+    #[test]
+    fn extensions() {
+        // LOW then HIGH: SUPER-CHIP resolution switch
+        let d = detect(&program(&[0x00FE, 0x00FF, 0x1204]), 0x200);
+        assert_eq!(d.platform, Platform::SuperChip);
+        assert_eq!(d.quirks, Platform::SuperChip.quirks());
+        assert!((d.confidence - 0.9).abs() < 1e-9); // One point per opcode
+
+        // XO-CHIP wins over SUPER-CHIP opcodes
+        let d = detect(&program(&[0x00FF, 0xF001, 0x1204]), 0x200);
+        assert_eq!(d.platform, Platform::XoChip);
+        assert!(d.reasons.iter().any(|r| r.starts_with("0x0202") && r.ends_with("needs xochip")));
+    }
+
+    #[test]
+    fn unreachable_data_is_ignored() {
+        // 0x00FF only appears after the infinite loop, as data
+        let d = detect(&program(&[0x1200, 0x00FF]), 0x200);
+        assert_eq!(d.platform, Platform::Chip8);
+    }
+
+    #[test]
+    fn large_roms_are_xochip() {
+        let mut rom = program(&[0x1200]);
+        rom.resize(MEMORY_SIZE, 0);
+        assert_eq!(detect(&rom, 0x200).platform, Platform::XoChip);
+    }
+
+    #[test]
+    fn machine_code_calls_mean_the_vip() {
+        let d = detect(&program(&[0x0300, 0x1202]), 0x200);
+        assert_eq!(d.platform, Platform::Chip8);
+        assert!((d.confidence - 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn quirks() {
+        // SHR V1, V1 and BXNN through V2
+        let d = detect(&program(&[0x8116, 0xB204]), 0x200);
+        assert!(!d.quirks.shift_vy);
+        assert!(d.quirks.jump_vx);
+        assert!(d.confidence < 0.6);
+
+        // SHR V1, V2 keeps the platform's shift quirk
+        let d = detect(&program(&[0x8126, 0x1202]), 0x200);
+        assert!(d.quirks.shift_vy);
+        assert!(!d.quirks.jump_vx);
+    }
+}
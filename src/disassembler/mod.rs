@@ -52,8 +52,13 @@ pub fn disassemble(instr: u16) -> String {
 
 /// Disassemble a vector of instructions
 pub fn disassemble_all(instr_vec: &Vec<u16>) -> String {
+    disassemble_at(instr_vec, 0)
+}
+
+/// Disassemble a vector of instructions starting at an address
+pub fn disassemble_at(instr_vec: &Vec<u16>, start: usize) -> String {
     let mut output = Vec::<String>::new();
-    let mut i = 0;
+    let mut i = start;
     for b in instr_vec {
         output.push(format!("0x{:04X}\t0x{:04X} -> {} ", i, b, disassemble(*b)));
         i += 2;
@@ -78,3 +83,75 @@ pub fn pair_bytes(vec: &Vec<u8>) -> Vec<u16> {
     }
     new_bytes
 }
+
+/// Broad family an instruction belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum OpcodeClass {
+    System, // 0NNN, CLS, RTS and SUPER-CHIP screen control
+    Flow, // Jumps and subroutine calls
+    Skip, // Conditional skips on registers
+    Load, // Register and I loads
+    Arith, // Additions, subtractions and shifts
+    Logic, // OR, AND, XOR
+    Random,
+    Draw,
+    Key, // Key skips and key wait
+    Timer,
+    Memory, // I arithmetic, font, BCD, register dumps and loads
+    Unknown
+}
+
+impl OpcodeClass {
+    /// All classes, in display order
+    pub const ALL: [OpcodeClass; 12] = [
+        OpcodeClass::System, OpcodeClass::Flow, OpcodeClass::Skip, OpcodeClass::Load,
+        OpcodeClass::Arith, OpcodeClass::Logic, OpcodeClass::Random, OpcodeClass::Draw,
+        OpcodeClass::Key, OpcodeClass::Timer, OpcodeClass::Memory, OpcodeClass::Unknown
+    ];
+
+    /// Returns the lowercase name of the class
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpcodeClass::System => "system",
+            OpcodeClass::Flow => "flow",
+            OpcodeClass::Skip => "skip",
+            OpcodeClass::Load => "load",
+            OpcodeClass::Arith => "arith",
+            OpcodeClass::Logic => "logic",
+            OpcodeClass::Random => "random",
+            OpcodeClass::Draw => "draw",
+            OpcodeClass::Key => "key",
+            OpcodeClass::Timer => "timer",
+            OpcodeClass::Memory => "memory",
+            OpcodeClass::Unknown => "unknown"
+        }
+    }
+
+    /// Returns the class with a given name
+    pub fn from_name(name: &str) -> Option<OpcodeClass> {
+        Self::ALL.into_iter().find(|c| c.name() == name)
+    }
+}
+
+/// Classify an instruction (follows the same decoding as `disassemble`)
+pub fn opcode_class(instr: u16) -> OpcodeClass {
+    let i = instr;
+    let nibbles = ((i >> 12) & 0xF, (i >> 8) & 0xF, (i >> 4) & 0xF, i & 0xF);
+
+    match nibbles {
+        (0x0, _, _, _) => OpcodeClass::System,
+        (0x1, _, _, _) | (0x2, _, _, _) | (0xB, _, _, _) => OpcodeClass::Flow,
+        (0x3, _, _, _) | (0x4, _, _, _) | (0x5, _, _, 0x0) | (0x9, _, _, 0x0) => OpcodeClass::Skip,
+        (0x6, _, _, _) | (0x8, _, _, 0x0) | (0xA, _, _, _) => OpcodeClass::Load,
+        (0x7, _, _, _) | (0x8, _, _, 0x4) | (0x8, _, _, 0x5) | (0x8, _, _, 0x6) |
+        (0x8, _, _, 0x7) | (0x8, _, _, 0xE) => OpcodeClass::Arith,
+        (0x8, _, _, 0x1) | (0x8, _, _, 0x2) | (0x8, _, _, 0x3) => OpcodeClass::Logic,
+        (0xC, _, _, _) => OpcodeClass::Random,
+        (0xD, _, _, _) => OpcodeClass::Draw,
+        (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) | (0xF, _, 0x0, 0xA) => OpcodeClass::Key,
+        (0xF, _, 0x0, 0x7) | (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) => OpcodeClass::Timer,
+        (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0xF, _, 0x3, 0x0) | (0xF, _, 0x3, 0x3) |
+        (0xF, _, 0x5, 0x5) | (0xF, _, 0x6, 0x5) => OpcodeClass::Memory,
+        _ => OpcodeClass::Unknown
+    }
+}
@@ -0,0 +1,302 @@
+use std::ops::Range;
+
+use super::{ExecError, MEMORY_SIZE};
+
+/// Answers the memory accesses of the emulator. Addresses are always below MEMORY_SIZE,
+/// the address policy has been applied before reaching the bus
+pub trait Bus {
+    /// Reads a byte without side effects, for tools and debuggers
+    fn peek(&self, addr: u16) -> u8;
+
+    /// Reads a byte on behalf of the program
+    fn read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    /// Writes a byte on behalf of the program, fails if nothing writable is there
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), ExecError>;
+
+    /// Returns true if a write at the address would be accepted, so that instructions
+    /// writing several bytes can fail before changing any of them
+    fn writable(&self, _addr: u16) -> bool {
+        true
+    }
+
+    /// Stores a byte regardless of protections, to load programs and fonts
+    fn load(&mut self, addr: u16, val: u8);
+}
+
+/// Plain 4 KiB of RAM, the default bus
+#[derive(Debug, Clone)]
+pub struct Ram([u8; MEMORY_SIZE]);
+
+impl Default for Ram {
+    fn default() -> Self {
+        Self([0u8; MEMORY_SIZE])
+    }
+}
+
+impl Bus for Ram {
+    fn peek(&self, addr: u16) -> u8 {
+        self.0[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), ExecError> {
+        self.0[addr as usize] = val;
+        Ok(())
+    }
+
+    fn load(&mut self, addr: u16, val: u8) {
+        self.0[addr as usize] = val;
+    }
+}
+
+/// A device answering accesses to a mapped region, offsets are relative to the region start
+pub trait Peripheral {
+    /// Reads a register without side effects
+    fn peek(&self, offset: u16) -> u8;
+
+    /// Reads a register on behalf of the program
+    fn read(&mut self, offset: u16) -> u8 {
+        self.peek(offset)
+    }
+
+    /// Writes a register on behalf of the program
+    fn write(&mut self, offset: u16, val: u8);
+}
+
+// What a mapped region does
+enum Region {
+    ReadOnly,
+    Mirror(u16, u16), // Start and size of the mirrored area
+    Device(Box<dyn Peripheral + Send>),
+}
+
+/// A bus made of regions mapped over another one (RAM by default), such as
+/// read-only interpreter / font areas, mirrors of smaller memories or peripherals.
+/// When regions overlap the last mapped one wins
+pub struct MappedBus<B: Bus = Ram> {
+    inner: B,
+    regions: Vec<(Range<u16>, Region)>,
+}
+
+impl MappedBus {
+    /// Returns a bus with nothing mapped over RAM
+    pub fn new() -> Self {
+        Self::over(Ram::default())
+    }
+}
+
+impl Default for MappedBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Bus> MappedBus<B> {
+    /// Returns a bus with nothing mapped over another one
+    pub fn over(inner: B) -> Self {
+        Self { inner, regions: Vec::new() }
+    }
+
+    /// Makes a region read-only for the program, loading still works
+    pub fn read_only(mut self, range: Range<u16>) -> Self {
+        self.regions.push((range, Region::ReadOnly));
+        self
+    }
+
+    /// Makes a region repeat the `size` bytes from `start`, like a smaller memory
+    /// showing up several times in the address space. Backing addresses past the end of
+    /// memory wrap around to 0x000
+    pub fn mirror(mut self, range: Range<u16>, start: u16, size: u16) -> Self {
+        self.regions.push((range, Region::Mirror(start, size.max(1))));
+        self
+    }
+
+    /// Hands a region to a peripheral
+    pub fn device(mut self, range: Range<u16>, device: Box<dyn Peripheral + Send>) -> Self {
+        self.regions.push((range, Region::Device(device)));
+        self
+    }
+
+    /// Returns the bus under the regions
+    pub fn inner(&self) -> &B {
+        &self.inner
+    }
+
+    // Returns the index of the region answering an address
+    fn region(&self, addr: u16) -> Option<usize> {
+        self.regions.iter().rposition(|(range, _)| range.contains(&addr))
+    }
+
+    // Follows mirrors to the address backing another
+    fn target(&self, addr: u16, index: usize) -> u16 {
+        match &self.regions[index] {
+            (range, Region::Mirror(start, size)) => ((*start as usize + ((addr - range.start) % size) as usize) % MEMORY_SIZE) as u16,
+            _ => addr
+        }
+    }
+}
+
+impl<B: Bus> Bus for MappedBus<B> {
+    fn peek(&self, addr: u16) -> u8 {
+        match self.region(addr) {
+            Some(i) => match &self.regions[i] {
+                (range, Region::Device(d)) => d.peek(addr - range.start),
+                _ => self.inner.peek(self.target(addr, i)),
+            },
+            None => self.inner.peek(addr)
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        match self.region(addr) {
+            Some(i) => {
+                let target = self.target(addr, i);
+                match &mut self.regions[i] {
+                    (range, Region::Device(d)) => d.read(addr - range.start),
+                    _ => self.inner.read(target),
+                }
+            },
+            None => self.inner.read(addr)
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) -> Result<(), ExecError> {
+        match self.region(addr) {
+            Some(i) => {
+                let target = self.target(addr, i);
+                match &mut self.regions[i] {
+                    (_, Region::ReadOnly) => Err(ExecError::WriteFault(addr)),
+                    (range, Region::Device(d)) => {
+                        d.write(addr - range.start, val);
+                        Ok(())
+                    },
+                    (_, Region::Mirror(..)) => self.inner.write(target, val),
+                }
+            },
+            None => self.inner.write(addr, val)
+        }
+    }
+
+    fn writable(&self, addr: u16) -> bool {
+        match self.region(addr) {
+            Some(i) => match &self.regions[i] {
+                (_, Region::ReadOnly) => false,
+                (_, Region::Mirror(..)) => self.inner.writable(self.target(addr, i)),
+                (_, Region::Device(_)) => true,
+            },
+            None => self.inner.writable(addr)
+        }
+    }
+
+    fn load(&mut self, addr: u16, val: u8) {
+        match self.region(addr) {
+            Some(i) => {
+                let target = self.target(addr, i);
+                match &mut self.regions[i] {
+                    (range, Region::Device(d)) => d.write(addr - range.start, val),
+                    _ => self.inner.load(target, val),
+                }
+            },
+            None => self.inner.load(addr, val)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // Four registers, counting the reads the program makes
+    struct Registers {
+        values: [u8; 4],
+        reads: Arc<Mutex<usize>>,
+    }
+
+    impl Peripheral for Registers {
+        fn peek(&self, offset: u16) -> u8 {
+            self.values[offset as usize]
+        }
+
+        fn read(&mut self, offset: u16) -> u8 {
+            *self.reads.lock().unwrap() += 1;
+            self.peek(offset)
+        }
+
+        fn write(&mut self, offset: u16, val: u8) {
+            self.values[offset as usize] = val;
+        }
+    }
+
+    #[test]
+    fn read_only() {
+        let mut bus = MappedBus::new().read_only(0x000..0x200);
+        bus.load(0x050, 0xF0);
+        assert_eq!(bus.write(0x050, 0x00), Err(ExecError::WriteFault(0x050)));
+        assert_eq!((bus.peek(0x050), bus.writable(0x050)), (0xF0, false));
+        assert_eq!(bus.write(0x200, 0x12), Ok(()));
+        assert!(bus.writable(0x200));
+    }
+
+    #[test]
+    fn mirror() {
+        // 0x800 to 0xFFF repeats the 256 bytes from 0x100
+        let mut bus = MappedBus::new().mirror(0x800..0x1000, 0x100, 0x100);
+        bus.load(0x105, 0xAA);
+        assert_eq!([bus.peek(0x805), bus.read(0x905), bus.peek(0xF05)], [0xAA; 3]);
+        bus.write(0xA06, 0xBB).unwrap();
+        assert_eq!(bus.inner().peek(0x106), 0xBB);
+        assert_eq!(bus.peek(0x806), 0xBB);
+    }
+
+    #[test]
+    fn mirror_past_the_end_of_memory() {
+        // Backed by 0xFF0 to 0x100F, the last 16 bytes wrap around
+        let mut bus = MappedBus::new().mirror(0x000..0x100, 0xFF0, 0x20);
+        bus.write(0x010, 0x12).unwrap();
+        assert_eq!(bus.inner().peek(0x000), 0x12);
+        assert_eq!(bus.peek(0x01F), 0);
+        let mut bus = MappedBus::new().mirror(0x000..0x100, 0xFFFF, 0x10);
+        bus.write(0x001, 0x34).unwrap();
+        assert_eq!(bus.inner().peek(0xFFF), 0); // 0xFFFF + 1 wraps to 0x000
+        assert_eq!(bus.inner().peek(0x000), 0x34);
+    }
+
+    #[test]
+    fn device() {
+        let reads = Arc::new(Mutex::new(0));
+        let mut bus = MappedBus::new().device(0xF00..0xF04, Box::new(Registers { values: [1, 2, 3, 4], reads: reads.clone() }));
+        assert_eq!(bus.peek(0xF02), 3);
+        assert_eq!(*reads.lock().unwrap(), 0); // Peeking has no side effects
+        assert_eq!(bus.read(0xF03), 4);
+        assert_eq!(*reads.lock().unwrap(), 1);
+        bus.write(0xF00, 9).unwrap();
+        assert_eq!(bus.peek(0xF00), 9);
+        assert_eq!(bus.inner().peek(0xF00), 0); // Never reaches RAM
+        assert!(bus.writable(0xF01));
+        bus.write(0xF04, 5).unwrap(); // Past the device
+        assert_eq!(bus.inner().peek(0xF04), 5);
+    }
+
+    #[test]
+    fn overlapping_regions() {
+        // The last mapped region wins: a mirror of the font, partly covered by a read-only
+        // region, with a device punched in the middle
+        let reads = Arc::new(Mutex::new(0));
+        let mut bus = MappedBus::new()
+            .mirror(0x100..0x200, 0x050, 0x50)
+            .read_only(0x000..0x180)
+            .device(0x140..0x144, Box::new(Registers { values: [0; 4], reads }));
+        bus.load(0x050, 0xF0);
+        bus.load(0x100, 0x11);
+        assert_eq!(bus.peek(0x100), 0x11); // Read-only, over the RAM at the address itself
+        assert_eq!(bus.write(0x100, 0), Err(ExecError::WriteFault(0x100)));
+        assert_eq!(bus.peek(0x1A0), 0xF0); // Mirror, writable past the read-only region
+        bus.write(0x1A1, 0x90).unwrap();
+        assert_eq!(bus.inner().peek(0x051), 0x90);
+        bus.write(0x141, 0x12).unwrap(); // Device
+        assert_eq!((bus.peek(0x141), bus.inner().peek(0x141)), (0x12, 0));
+    }
+}
@@ -0,0 +1,25 @@
+use std::fmt;
+
+/// Why an instruction couldn't be executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecError {
+    UnknownInstruction,
+    MemoryFault(usize), // Address past the end of memory
+    WriteFault(u16), // Write refused by the bus
+    InvalidKey(u8),
+    StackOverflow,
+    StackUnderflow,
+}
+
+impl fmt::Display for ExecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecError::UnknownInstruction => write!(f, "unknown instruction"),
+            ExecError::MemoryFault(addr) => write!(f, "address 0x{:04X} is past the end of memory", addr),
+            ExecError::WriteFault(addr) => write!(f, "can't write at 0x{:03X}", addr),
+            ExecError::InvalidKey(key) => write!(f, "invalid key 0x{:02X}", key),
+            ExecError::StackOverflow => write!(f, "stack overflow"),
+            ExecError::StackUnderflow => write!(f, "return with an empty stack"),
+        }
+    }
+}
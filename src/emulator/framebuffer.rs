@@ -0,0 +1,214 @@
+/// Display width in pixels
+pub const WIDTH: usize = 64;
+/// Display height in pixels
+pub const HEIGHT: usize = 32;
+
+/// Monochrome 64x32 display, one u64 per row with the leftmost pixel in the high bit.
+/// Rows changed since the last `clear_dirty` are flagged, so frontends only redraw when needed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framebuffer {
+    rows: [u64; HEIGHT],
+    dirty: u32, // One bit per row
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Framebuffer {
+    /// Returns a blank display, with every row dirty so that it replaces whatever was shown before
+    pub fn new() -> Self {
+        Self { rows: [0; HEIGHT], dirty: u32::MAX }
+    }
+
+    // Bit of a pixel within its row
+    fn mask(x: usize) -> u64 {
+        1 << (WIDTH - 1 - x)
+    }
+
+    /// Returns the value of a pixel
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & Self::mask(x) != 0
+    }
+
+    /// Sets the value of a pixel
+    pub fn set(&mut self, x: usize, y: usize, val: bool) {
+        let row = if val { self.rows[y] | Self::mask(x) } else { self.rows[y] & !Self::mask(x) };
+        self.set_row(y, row);
+    }
+
+    /// Returns a row, the leftmost pixel in the high bit
+    pub fn row(&self, y: usize) -> u64 {
+        self.rows[y]
+    }
+
+    /// Returns all the rows
+    pub fn rows(&self) -> &[u64; HEIGHT] {
+        &self.rows
+    }
+
+    /// Replaces a row
+    pub fn set_row(&mut self, y: usize, row: u64) {
+        if self.rows[y] != row {
+            self.rows[y] = row;
+            self.dirty |= 1 << y;
+        }
+    }
+
+    /// Turns every pixel off
+    pub fn clear(&mut self) {
+        for y in 0..HEIGHT {
+            self.set_row(y, 0);
+        }
+    }
+
+    /// XORs a byte of sprite onto a row from column x, clipping it at the right edge or wrapping it around.
+    /// Returns true if a pixel was turned off
+    pub fn blit(&mut self, x: usize, y: usize, sprite: u8, wrap: bool) -> bool {
+        let bits = (sprite as u64) << (WIDTH - 8);
+        let bits = if wrap { bits.rotate_right(x as u32) } else { bits >> x };
+        let collision = self.rows[y] & bits != 0;
+        self.set_row(y, self.rows[y] ^ bits);
+        collision
+    }
+
+    /// Returns true if a row changed since the last `clear_dirty`
+    pub fn is_dirty(&self) -> bool {
+        self.dirty != 0
+    }
+
+    /// Returns the rows changed since the last `clear_dirty`, one bit per row
+    pub fn dirty_rows(&self) -> u32 {
+        self.dirty
+    }
+
+    /// Marks every row as drawn
+    pub fn clear_dirty(&mut self) {
+        self.dirty = 0;
+    }
+
+    /// Iterates over the coordinates of the pixels turned on, row by row
+    pub fn pixels(&self) -> Pixels<'_> {
+        Pixels { rows: &self.rows, y: 0, bits: self.rows[0] }
+    }
+
+    /// Returns the display as booleans
+    pub fn to_array(&self) -> [[bool; WIDTH]; HEIGHT] {
+        let mut display = [[false; WIDTH]; HEIGHT];
+        for (y, row) in display.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = self.get(x, y);
+            }
+        }
+        display
+    }
+}
+
+/// Coordinates of the pixels turned on in a framebuffer, skipping blank rows and spans a word at a time
+pub struct Pixels<'a> {
+    rows: &'a [u64; HEIGHT],
+    y: usize,
+    bits: u64, // Pixels of the current row left to return
+}
+
+impl Iterator for Pixels<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<(usize, usize)> {
+        while self.bits == 0 {
+            self.y += 1;
+            if self.y >= HEIGHT { return None; }
+            self.bits = self.rows[self.y];
+        }
+        let x = self.bits.leading_zeros() as usize;
+        self.bits &= !Framebuffer::mask(x);
+        Some((x, self.y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_display_is_drawn() {
+        let mut fb = Framebuffer::new();
+        assert_eq!(fb.dirty_rows(), u32::MAX);
+        fb.clear_dirty();
+        assert!(!fb.is_dirty());
+    }
+
+    #[test]
+    fn blit_xors_and_reports_collisions() {
+        let mut fb = Framebuffer::new();
+        assert!(!fb.blit(4, 3, 0b1100_0000, false));
+        assert_eq!(fb.row(3), 0b11 << 58);
+        // Turning a pixel off is a collision, turning one on isn't
+        assert!(fb.blit(5, 3, 0b1100_0000, false));
+        assert_eq!(fb.row(3), 0b101 << 57);
+        assert!(!fb.blit(0, 3, 0b1000_0000, false));
+        assert!(fb.get(0, 3) && fb.get(4, 3) && !fb.get(5, 3) && fb.get(6, 3));
+    }
+
+    #[test]
+    fn blit_clips_or_wraps_at_the_right_edge() {
+        let mut fb = Framebuffer::new();
+        fb.blit(60, 0, 0xFF, false);
+        assert_eq!(fb.row(0), 0xF);
+        fb.blit(60, 1, 0xFF, true);
+        assert_eq!(fb.row(1), 0xF000_0000_0000_000F);
+        // Nothing collides with the clipped pixels
+        assert!(!fb.blit(0, 0, 0xF0, false));
+    }
+
+    #[test]
+    fn sprites_clip_or_wrap_at_the_bottom_edge() {
+        use crate::emulator::{Chip8, Quirks};
+
+        // Draws 3 rows of 0xFF from (60, 30)
+        let program = vec![0x60, 60, 0x61, 30, 0xA2, 0x0A, 0xD0, 0x13, 0x00, 0x00, 0xFF, 0xFF, 0xFF];
+        for wrap in [false, true] {
+            let mut emu = Chip8::new().set_quirks(Quirks { wrap_sprites: wrap, ..Quirks::default() })
+                .load_program(program.clone()).unwrap();
+            for _ in 0..4 {
+                let instr = emu.fetch().unwrap();
+                emu.exec(instr).unwrap();
+            }
+            let fb = emu.framebuffer();
+            let (row, top, lit) = if wrap { (0xF000_0000_0000_000F, 0xF000_0000_0000_000F, 24) } else { (0xF, 0, 8) };
+            assert_eq!((fb.row(30), fb.row(31), fb.row(0)), (row, row, top));
+            assert_eq!(fb.pixels().count(), lit);
+        }
+    }
+
+    #[test]
+    fn pixels_in_reading_order() {
+        let mut fb = Framebuffer::new();
+        assert_eq!(fb.pixels().next(), None);
+        fb.set(63, 0, true);
+        fb.set(0, 0, true);
+        fb.set(10, 5, true);
+        fb.set(63, 31, true);
+        assert_eq!(fb.pixels().collect::<Vec<_>>(), [(0, 0), (63, 0), (10, 5), (63, 31)]);
+        let count = fb.to_array().iter().flatten().filter(|p| **p).count();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn changed_rows_are_dirty() {
+        let mut fb = Framebuffer::new();
+        fb.clear_dirty();
+        fb.blit(0, 2, 0xFF, false);
+        fb.set(0, 7, false); // Already off
+        fb.set_row(9, 0);
+        assert_eq!(fb.dirty_rows(), 1 << 2);
+        fb.blit(0, 2, 0xFF, false);
+        fb.blit(0, 31, 0x01, false);
+        assert_eq!(fb.dirty_rows(), 1 << 2 | 1 << 31);
+        fb.clear_dirty();
+        fb.clear(); // Only row 31 is still lit
+        assert_eq!(fb.dirty_rows(), 1 << 31);
+    }
+}
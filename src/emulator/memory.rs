@@ -0,0 +1,143 @@
+/// Number of addressable bytes (4 KiB, 0x000 to 0xFFF)
+pub const MEMORY_SIZE: usize = 0x1000;
+
+/// What happens when the program counter or I + offset goes past the end of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AddressPolicy {
+    /// Addresses wrap around to 0x000, as the 12 bit address bus of the COSMAC VIP does
+    Wrap,
+    /// The instruction fails
+    #[default]
+    Fault,
+}
+
+impl AddressPolicy {
+    pub const NAMES: [&'static str; 2] = ["wrap", "fault"];
+
+    /// Returns the policy with a given name
+    pub fn from_name(name: &str) -> Option<AddressPolicy> {
+        match name {
+            "wrap" => Some(AddressPolicy::Wrap),
+            "fault" => Some(AddressPolicy::Fault),
+            _ => None
+        }
+    }
+
+    /// Returns the memory index an address refers to, None if it faults
+    pub fn resolve(self, addr: usize) -> Option<usize> {
+        match self {
+            AddressPolicy::Wrap => Some(addr % MEMORY_SIZE),
+            AddressPolicy::Fault => if addr < MEMORY_SIZE { Some(addr) } else { None },
+        }
+    }
+}
+
+/// Where the interpreter keeps the call stack and the display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Outside of memory, out of reach of programs
+    #[default]
+    Separate,
+    /// In RAM as on the COSMAC VIP: the stack grows down from 0xECF and the display
+    /// is a 1 bit per pixel framebuffer at 0xF00, so programs can reach (and trash) both
+    Vip,
+}
+
+impl Layout {
+    /// First byte above the stack, which grows down
+    pub const VIP_STACK_TOP: u16 = 0xED0;
+    /// Start of the 256 bytes framebuffer, 8 bytes per row with the leftmost pixel in the high bit
+    pub const VIP_FRAMEBUFFER: u16 = 0xF00;
+
+    pub const NAMES: [&'static str; 2] = ["separate", "vip"];
+
+    /// Returns the layout with a given name
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "separate" => Some(Layout::Separate),
+            "vip" => Some(Layout::Vip),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Chip8, VReg};
+
+    // Returns an emulator in the VIP layout, about to run a program
+    fn vip(program: &[u16]) -> Chip8 {
+        Chip8::new().set_layout(Layout::Vip)
+            .load_program(program.iter().flat_map(|i| i.to_be_bytes()).collect()).unwrap()
+    }
+
+    // Runs some instructions
+    fn run(emu: &mut Chip8, steps: usize) {
+        for _ in 0..steps {
+            let instr = emu.fetch().unwrap();
+            emu.exec(instr).unwrap();
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        assert_eq!(AddressPolicy::Wrap.resolve(0x1001), Some(0x001));
+        assert_eq!(AddressPolicy::Fault.resolve(0xFFF), Some(0xFFF));
+        assert_eq!(AddressPolicy::Fault.resolve(0x1000), None);
+    }
+
+    #[test]
+    fn stores_into_the_framebuffer_are_displayed() {
+        // LD I, 0xF08 then STR V0-V1: the first two bytes of the second row
+        let mut emu = vip(&[0x6081, 0x61FF, 0xAF08, 0xF155]);
+        run(&mut emu, 4);
+        assert_eq!(emu.framebuffer().row(1), 0x81FF_0000_0000_0000);
+        assert_eq!(emu.framebuffer().row(0), 0);
+    }
+
+    #[test]
+    fn sprites_are_drawn_into_ram() {
+        // Two rows of sprite at (8, 1)
+        let mut emu = vip(&[0x6008, 0x6101, 0xA208, 0xD012, 0xF090]);
+        run(&mut emu, 4);
+        let memory = emu.get_memory();
+        assert_eq!((memory[0xF09], memory[0xF11]), (0xF0, 0x90));
+        assert_eq!(emu.framebuffer().row(1), 0x00F0_0000_0000_0000);
+        // Clearing the screen clears the framebuffer in RAM
+        emu.exec(0x00E0).unwrap();
+        assert!(emu.get_memory()[0xF00..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn calls_push_down_from_0xecf() {
+        // CALL 0x204, then at 0x204: CALL 0x208
+        let mut emu = vip(&[0x2204, 0x0000, 0x2208]);
+        run(&mut emu, 2);
+        let memory = emu.get_memory();
+        assert_eq!(memory[0xECE..0xED0], [0x02, 0x02]); // 0x202, high nibble first
+        assert_eq!(memory[0xECC..0xECE], [0x02, 0x06]);
+        assert_eq!(emu.get_stack(), [0x202, 0x206]);
+        emu.exec(0x00EE).unwrap();
+        assert_eq!((emu.get_pc(), emu.get_stack()), (0x206, vec![0x202]));
+    }
+
+    #[test]
+    fn programs_can_rewrite_return_addresses() {
+        // Overwrite the return address on the stack with 0x2AA (LD I, 0xECE; STR V0-V1), then return
+        let mut emu = vip(&[0x2204, 0x0000, 0x6002, 0x61AA, 0xAECE, 0xF155, 0x00EE]);
+        run(&mut emu, 6);
+        assert_eq!(emu.get_pc(), 0x2AA);
+        assert_eq!(emu.get_reg(VReg::from_nibble(1)), 0xAA);
+    }
+
+    #[test]
+    fn switching_layout_keeps_the_display() {
+        // Draws 0xF0 at (0, 0)
+        let mut emu = Chip8::new().load_program(vec![0x60, 0x00, 0xA2, 0x06, 0xD0, 0x01, 0xF0]).unwrap();
+        run(&mut emu, 3);
+        let emu = emu.set_layout(Layout::Vip);
+        assert_eq!(emu.get_memory()[0xF00], 0xF0);
+        assert_eq!(emu.framebuffer().row(0), 0xF000_0000_0000_0000);
+    }
+}
@@ -1,49 +1,182 @@
 use std::cmp::{min, max};
-use rand::Rng;
-
-pub struct Chip8 {
-    memory: [u8; 0xFFF],
+use crate::loader;
+
+pub mod bus;
+pub mod error;
+pub mod framebuffer;
+pub mod memory;
+pub mod observer;
+pub mod quirks;
+pub mod random;
+pub mod registers;
+pub mod stack;
+pub mod timing;
+
+pub use bus::{Bus, MappedBus, Peripheral, Ram};
+pub use error::ExecError;
+pub use framebuffer::Framebuffer;
+pub use memory::{AddressPolicy, Layout, MEMORY_SIZE};
+pub use observer::{NoObserver, Observer};
+pub use quirks::Quirks;
+pub use random::{RandomSource, SeededRandom, VipRandom};
+pub use registers::{Registers, VReg};
+pub use stack::{StackDepth, StackPolicy};
+pub use timing::{Clock, Timing};
+
+
+pub struct Chip8<O: Observer = NoObserver, B: Bus = Ram> {
+    memory: B,
     freq: u32, // Number of instructions ran per second
+    timing: Timing, // How long instructions take
+    frame_clock: Clock, // Progress through the current frame, carried between frames
     pc: u16,
     i: u16,
-    stack: Vec<u16>,
-    vars: [u8; 0xF0],
-    display: [[bool; 64]; 32],
+    stack: Vec<u16>, // Return addresses in the separate layout
+    stack_top: usize, // Next free slot of the stack
+    sp: u16, // Stack pointer in the VIP layout
+    stack_depth: StackDepth,
+    stack_policy: StackPolicy, // Past either end of the stack
+    vars: Registers, // V0 to VF
+    display: Framebuffer, // Mirrored in RAM in the VIP layout
     delay_timer: u8,
     sound_timer: u8,
     key_states: [bool; 16],
+    quirks: Quirks,
+    address_policy: AddressPolicy, // Past the end of memory
+    layout: Layout, // Where the stack and display live
 
-    rng: rand::rngs::ThreadRng // Generates rng numbers
+    rng: Box<dyn RandomSource>, // Generates random numbers
+    observer: O, // Notified of what the instructions do
 }
 
 impl Chip8 {
     /// Returns a new instance
     pub fn new() -> Self {
         Self {
-            memory: [0u8; 0xFFF],
+            memory: Ram::default(),
             freq: 700,
+            timing: Timing::default(),
+            frame_clock: Clock::new(Timing::default(), 700),
             pc: 0x200,
             i: 0x0050,
             stack: Vec::new(),
-            vars: [0u8; 0xF0],
-            display: [[false; 64]; 32],
+            stack_top: 0,
+            sp: Layout::VIP_STACK_TOP,
+            stack_depth: StackDepth::default(),
+            stack_policy: StackPolicy::default(),
+            vars: Registers::default(),
+            display: Framebuffer::new(),
             delay_timer: 60,
             sound_timer: 60,
             key_states: [false; 16],
+            quirks: Quirks::default(),
+            address_policy: AddressPolicy::default(),
+            layout: Layout::default(),
 
-            rng: rand::thread_rng()
+            rng: Box::new(SeededRandom::from_entropy()),
+            observer: NoObserver,
         }
     }
 
-    /// Loads the program (as a byte vector) into the emulator memory
-    pub fn load_program(mut self, prog: Vec<u8>) -> Self {
-        // Limit bytes
-        let prog: Vec<u8> = prog.into_iter().take(0xFFF - 0x200).collect();
-        // Write to memory
+    /// Decodes a u16 instruction into hex
+    pub fn decode_to_nibbles(instr: u16) -> (u8, u8, u8, u8) {
+        let i = instr;
+        (((i >> 12) & 0xF) as u8, ((i >> 8) & 0xF) as u8, ((i >> 4) & 0xF) as u8, (i & 0xF) as u8)
+    }
+}
+
+impl<O: Observer, B: Bus> Chip8<O, B> {
+    /// Attaches an observer to the emulator, replacing the current one
+    pub fn with_observer<P: Observer>(self, observer: P) -> Chip8<P, B> {
+        Chip8 {
+            memory: self.memory,
+            freq: self.freq,
+            timing: self.timing,
+            frame_clock: self.frame_clock,
+            pc: self.pc,
+            i: self.i,
+            stack: self.stack,
+            stack_top: self.stack_top,
+            sp: self.sp,
+            stack_depth: self.stack_depth,
+            stack_policy: self.stack_policy,
+            vars: self.vars,
+            display: self.display,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            key_states: self.key_states,
+            quirks: self.quirks,
+            address_policy: self.address_policy,
+            layout: self.layout,
+            rng: self.rng,
+            observer,
+        }
+    }
+
+    /// Replaces the memory bus, the current memory contents are loaded into the new one
+    pub fn with_bus<C: Bus>(self, mut bus: C) -> Chip8<O, C> {
+        for addr in 0..MEMORY_SIZE as u16 {
+            bus.load(addr, self.memory.peek(addr));
+        }
+        Chip8 {
+            memory: bus,
+            freq: self.freq,
+            timing: self.timing,
+            frame_clock: self.frame_clock,
+            pc: self.pc,
+            i: self.i,
+            stack: self.stack,
+            stack_top: self.stack_top,
+            sp: self.sp,
+            stack_depth: self.stack_depth,
+            stack_policy: self.stack_policy,
+            vars: self.vars,
+            display: self.display,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            key_states: self.key_states,
+            quirks: self.quirks,
+            address_policy: self.address_policy,
+            layout: self.layout,
+            rng: self.rng,
+            observer: self.observer,
+        }
+    }
+
+    /// Returns the memory bus
+    pub fn bus(&self) -> &B {
+        &self.memory
+    }
+
+    /// Returns the memory bus mutably
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.memory
+    }
+
+    /// Returns the observer
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// Returns the observer mutably
+    pub fn observer_mut(&mut self) -> &mut O {
+        &mut self.observer
+    }
+
+    /// Loads the program (as a byte vector) into the emulator memory at 0x200
+    pub fn load_program(self, prog: Vec<u8>) -> Result<Self, String> {
+        self.load_program_at(prog, loader::DEFAULT_ADDRESS)
+    }
+
+    /// Loads the program at an address and starts executing it from there,
+    /// fails if it is empty or doesn't fit in memory
+    pub fn load_program_at(mut self, prog: Vec<u8>, address: usize) -> Result<Self, String> {
+        loader::check_fits(&prog, address)?;
         for (i, b) in prog.iter().enumerate() {
-            self.memory[0x200 + i] = *b;
+            self.memory.load((address + i) as u16, *b);
         }
-        self
+        self.pc = address as u16;
+        Ok(self)
     }
 
     /// Sets the font for the emulator within 0x050-0x200
@@ -52,7 +185,7 @@ impl Chip8 {
         let font: Vec<u8> = font.into_iter().take(0x200 - 0x050).collect();
         // Write to memory
         for (i, b) in font.iter().enumerate() {
-            self.memory[0x050 + i] = *b;
+            self.memory.load((0x050 + i) as u16, *b);
         }
         self
     }
@@ -60,18 +193,117 @@ impl Chip8 {
     /// Set the frequency of the processor (Hz)
     pub fn set_freq(mut self, freq: u32) -> Self {
         self.freq = freq;
+        self.frame_clock = self.clock();
+        self
+    }
+
+    /// Sets where random numbers come from
+    pub fn set_random<R: RandomSource + 'static>(mut self, rng: R) -> Self {
+        self.rng = Box::new(rng);
+        self
+    }
+
+    /// Sets the interpreter quirks to follow
+    pub fn set_quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// Returns the interpreter quirks followed
+    pub fn get_quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Sets what happens on accesses past the end of memory
+    pub fn set_address_policy(mut self, policy: AddressPolicy) -> Self {
+        self.address_policy = policy;
+        self
+    }
+
+    /// Returns what happens on accesses past the end of memory
+    pub fn get_address_policy(&self) -> AddressPolicy {
+        self.address_policy
+    }
+
+    /// Sets how many return addresses the stack holds and what happens past either end
+    pub fn set_stack(mut self, depth: StackDepth, policy: StackPolicy) -> Self {
+        self.stack_depth = depth;
+        self.stack_policy = policy;
+        self
+    }
+
+    /// Returns how many return addresses the stack holds
+    pub fn get_stack_depth(&self) -> StackDepth {
+        self.stack_depth
+    }
+
+    /// Returns the return addresses on the stack, from the oldest call.
+    /// Once a wrapping stack went around, the slots are returned in storage order
+    pub fn get_stack(&self) -> Vec<u16> {
+        match self.layout {
+            Layout::Separate => (0..self.stack_top).map(|n| self.stack.get(n).copied().unwrap_or(0)).collect(), // Slots never written hold 0
+            Layout::Vip if self.sp >= Layout::VIP_STACK_TOP => Vec::new(),
+            Layout::Vip => (self.sp..Layout::VIP_STACK_TOP).step_by(2).rev()
+                .map(|addr| (self.memory.peek(addr) as u16 & 0xF) << 8 | self.memory.peek(addr + 1) as u16)
+                .collect()
+        }
+    }
+
+    /// Sets where the stack and display live, the display is carried over
+    pub fn set_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        for y in 0..framebuffer::HEIGHT {
+            self.store_row(y);
+        }
         self
     }
 
+    /// Returns where the stack and display live
+    pub fn get_layout(&self) -> Layout {
+        self.layout
+    }
+
     /// Returns the frequency of the processor (Hz)
     pub fn get_freq(&self) -> u32 {
         self.freq
     }
 
+    /// Sets how long instructions take
+    pub fn set_timing(mut self, timing: Timing) -> Self {
+        self.timing = timing;
+        self.frame_clock = self.clock();
+        self
+    }
+
+    /// Returns how long instructions take
+    pub fn get_timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Returns a clock following the timing and frequency, at the start of a frame
+    pub fn clock(&self) -> Clock {
+        Clock::new(self.timing, self.freq)
+    }
+
+    /// Returns true if the instruction fetched at `pc` skipped the next one, to be called once it ran
+    pub fn skipped_from(&self, pc: u16) -> bool {
+        self.pc.wrapping_sub(pc) as usize % MEMORY_SIZE == 4
+    }
+
+    /// Returns the clock that ends frames, keeping cycles owed from one frame to the next
+    pub fn frame_clock_mut(&mut self) -> &mut Clock {
+        &mut self.frame_clock
+    }
+
     /// Decrements both timers
     pub fn decr_timers(&mut self) {
+        let before = (self.delay_timer, self.sound_timer);
         self.delay_timer = if self.delay_timer >= 1 { self.delay_timer - 1 } else { 0 };
         self.sound_timer = if self.sound_timer >= 1 { self.sound_timer - 1 } else { 0 };
+        if before != (self.delay_timer, self.sound_timer) {
+            self.observer.on_timer(self.delay_timer, self.sound_timer);
+        }
+        self.rng.on_frame();
     }
 
     /// Returns both timer values as tuple
@@ -110,52 +342,148 @@ impl Chip8 {
         self.delay_timer
     }
 
+    /// Returns the program counter
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Returns the value of the I register
+    pub fn get_i(&self) -> u16 {
+        self.i
+    }
+
+    /// Returns a copy of the whole memory, as seen from the bus
+    pub fn get_memory(&self) -> Vec<u8> {
+        (0..MEMORY_SIZE as u16).map(|addr| self.memory.peek(addr)).collect()
+    }
+
+    /// Returns the byte at an address without side effects, following the address policy
+    pub fn peek(&self, addr: usize) -> Option<u8> {
+        let addr = self.address_policy.resolve(addr)?;
+        Some(self.memory.peek(addr as u16))
+    }
+
     /// Returns byte pointed at the I register
     pub fn read_at_i(&self, offset: u8) -> Option<u8> {
-        self.memory.get(self.i as usize + offset as usize).copied()
+        self.peek(self.i as usize + offset as usize)
     }
 
     // Sets the value of the I register
     fn set_i(&mut self, val: u16) {
         self.i = val & 0xFFF;
+        self.observer.on_i_write(self.i);
     }
 
-    /// Fetch the two succeeding bytes at pc
-    pub fn fetch(&mut self) -> u16 {
-        match (self.memory.get(self.pc as usize), self.memory.get((self.pc + 1) as usize)) {
-            (Some(&b1), Some(&b2)) => {
-                self.pc += 2; // Increment pc
-                ((b1 as u16) << 8) | b2 as u16 // Return the two fetched bytes
-            },
-            (_, _) => panic!("Couldn't fetch two bytes at program counter !")
+    // Reads a byte of memory on behalf of an instruction, following the address policy
+    fn read_mem(&mut self, addr: usize) -> Option<u8> {
+        let addr = self.address_policy.resolve(addr)?;
+        let val = self.memory.read(addr as u16);
+        self.observer.on_mem_read(addr as u16, val);
+        Some(val)
+    }
+
+    // Writes a byte of memory on behalf of an instruction, following the address policy
+    fn write_mem(&mut self, addr: usize, val: u8) -> Result<(), ExecError> {
+        let resolved = self.address_policy.resolve(addr).ok_or(ExecError::MemoryFault(addr))?;
+        let addr = resolved as u16;
+        self.memory.write(addr, val)?;
+        self.load_row(addr);
+        self.observer.on_mem_write(addr, val);
+        Ok(())
+    }
+
+    // Checks `len` bytes from I can be accessed, so that instructions fault before changing anything
+    fn check_at_i(&self, len: usize) -> Result<(), ExecError> {
+        let last = self.i as usize + len - 1;
+        match self.address_policy.resolve(last) {
+            Some(_) => Ok(()),
+            None => Err(ExecError::MemoryFault(last))
         }
     }
 
-    // Push value onto stack and checks for overflow
-    fn push_stack(&mut self, val: u16) {
-        if self.stack.len() >= 16 { panic!("Stack overflow !"); }
-        self.stack.push(val & 0xFFF);
+    // Checks `len` bytes from I can be written, so that stores fault before writing any of them
+    fn check_write_at_i(&self, len: usize) -> Result<(), ExecError> {
+        self.check_at_i(len)?;
+        for offset in 0..len {
+            let addr = self.address_policy.resolve(self.i as usize + offset).unwrap() as u16; // Checked above
+            if !self.memory.writable(addr) {
+                return Err(ExecError::WriteFault(addr));
+            }
+        }
+        Ok(())
     }
-    
-    // Pops last stack value
-    fn pop_stack(&mut self) -> u16 {
-        match self.stack.pop() {
-            Some(v) => v,
-            None => { panic!("No value in stack !") }
+
+    /// Fetch the two succeeding bytes at pc, fails if they are past the end of memory
+    /// (unless addresses wrap)
+    pub fn fetch(&mut self) -> Result<u16, ExecError> {
+        let policy = self.address_policy;
+        match (policy.resolve(self.pc as usize), policy.resolve(self.pc as usize + 1)) {
+            (Some(a1), Some(a2)) => {
+                let pc = a1 as u16;
+                let instr = ((self.memory.read(a1 as u16) as u16) << 8) | self.memory.read(a2 as u16) as u16;
+                self.observer.on_fetch(pc, instr);
+                self.pc = pc + 2; // Increment pc
+                if policy == AddressPolicy::Wrap { self.pc %= MEMORY_SIZE as u16; }
+                Ok(instr) // Return the two fetched bytes
+            },
+            (None, _) => Err(ExecError::MemoryFault(self.pc as usize)),
+            (_, None) => Err(ExecError::MemoryFault(self.pc as usize + 1))
         }
     }
 
-    /// Decodes a u16 instruction into hex
-    pub fn decode_to_nibbles(instr: u16) -> (u8, u8, u8, u8) {
-        let i = instr;
-        (((i >> 12) & 0xF) as u8, ((i >> 8) & 0xF) as u8, ((i >> 4) & 0xF) as u8, (i & 0xF) as u8)
+    // Push value onto stack and checks for overflow
+    fn push_stack(&mut self, val: u16) -> Result<(), ExecError> {
+        if self.layout == Layout::Vip {
+            let len = Layout::VIP_STACK_TOP.saturating_sub(self.sp) as usize / 2;
+            if self.stack_policy == StackPolicy::Fault && self.stack_depth.is_full(len) {
+                return Err(ExecError::StackOverflow);
+            }
+            // Nothing stops a wrapping stack from growing down into the program
+            self.sp = self.sp.wrapping_sub(2) % MEMORY_SIZE as u16;
+            let _ = self.memory.write(self.sp, (val >> 8) as u8 & 0xF);
+            let _ = self.memory.write((self.sp + 1) % MEMORY_SIZE as u16, val as u8);
+            self.load_row(self.sp + 1); // Wrapped around into the framebuffer
+            return Ok(());
+        }
+        if self.stack_depth.is_full(self.stack_top) {
+            match self.stack_policy {
+                StackPolicy::Wrap => self.stack_top = 0, // Overwrite the oldest return address
+                StackPolicy::Fault => return Err(ExecError::StackOverflow),
+            }
+        }
+        if self.stack_top < self.stack.len() { self.stack[self.stack_top] = val & 0xFFF; }
+        else { self.stack.push(val & 0xFFF); }
+        self.stack_top += 1;
+        Ok(())
+    }
+    
+    // Pops last stack value and checks for underflow
+    fn pop_stack(&mut self) -> Result<u16, ExecError> {
+        if self.layout == Layout::Vip {
+            if self.stack_policy == StackPolicy::Fault && self.sp >= Layout::VIP_STACK_TOP {
+                return Err(ExecError::StackUnderflow);
+            }
+            let val = (self.memory.read(self.sp) as u16 & 0xF) << 8 | self.memory.read((self.sp + 1) % MEMORY_SIZE as u16) as u16;
+            self.sp = (self.sp + 2) % MEMORY_SIZE as u16;
+            return Ok(val);
+        }
+        if self.stack_top == 0 {
+            match (self.stack_policy, self.stack_depth) {
+                (StackPolicy::Wrap, StackDepth::Limited(n)) => self.stack_top = n, // Return to whatever the top slot holds
+                _ => return Err(ExecError::StackUnderflow),
+            }
+        }
+        self.stack_top -= 1;
+        Ok(self.stack.get(self.stack_top).copied().unwrap_or(0)) // Slots never written hold 0
     }
 
     /// Executes a u16 instruction
-    pub fn exec(&mut self, instr: u16) -> Result<(), ()> {
+    pub fn exec(&mut self, instr: u16) -> Result<(), ExecError> {
         let i = instr;
-        let nibbles = Self::decode_to_nibbles(i);
+        let nibbles = Chip8::decode_to_nibbles(i);
         let (b2, imm_address) = ((i & 0xFF) as u8, (i & 0xFFF) as u16);
+        let (x, y) = (VReg::from_nibble(nibbles.1), VReg::from_nibble(nibbles.2));
+        let (vx, vy) = (self.vars[x], self.vars[y]);
 
         match nibbles {
             // (0x0, 0x0, 0xC, _) => format!("SCDOWN {:01X}", i & 0xF),
@@ -164,7 +492,7 @@ impl Chip8 {
                 Ok(())
             },
             (0x0, 0x0, 0xE, 0xE) => { // RTS (UNTESTED)
-                self.pc = self.pop_stack();
+                self.pc = self.pop_stack()?;
                 Ok(())
             },
             // (0x0, 0x0, 0xF, 0xB) => format!("SCRIGHT"),
@@ -176,292 +504,241 @@ impl Chip8 {
                 Ok(())
             },
             (0x2, _, _, _) => { // JSR NNN (UNTESTED)
-                self.push_stack(self.pc); // Push pc into stack
+                self.push_stack(self.pc)?; // Push pc into stack
                 self.jump_to(imm_address); // Jump to address
                 Ok(())
             },
             (0x3, _, _, _) => { // SKEQ VX, NN (UNTESTED)
-                match self.get_reg(nibbles.1) {
-                    Some(vx) => {
-                        if vx == b2 { self.pc += 2; } // Skip next instruction
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                if vx == b2 { self.pc += 2; } // Skip next instruction
+                Ok(())
             },
             (0x4, _, _, _) => { // SKNE VX, NN (UNTESTED)
-                match self.get_reg(nibbles.1) {
-                    Some(vx) => {
-                        if vx != b2 { self.pc += 2; } // Skip next instruction
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                if vx != b2 { self.pc += 2; } // Skip next instruction
+                Ok(())
             },
             (0x5, _, _, 0x0) => { // SKEQ VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    if vx == vy { self.pc += 2; } // Skip next instruction
-                    Ok(())
-                } else { return Err(()) }
+                if vx == vy { self.pc += 2; } // Skip next instruction
+                Ok(())
             },
             (0x6, _, _, _) => { // MOV VX, NN
-                self.set_reg(nibbles.1 & 0xF, b2)
+                self.set_reg(x, b2);
+                Ok(())
             },
             (0x7, _, _, _) => { // ADD VX, NN
-                // Get register value
-                match self.get_reg(nibbles.1) {
-                    Some(v) => {
-                        let val: u16 = v as u16 + b2 as u16; // u16 to prevent overflow
-                        return self.set_reg(nibbles.1 & 0xF, (val & 0xFF) as u8)
-                    },
-                    None => return Err(())
-                }
+                self.set_reg(x, vx.wrapping_add(b2));
+                Ok(())
             },
             (0x8, _, _, 0x0) => { // MOV VX, VY (UNTESTED)
-                if let (Some(_), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    self.set_reg(nibbles.1, vy)?;
-                    Ok(())
-                } else { return Err(()) }
+                self.set_reg(x, vy);
+                Ok(())
             },
             (0x8, _, _, 0x1) => { // OR VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    self.set_reg(nibbles.1, vx | vy)?;
-                    Ok(())
-                } else { return Err(()) }
+                self.set_reg(x, vx | vy);
+                if self.quirks.vf_reset { self.set_flag(0); }
+                Ok(())
             },
             (0x8, _, _, 0x2) => { // AND VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    self.set_reg(nibbles.1, vx & vy)?;
-                    Ok(())
-                } else { return Err(()) }
+                self.set_reg(x, vx & vy);
+                if self.quirks.vf_reset { self.set_flag(0); }
+                Ok(())
             },
             (0x8, _, _, 0x3) => { // XOR VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    self.set_reg(nibbles.1, vx ^ vy)?;
-                    Ok(())
-                } else { return Err(()) }
+                self.set_reg(x, vx ^ vy);
+                if self.quirks.vf_reset { self.set_flag(0); }
+                Ok(())
             },
             (0x8, _, _, 0x4) => { // ADD VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    let val = vx as u16 + vy as u16; // u16 to prevent overflow
-                    self.set_flag({
-                        if val > 0xFF { 1 } // Set VF
-                        else { 0 }
-                    });
-                    self.set_reg(nibbles.1, (val & 0xFF) as u8)?;
-                    Ok(())
-                } else { return Err(()) }
+                let val = vx as u16 + vy as u16; // u16 to prevent overflow
+                self.set_flag({
+                    if val > 0xFF { 1 } // Set VF
+                    else { 0 }
+                });
+                self.set_reg(x, (val & 0xFF) as u8);
+                Ok(())
             },
             (0x8, _, _, 0x5) => { // SUB VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    let diff = max(vx, vy) - min(vx, vy); // For knowing how much to subtract
-                    let val = if vx < vy { // Check underflow
-                        self.set_flag(0);
-                        0xFF - diff
-                    } else { self.set_flag(1); vx - diff };
-                    self.set_reg(nibbles.1, val)?;
-                    Ok(())
-                } else { return Err(()) }
+                let diff = max(vx, vy) - min(vx, vy); // For knowing how much to subtract
+                let val = if vx < vy { // Check underflow
+                    self.set_flag(0);
+                    0xFF - diff
+                } else { self.set_flag(1); vx - diff };
+                self.set_reg(x, val);
+                Ok(())
             },
             (0x8, _, _, 0x6) => { // SHR VX (UNTESTED AMBIGUOUS)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    self.set_reg(nibbles.1, vy)?; // Optional (set vx to vy)
-                    self.set_flag(vx & 0x1); // Set VF flag (Check least significant bit)
-                    self.set_reg(nibbles.1, (vx >> 1) & 0xFF)?; // Shift VX
-                    Ok(())
-                } else { return Err(()) }
+                let src = if self.quirks.shift_vy { vy } else { vx }; // Shift VY into VX on the COSMAC VIP
+                self.set_reg(x, src >> 1); // Shift
+                self.set_flag(src & 0x1); // Set VF flag (Check least significant bit)
+                Ok(())
             },
             (0x8, _, _, 0x7) => { // RSB VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    let diff = max(vx, vy) - min(vx, vy); // For knowing how much to subtract
-                    let val = if vy < vx { // Check underflow
-                        self.set_flag(0);
-                        0xFF - diff
-                    } else { self.set_flag(1); vy - diff };
-                    self.set_reg(nibbles.1, val)?;
-                    Ok(())
-                } else { return Err(()) }
+                let diff = max(vx, vy) - min(vx, vy); // For knowing how much to subtract
+                let val = if vy < vx { // Check underflow
+                    self.set_flag(0);
+                    0xFF - diff
+                } else { self.set_flag(1); vy - diff };
+                self.set_reg(x, val);
+                Ok(())
             },
             (0x8, _, _, 0xE) => { // SHL VX (UNTESTED AMBIGUOUS)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    self.set_reg(nibbles.1, vy)?; // Optional (set vx to vy)
-                    self.set_flag((vx >> 7) & 0x1); // Set VF flag (Check most significant bit)
-                    self.set_reg(nibbles.1, (vx << 1) & 0xFF)?; // Shift VX
-                    Ok(())
-                } else { return Err(()) }
+                let src = if self.quirks.shift_vy { vy } else { vx }; // Shift VY into VX on the COSMAC VIP
+                self.set_reg(x, src << 1); // Shift
+                self.set_flag((src >> 7) & 0x1); // Set VF flag (Check most significant bit)
+                Ok(())
             },
             (0x9, _, _, 0x0) => { // SKNE VX, VY (UNTESTED)
-                if let (Some(vx), Some(vy)) = (self.get_reg(nibbles.1), self.get_reg(nibbles.2)) {
-                    if vx != vy { self.pc += 2; } // Skip next instruction
-                    Ok(())
-                } else { return Err(()) }
+                if vx != vy { self.pc += 2; } // Skip next instruction
+                Ok(())
             },
             (0xA, _, _, _) => { // MVI I NNN (Sets i register)
                 self.set_i(imm_address);
                 Ok(())
             },
             (0xB, _, _, _) => { // JMI NNN (UNTESTED AMBIGUOUS)
-                self.jump_to(imm_address + self.get_reg(0x0).unwrap() as u16);
+                let reg = if self.quirks.jump_vx { x } else { VReg::V0 }; // BXNN uses VX on SUPER-CHIP
+                self.jump_to(imm_address + self.get_reg(reg) as u16);
                 Ok(())
             },
             (0xC, _, _, _) => { // RAND VX, NN
-                if let Some(_) = self.get_reg(nibbles.1) {
-                    let val = self.rng.gen::<u8>() & b2; 
-                    self.set_reg(nibbles.1, val & 0xFF)?; // Generate a random number and binary ANDs the number with the second byte
-                    Ok(())
-                } else { return Err(()) }
+                let val = self.rng.next_byte() & b2;
+                self.set_reg(x, val); // Generate a random number and binary ANDs the number with the second byte
+                Ok(())
             },
             // (0xD, _, _, 0x0) => format!("XSPRITE R{:01X}, R{:01X}", nibbles.1, nibbles.2),
             (0xD, _, _, _) => { // SRPITE VX, VY, N
-                self.display(nibbles.1, nibbles.2, nibbles.3)
+                self.display(x, y, nibbles.3)
             },
             (0xE, _, 0x9, 0xE) => { // SKPR K
-                match self.get_reg(nibbles.1) {
-                    Some(v) => {
-                        match self.read_key(v) {
-                            Ok(pressed) => {
-                                if pressed { self.pc += 2; } // If the key is pressed we skip an instruction
-                                Ok(())
-                            },
-                            Err(_) => Err(())
-                        }
-                    },
-                    None => Err(())
-                }
+                if self.read_key(vx).map_err(|_| ExecError::InvalidKey(vx))? { self.pc += 2; } // If the key is pressed we skip an instruction
+                Ok(())
             },
             (0xE, _, 0xA, 0x1) => { // SKUP K
-                match self.get_reg(nibbles.1) {
-                    Some(v) => {
-                        match self.read_key(v) {
-                            Ok(pressed) => {
-                                if !pressed { self.pc += 2; } // If the key is pressed we skip an instruction
-                                Ok(())
-                            },
-                            Err(_) => Err(())
-                        }
-                    },
-                    None => Err(())
-                }
+                if !self.read_key(vx).map_err(|_| ExecError::InvalidKey(vx))? { self.pc += 2; } // If the key is pressed we skip an instruction
+                Ok(())
             },
             (0xF, _, 0x0, 0x7) => { // GDELAY VR
-                self.set_reg(nibbles.1, self.delay_timer)
+                self.set_reg(x, self.delay_timer);
+                Ok(())
             },
             (0xF, _, 0x0, 0xA) => { // KEY VR (UNTESTED)
-                match self.get_reg(nibbles.1) {
-                    Some(_) => {
-                        for (i, val) in self.key_states.iter().enumerate() { // Loop through all the key states
-                            if *val {
-                                self.set_reg(nibbles.1, i as u8)?; // Set VX register to key index
-                                return Ok(()) // Exit function
-                            }
-                        }
-                        self.pc -= 2; // Execute the same instruction if we didn't find any keypress
-                        Ok(())
-                    },
-                    None => Err(())
+                // Loop through all the key states
+                if let Some(key) = self.key_states.iter().position(|pressed| *pressed) {
+                    self.set_reg(x, key as u8); // Set VX register to key index
+                    return Ok(())
                 }
+                self.observer.on_key_wait(x);
+                self.pc = self.pc.wrapping_sub(2) % MEMORY_SIZE as u16; // Execute the same instruction if we didn't find any keypress
+                Ok(())
             },
             (0xF, _, 0x1, 0x5) => { // SDELAY VR
-                match self.get_reg(nibbles.1) {
-                    Some(v) => {
-                        self.delay_timer = v;
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                self.delay_timer = vx;
+                self.observer.on_timer(self.delay_timer, self.sound_timer);
+                Ok(())
             },
             (0xF, _, 0x1, 0x8) => { // SSOUND VR
-                match self.get_reg(nibbles.1) {
-                    Some(v) => {
-                        self.sound_timer = v;
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                self.sound_timer = vx;
+                self.observer.on_timer(self.delay_timer, self.sound_timer);
+                Ok(())
             },
             (0xF, _, 0x1, 0xE) => { // ADI VR
-                match self.get_reg(nibbles.1) {
-                    Some(v) => {
-                        let val: u32 = self.i as u32 + v as u32;
-                        if val > 0xFFF { self.set_flag(1); } else { self.set_flag(0); } // Check overflow
-                        self.set_i((val & 0xFFF) as u16);
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                let val: u32 = self.i as u32 + vx as u32;
+                if val > 0xFFF { self.set_flag(1); } else { self.set_flag(0); } // Check overflow
+                self.set_i((val & 0xFFF) as u16);
+                Ok(())
             },
             (0xF, _, 0x2, 0x9) => { // FONT VR
-                match self.get_reg(nibbles.1) { // Read VX register
-                    Some(v) => {
-                        let v = v >> 4; // Get second nibble of register (COSMAC VIP)
-                        self.set_i(0x050 + v as u16); // Hardcoded font location
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                let v = vx >> 4; // Get second nibble of register (COSMAC VIP)
+                self.set_i(0x050 + v as u16); // Hardcoded font location
+                Ok(())
             },
             // (0xF, _, 0x3, 0x0) => format!("XFONT V{:01X}", nibbles.1),
             (0xF, _, 0x3, 0x3) => { // BCD VR
-                match self.get_reg(nibbles.1) { // Read VX register
-                    Some(v) => {
-                        // Check if I is pointing at valid space to store the decimal number
-                        if let (Some(_), Some(_), Some(_)) = (self.read_at_i(0), self.read_at_i(1), self.read_at_i(2)) {
-                            // Read digits
-                            let hundreds = v / 100 % 10;
-                            let tens = v / 10 % 10;
-                            let units = v % 10;
-
-                            // Write to memory
-                            self.memory[self.i as usize] = hundreds;
-                            self.memory[self.i as usize + 1] = tens;
-                            self.memory[self.i as usize + 2] = units;
-                        } else { return Err(()); }
-                        Ok(())
-                    },
-                    None => Err(())
-                }
+                // Check if I is pointing at valid space to store the decimal number
+                self.check_write_at_i(3)?;
+
+                // Read digits
+                let hundreds = vx / 100 % 10;
+                let tens = vx / 10 % 10;
+                let units = vx % 10;
+
+                // Write to memory
+                self.write_mem(self.i as usize, hundreds)?;
+                self.write_mem(self.i as usize + 1, tens)?;
+                self.write_mem(self.i as usize + 2, units)?;
+                Ok(())
             },
             (0xF, _, 0x5, 0x5) => { // STR V0-VX
                 // Check if I is pointing at valid space
-                for i in 0..=nibbles.1 {
-                    if let None = self.memory.get(self.i as usize + i as usize) {
-                        return Err(())
-                    }
-                }
+                self.check_write_at_i(x.number() as usize + 1)?;
 
                 // Store registers V0-VX into memory pointed at I
-                for i in 0..=nibbles.1 {
-                    self.memory[self.i as usize + i as usize] = self.get_reg(i).unwrap();
+                for (offset, reg) in x.up_to().enumerate() {
+                    self.write_mem(self.i as usize + offset, self.get_reg(reg))?;
                 }
+                if self.quirks.load_store_increment_i { self.set_i(self.i + x.number() as u16 + 1); }
                 Ok(())
             },
             (0xF, _, 0x6, 0x5) => { // LDR V0-VX
                 // Check if I is pointing at valid space
-                for i in 0..=nibbles.1 {
-                    if let None = self.memory.get(self.i as usize + i as usize) {
-                        return Err(())
-                    }
-                }
+                self.check_at_i(x.number() as usize + 1)?;
 
                 // Load into register VX the value pointed by I (+ offset)
-                for i in 0..=nibbles.1 {
-                    self.set_reg(i, self.memory[self.i as usize + i as usize])?;
+                for (offset, reg) in x.up_to().enumerate() {
+                    let addr = self.i as usize + offset;
+                    let val = self.read_mem(addr).ok_or(ExecError::MemoryFault(addr))?;
+                    self.set_reg(reg, val);
                 }
+                if self.quirks.load_store_increment_i { self.set_i(self.i + x.number() as u16 + 1); }
                 Ok(())
             },
             // (0xF, _, 0x6, 0x5) => format!("LDR V0-V{:01X}", nibbles.1),
-            _ => return Err(())
+            _ => return Err(ExecError::UnknownInstruction)
         }
     }
 
     // Clears display by setting all display values to false
     fn clear_screen(&mut self) {
-        self.display.map(|_| [false; 64]);
+        self.display.clear();
+        for y in 0..framebuffer::HEIGHT {
+            self.store_row(y);
+        }
+        self.observer.on_clear();
     }
 
     /// Returns the display values
     pub fn get_display(&self) -> [[bool; 64]; 32] {
-        self.display
+        self.display.to_array()
+    }
+
+    /// Returns the display without copying it
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    /// Returns the display rows changed since the last call, one bit per row
+    pub fn take_dirty(&mut self) -> u32 {
+        let dirty = self.display.dirty_rows();
+        self.display.clear_dirty();
+        dirty
+    }
+
+    // Copies a display row into the framebuffer in RAM, in the VIP layout (without notifying the observer)
+    fn store_row(&mut self, y: usize) {
+        if self.layout != Layout::Vip { return; }
+        let start = Layout::VIP_FRAMEBUFFER + y as u16 * 8;
+        for (i, byte) in self.display.row(y).to_be_bytes().into_iter().enumerate() {
+            let _ = self.memory.write(start + i as u16, byte);
+        }
+        self.load_row(start); // The bus may have refused the writes
+    }
+
+    // Copies the display row holding an address back from RAM, after a write into the framebuffer in the VIP layout
+    fn load_row(&mut self, addr: u16) {
+        if self.layout != Layout::Vip || addr < Layout::VIP_FRAMEBUFFER { return; }
+        let y = (addr - Layout::VIP_FRAMEBUFFER) as usize / 8;
+        let start = Layout::VIP_FRAMEBUFFER + y as u16 * 8;
+        let row = (0..8).fold(0, |row, i| row << 8 | self.memory.peek(start + i) as u64);
+        self.display.set_row(y, row);
     }
 
     // Jump to address
@@ -470,86 +747,168 @@ impl Chip8 {
     }
 
     // Sets a register value
-    fn set_reg(&mut self, reg: u8, val: u8) -> Result<(), ()> {
-        match self.vars.get(reg as usize) {
-            Some(_) => {
-                self.vars[reg as usize] = val;
-                Ok(())
-            },
-            None => Err(())
-        }
+    fn set_reg(&mut self, reg: VReg, val: u8) {
+        self.vars[reg] = val;
+        self.observer.on_reg_write(reg, val);
     }
 
     /// Gets a register value
-    pub fn get_reg(&self, reg: u8) -> Option<u8> {
-        match self.vars.get(reg as usize) {
-            Some(_) => {
-                Some(self.vars[reg as usize])
-            },
-            None => None
-        }
+    pub fn get_reg(&self, reg: VReg) -> u8 {
+        self.vars[reg]
+    }
+
+    /// Returns the register file
+    pub fn get_registers(&self) -> &Registers {
+        &self.vars
     }
 
     // Set flag register (VF)
     fn set_flag(&mut self, flag: u8) {
-        self.set_reg(0xF, flag).unwrap(); // I know 0xF is a valid register so I use unwrap
+        self.set_reg(VReg::VF, flag);
     }
 
     // Draws onto the display
-    fn display(&mut self, reg_x: u8, reg_y: u8, n: u8) -> Result<(), ()> {
-        if let (Some(mut x), Some(mut y)) = (self.get_reg(reg_x), self.get_reg(reg_y)) {
-            // To store I byte
-            let mut sprite: u8;
-            // Get horizontal and vertical position using modulo
-            x = x % 64;
-            y = y % 32;
-            let mut row_x = x; // For resetting the x value
-
-            let mut bit: u8; // For knowing which bit to read in the sprite
-            let mut offset = 0; // I offset
-
-            // Set flag to 0 by default
-            self.set_flag(0);
-
-            // Limit n
-            let n = n & 0xF;
-
-            // Write to screen
-            for _ in 0..n {
-                bit = 0b1 << 7;
-                
-                // Read byte located at I + Offset
-                sprite = {
-                    match self.read_at_i(offset) {
-                        Some(v) => v,
-                        None => panic!("Register I out of bounds !")
-                    }
-                };
-
-                // Loop through each bit of the sprite
-                for _ in 0..8 {
-                    match self.display[y as usize].get(row_x as usize) {
-                        Some(_) => {
-                            if bit & sprite > 0x0 { // If the bit is set
-                                let pixel: &mut bool = &mut self.display[y as usize][row_x as usize]; // Get reference to pixel on the display
-                                *pixel = !*pixel; // Flip the pixel on display
-                                if *pixel == false { self.set_flag(1); } // Set the VF Flag if we turned off the pixel
-                            }
-                        }, 
-                        None => { // When out of bounds
-                            break; // Break out of for loop (go to next row)
-                        }
-                    }
-                    row_x += 1; // Increment X
-                    bit = bit >> 1; // Shift the bit
-                }
-                
-                offset += 1; // Increment offset
-                row_x = x; // Reset X
-                y += 1;
-                if y >= 64 { break; } // Break loop if y is outside display
+    fn display(&mut self, reg_x: VReg, reg_y: VReg, n: u8) -> Result<(), ExecError> {
+        // Get horizontal and vertical position using modulo
+        let x = self.get_reg(reg_x) % 64;
+        let start_y = self.get_reg(reg_y) % 32;
+
+        // Limit n
+        let n = n & 0xF;
+
+        // Check the sprite can be read before drawing any of it
+        if n > 0 { self.check_at_i(n as usize)?; }
+
+        // XOR each byte of the sprite onto a row
+        let mut collision = false;
+        let mut y = start_y as usize;
+        for offset in 0..n as usize {
+            let addr = self.i as usize + offset;
+            let sprite = self.read_mem(addr).ok_or(ExecError::MemoryFault(addr))?;
+            collision |= self.display.blit(x as usize, y, sprite, self.quirks.wrap_sprites);
+            self.store_row(y);
+            y += 1;
+            if y >= 32 { // Outside display
+                if self.quirks.wrap_sprites { y = 0; } else { break; }
             }
-            return Ok(());
-        } else { return Err(()); }
+        }
+
+        // Set the VF Flag if we turned off a pixel
+        self.set_flag(collision as u8);
+        self.observer.on_draw(x, start_y, n, collision);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stores_fail_before_writing_read_only_memory() {
+        let mut emu = Chip8::new().with_bus(MappedBus::new().read_only(0x300..0x400));
+        emu.vars[VReg::from_nibble(0)] = 123;
+        emu.i = 0x2FE;
+        assert_eq!(emu.exec(0xF355), Err(ExecError::WriteFault(0x300))); // STR V0-V3
+        assert_eq!(emu.exec(0xF033), Err(ExecError::WriteFault(0x300))); // BCD V0
+        assert_eq!(&emu.get_memory()[0x2FE..0x300], &[0, 0]);
+
+        emu.i = 0x2FD;
+        assert_eq!(emu.exec(0xF033), Ok(()));
+        assert_eq!(&emu.get_memory()[0x2FD..0x300], &[1, 2, 3]);
+    }
+
+    // Returns an emulator with the policy and the last and first bytes of memory marked
+    fn at_the_end(policy: AddressPolicy) -> Chip8 {
+        let mut emu = Chip8::new().set_address_policy(policy);
+        for (addr, val) in [(0xFFE, 0xA1), (0xFFF, 0xA2), (0x000, 0xA3), (0x001, 0xA4)] {
+            emu.memory.load(addr, val);
+        }
+        emu
+    }
+
+    #[test]
+    fn fetch_at_the_end_of_memory() {
+        let mut emu = at_the_end(AddressPolicy::Fault);
+        emu.pc = 0xFFE;
+        assert_eq!(emu.fetch(), Ok(0xA1A2));
+        emu.pc = 0xFFF;
+        assert_eq!(emu.fetch(), Err(ExecError::MemoryFault(0x1000)));
+        emu.pc = 0x1000;
+        assert_eq!(emu.fetch(), Err(ExecError::MemoryFault(0x1000)));
+
+        let mut emu = at_the_end(AddressPolicy::Wrap);
+        emu.pc = 0xFFE;
+        assert_eq!(emu.fetch(), Ok(0xA1A2));
+        assert_eq!(emu.get_pc(), 0x000);
+        emu.pc = 0xFFF;
+        assert_eq!(emu.fetch(), Ok(0xA2A3));
+        assert_eq!(emu.get_pc(), 0x001);
+    }
+
+    #[test]
+    fn key_wait_at_the_end_of_memory() {
+        for policy in [AddressPolicy::Fault, AddressPolicy::Wrap] {
+            let mut emu = at_the_end(policy);
+            emu.memory.load(0xFFE, 0xF0);
+            emu.memory.load(0xFFF, 0x0A); // KEY V0
+            emu.pc = 0xFFE;
+            let instr = emu.fetch().unwrap();
+            assert_eq!(emu.exec(instr), Ok(()));
+            assert_eq!(emu.get_pc(), 0xFFE); // Waits on the same instruction
+
+            emu.update_key(0x7, true).unwrap();
+            let instr = emu.fetch().unwrap();
+            assert_eq!(emu.exec(instr), Ok(()));
+            assert_eq!(emu.get_reg(VReg::from_nibble(0)), 0x7);
+        }
+    }
+
+    #[test]
+    fn sprite_across_the_end_of_memory() {
+        let mut emu = at_the_end(AddressPolicy::Fault);
+        emu.i = 0xFFE;
+        assert_eq!(emu.exec(0xD004), Err(ExecError::MemoryFault(0x1001))); // DRW V0, V0, 4
+        assert!(emu.framebuffer().rows().iter().all(|r| *r == 0));
+
+        let mut emu = at_the_end(AddressPolicy::Wrap);
+        emu.i = 0xFFE;
+        assert_eq!(emu.exec(0xD004), Ok(()));
+        let rows: Vec<u64> = emu.framebuffer().rows()[..4].iter().map(|r| r >> 56).collect();
+        assert_eq!(rows, [0xA1, 0xA2, 0xA3, 0xA4]);
+    }
+
+    #[test]
+    fn bcd_across_the_end_of_memory() {
+        let mut emu = at_the_end(AddressPolicy::Fault);
+        emu.vars[VReg::from_nibble(0)] = 123;
+        emu.i = 0xFFE;
+        assert_eq!(emu.exec(0xF033), Err(ExecError::MemoryFault(0x1000)));
+        assert_eq!(emu.peek(0xFFE), Some(0xA1));
+
+        let mut emu = at_the_end(AddressPolicy::Wrap);
+        emu.vars[VReg::from_nibble(0)] = 123;
+        emu.i = 0xFFE;
+        assert_eq!(emu.exec(0xF033), Ok(()));
+        assert_eq!([emu.peek(0xFFE), emu.peek(0xFFF), emu.peek(0x000)], [Some(1), Some(2), Some(3)]);
+    }
+
+    #[test]
+    fn store_and_load_across_the_end_of_memory() {
+        let mut emu = at_the_end(AddressPolicy::Fault);
+        emu.i = 0xFFF;
+        assert_eq!(emu.exec(0xF155), Err(ExecError::MemoryFault(0x1000))); // STR V0-V1
+        assert_eq!(emu.peek(0xFFF), Some(0xA2));
+        assert_eq!(emu.exec(0xF165), Err(ExecError::MemoryFault(0x1000))); // LDR V0-V1
+        assert_eq!(emu.get_reg(VReg::from_nibble(0)), 0);
+
+        let mut emu = at_the_end(AddressPolicy::Wrap);
+        emu.i = 0xFFF;
+        assert_eq!(emu.exec(0xF165), Ok(()));
+        assert_eq!(emu.get_registers().values()[..2], [0xA2, 0xA3]);
+        emu.vars[VReg::from_nibble(0)] = 0x11;
+        emu.vars[VReg::from_nibble(1)] = 0x22;
+        assert_eq!(emu.exec(0xF155), Ok(()));
+        assert_eq!([emu.peek(0xFFF), emu.peek(0x000)], [Some(0x11), Some(0x22)]);
     }
 }
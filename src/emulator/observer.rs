@@ -0,0 +1,78 @@
+use super::VReg;
+
+/// Receives events from the emulator as instructions execute.
+/// Every method does nothing by default, implement only the ones needed.
+/// The emulator is generic over its observer so unused hooks compile away
+pub trait Observer {
+    /// An instruction has been fetched at pc
+    fn on_fetch(&mut self, _pc: u16, _instr: u16) {}
+
+    /// A byte has been read from memory by an instruction (sprites, LDR)
+    fn on_mem_read(&mut self, _addr: u16, _val: u8) {}
+
+    /// A byte has been written to memory by an instruction (BCD, STR)
+    fn on_mem_write(&mut self, _addr: u16, _val: u8) {}
+
+    /// A V register has been written
+    fn on_reg_write(&mut self, _reg: VReg, _val: u8) {}
+
+    /// The I register has been written
+    fn on_i_write(&mut self, _val: u16) {}
+
+    /// A sprite of `rows` rows has been drawn at (x, y), `collision` is the resulting VF
+    fn on_draw(&mut self, _x: u8, _y: u8, _rows: u8, _collision: bool) {}
+
+    /// The display has been cleared
+    fn on_clear(&mut self) {}
+
+    /// The delay or sound timer has changed
+    fn on_timer(&mut self, _delay: u8, _sound: u8) {}
+
+    /// The program is waiting for a key press to store into a register (FX0A)
+    fn on_key_wait(&mut self, _reg: VReg) {}
+}
+
+/// Observer ignoring every event, used by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// An optional observer, `None` ignores every event
+impl<T: Observer> Observer for Option<T> {
+    fn on_fetch(&mut self, pc: u16, instr: u16) {
+        if let Some(o) = self { o.on_fetch(pc, instr); }
+    }
+
+    fn on_mem_read(&mut self, addr: u16, val: u8) {
+        if let Some(o) = self { o.on_mem_read(addr, val); }
+    }
+
+    fn on_mem_write(&mut self, addr: u16, val: u8) {
+        if let Some(o) = self { o.on_mem_write(addr, val); }
+    }
+
+    fn on_reg_write(&mut self, reg: VReg, val: u8) {
+        if let Some(o) = self { o.on_reg_write(reg, val); }
+    }
+
+    fn on_i_write(&mut self, val: u16) {
+        if let Some(o) = self { o.on_i_write(val); }
+    }
+
+    fn on_draw(&mut self, x: u8, y: u8, rows: u8, collision: bool) {
+        if let Some(o) = self { o.on_draw(x, y, rows, collision); }
+    }
+
+    fn on_clear(&mut self) {
+        if let Some(o) = self { o.on_clear(); }
+    }
+
+    fn on_timer(&mut self, delay: u8, sound: u8) {
+        if let Some(o) = self { o.on_timer(delay, sound); }
+    }
+
+    fn on_key_wait(&mut self, reg: VReg) {
+        if let Some(o) = self { o.on_key_wait(reg); }
+    }
+}
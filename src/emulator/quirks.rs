@@ -0,0 +1,44 @@
+/// Behaviours that differ between CHIP-8 interpreters.
+/// Everything off matches what this emulator has always done
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    pub shift_vy: bool, // 8XY6 / 8XYE shift VY into VX instead of shifting VX in place
+    pub load_store_increment_i: bool, // FX55 / FX65 leave I pointing after the last register
+    pub jump_vx: bool, // BXNN jumps to XNN + VX instead of NNN + V0
+    pub vf_reset: bool, // 8XY1 / 8XY2 / 8XY3 reset VF
+    pub wrap_sprites: bool, // Sprites wrap around the screen edges instead of being clipped
+}
+
+impl Quirks {
+    /// Names of the quirks, as used on the command line
+    pub const NAMES: [&'static str; 5] = ["shift", "loadstore", "jump", "vfreset", "wrap"];
+
+    // Returns the flag behind a quirk name
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift" => Some(&mut self.shift_vy),
+            "loadstore" => Some(&mut self.load_store_increment_i),
+            "jump" => Some(&mut self.jump_vx),
+            "vfreset" => Some(&mut self.vf_reset),
+            "wrap" => Some(&mut self.wrap_sprites),
+            _ => None
+        }
+    }
+
+    /// Turns a quirk on or off by name, fails if there's no such quirk
+    pub fn set(&mut self, name: &str, val: bool) -> Result<(), String> {
+        match self.flag(name) {
+            Some(f) => {
+                *f = val;
+                Ok(())
+            },
+            None => Err(format!("Unknown quirk \"{}\" ! ({})", name, Self::NAMES.join(", ")))
+        }
+    }
+
+    /// Returns the names of the quirks turned on
+    pub fn enabled(&self) -> Vec<&'static str> {
+        let mut copy = *self;
+        Self::NAMES.into_iter().filter(|n| *copy.flag(n).unwrap()).collect()
+    }
+}
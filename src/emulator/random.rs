@@ -0,0 +1,62 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// Where CXNN gets its random bytes from
+pub trait RandomSource: Send {
+    /// Returns the next random byte
+    fn next_byte(&mut self) -> u8;
+
+    /// Called once per 60 Hz frame, when the timers are decremented
+    fn on_frame(&mut self) {}
+}
+
+/// Pseudo random generator, reproducible from its seed
+pub struct SeededRandom {
+    rng: StdRng,
+}
+
+impl SeededRandom {
+    /// Returns a generator producing the same bytes for the same seed
+    pub fn new(seed: u64) -> Self {
+        Self { rng: StdRng::seed_from_u64(seed) }
+    }
+
+    /// Returns a generator seeded from the operating system
+    pub fn from_entropy() -> Self {
+        Self { rng: StdRng::from_entropy() }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.rng.gen()
+    }
+}
+
+/// Random routine of the original COSMAC VIP interpreter: a counter advanced by the display
+/// interrupt every frame picks a byte of the interpreter's own code, which is added to the
+/// previous number. Numbers depend on timing and repeat with short periods, as on hardware
+pub struct VipRandom {
+    code: [u8; 256], // Interpreter page the bytes are picked from
+    counter: u8,
+    last: u8,
+}
+
+impl VipRandom {
+    /// Returns the routine reading from a page of interpreter code (0x100-0x1FF on the VIP)
+    pub fn new(code: [u8; 256], seed: u8) -> Self {
+        Self { code, counter: seed, last: 0 }
+    }
+}
+
+impl RandomSource for VipRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.counter = self.counter.wrapping_add(1);
+        self.last = self.code[self.counter as usize].wrapping_add(self.last);
+        self.last
+    }
+
+    fn on_frame(&mut self) {
+        self.counter = self.counter.wrapping_add(1);
+    }
+}
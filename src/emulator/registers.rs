@@ -0,0 +1,86 @@
+use std::fmt;
+use std::ops::{Index, IndexMut};
+
+/// Names one of the 16 general purpose registers, V0 to VF
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VReg(u8);
+
+impl VReg {
+    pub const V0: VReg = VReg(0x0);
+    pub const VF: VReg = VReg(0xF); // Flag register
+
+    /// Every register, from V0 to VF
+    pub const ALL: [VReg; 16] = {
+        let mut all = [VReg(0); 16];
+        let mut n = 0;
+        while n < 16 {
+            all[n] = VReg(n as u8);
+            n += 1;
+        }
+        all
+    };
+
+    /// Returns the register with a given number, if there is one
+    pub fn new(n: u8) -> Option<VReg> {
+        if n < 16 { Some(VReg(n)) } else { None }
+    }
+
+    /// Returns the register named by the low nibble of a value, as encoded in instructions
+    pub fn from_nibble(n: u8) -> VReg {
+        VReg(n & 0xF)
+    }
+
+    /// Returns the number of the register
+    pub fn number(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the registers from V0 up to this one included (FX55, FX65)
+    pub fn up_to(self) -> impl Iterator<Item = VReg> {
+        (0..=self.0).map(VReg)
+    }
+}
+
+impl TryFrom<u8> for VReg {
+    type Error = ();
+
+    fn try_from(n: u8) -> Result<Self, Self::Error> {
+        VReg::new(n).ok_or(())
+    }
+}
+
+impl fmt::Display for VReg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "V{:X}", self.0)
+    }
+}
+
+/// The general purpose registers, indexed by `VReg`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers([u8; 16]);
+
+impl Registers {
+    /// Returns the registers along with their values
+    pub fn iter(&self) -> impl Iterator<Item = (VReg, u8)> + '_ {
+        VReg::ALL.into_iter().map(|r| (r, self[r]))
+    }
+
+    /// Returns the values from V0 to VF
+    pub fn values(&self) -> [u8; 16] {
+        self.0
+    }
+}
+
+impl Index<VReg> for Registers {
+    type Output = u8;
+
+    fn index(&self, reg: VReg) -> &u8 {
+        &self.0[reg.0 as usize] // Always in bounds
+    }
+}
+
+impl IndexMut<VReg> for Registers {
+    fn index_mut(&mut self, reg: VReg) -> &mut u8 {
+        &mut self.0[reg.0 as usize]
+    }
+}
@@ -0,0 +1,160 @@
+/// Number of return addresses the call stack can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackDepth {
+    Limited(usize),
+    Unlimited,
+}
+
+impl Default for StackDepth {
+    fn default() -> Self {
+        StackDepth::Limited(16)
+    }
+}
+
+impl StackDepth {
+    /// Returns the depth with a given name, a number of levels or "unlimited"
+    pub fn from_name(name: &str) -> Option<StackDepth> {
+        match name {
+            "unlimited" => Some(StackDepth::Unlimited),
+            _ => name.parse().ok().filter(|n| *n > 0).map(StackDepth::Limited)
+        }
+    }
+
+    /// Returns true if `len` return addresses fill the stack
+    pub fn is_full(self, len: usize) -> bool {
+        match self {
+            StackDepth::Limited(n) => len >= n,
+            StackDepth::Unlimited => false,
+        }
+    }
+}
+
+/// What happens on a call with a full stack or a return with an empty one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StackPolicy {
+    /// The stack pointer wraps around the levels, overwriting the oldest return address or
+    /// returning to a stale one, as interpreters without bounds checks did
+    Wrap,
+    /// The instruction fails
+    #[default]
+    Fault,
+}
+
+impl StackPolicy {
+    pub const NAMES: [&'static str; 2] = ["wrap", "fault"];
+
+    /// Returns the policy with a given name
+    pub fn from_name(name: &str) -> Option<StackPolicy> {
+        match name {
+            "wrap" => Some(StackPolicy::Wrap),
+            "fault" => Some(StackPolicy::Fault),
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Bus, Chip8, ExecError, Layout};
+
+    // Calls 0x300 from `from`, pushing the address after the call
+    fn call(emu: &mut Chip8, from: u16) -> Result<(), ExecError> {
+        emu.pc = from;
+        emu.exec(0x2300)
+    }
+
+    fn chip8(layout: Layout, depth: StackDepth, policy: StackPolicy) -> Chip8 {
+        Chip8::new().set_layout(layout).set_stack(depth, policy)
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(StackDepth::from_name("12"), Some(StackDepth::Limited(12)));
+        assert_eq!(StackDepth::from_name("unlimited"), Some(StackDepth::Unlimited));
+        assert_eq!(StackDepth::from_name("0"), None);
+        assert_eq!(StackPolicy::from_name("wrap"), Some(StackPolicy::Wrap));
+        assert!(StackDepth::Limited(12).is_full(12) && !StackDepth::Limited(12).is_full(11));
+        assert!(!StackDepth::Unlimited.is_full(usize::MAX));
+    }
+
+    #[test]
+    fn overflow_faults_at_each_depth() {
+        for layout in [Layout::Separate, Layout::Vip] {
+            for n in [12, 16] {
+                let mut emu = chip8(layout, StackDepth::Limited(n), StackPolicy::Fault);
+                for k in 0..n as u16 {
+                    assert_eq!(call(&mut emu, 0x200 + k * 2), Ok(()));
+                }
+                assert_eq!(call(&mut emu, 0x400), Err(ExecError::StackOverflow), "{:?} at {}", layout, n);
+                let expected: Vec<u16> = (0..n as u16).map(|k| 0x200 + k * 2).collect();
+                assert_eq!(emu.get_stack(), expected);
+            }
+            let mut emu = chip8(layout, StackDepth::Unlimited, StackPolicy::Fault);
+            for k in 0..100 {
+                assert_eq!(call(&mut emu, 0x200 + k * 2), Ok(()));
+            }
+            assert_eq!(emu.get_stack().len(), 100);
+        }
+    }
+
+    #[test]
+    fn underflow_faults() {
+        for layout in [Layout::Separate, Layout::Vip] {
+            for depth in [StackDepth::Limited(12), StackDepth::Limited(16), StackDepth::Unlimited] {
+                let mut emu = chip8(layout, depth, StackPolicy::Fault);
+                call(&mut emu, 0x200).unwrap();
+                assert_eq!(emu.exec(0x00EE), Ok(()));
+                assert_eq!(emu.get_pc(), 0x200);
+                assert_eq!(emu.exec(0x00EE), Err(ExecError::StackUnderflow));
+            }
+        }
+    }
+
+    #[test]
+    fn wrapped_overflow_overwrites_the_oldest_call() {
+        for n in [12, 16] {
+            let mut emu = chip8(Layout::Separate, StackDepth::Limited(n), StackPolicy::Wrap);
+            for k in 0..=n as u16 {
+                call(&mut emu, 0x200 + k * 2).unwrap();
+            }
+            let last = 0x200 + n as u16 * 2;
+            assert_eq!(emu.get_stack(), [last]);
+            // Returning past the bottom goes back around to the newest surviving call
+            emu.exec(0x00EE).unwrap();
+            assert_eq!(emu.get_pc(), last);
+            emu.exec(0x00EE).unwrap();
+            assert_eq!(emu.get_pc(), last - 2);
+        }
+    }
+
+    #[test]
+    fn wrapped_underflow_returns_to_the_top_slot() {
+        let mut emu = chip8(Layout::Separate, StackDepth::Limited(12), StackPolicy::Wrap);
+        assert_eq!(emu.exec(0x00EE), Ok(()));
+        assert_eq!(emu.get_pc(), 0x000); // Never written
+        assert_eq!(emu.get_stack().len(), 11);
+
+        // Without a top slot there is nowhere to wrap to
+        let mut emu = chip8(Layout::Separate, StackDepth::Unlimited, StackPolicy::Wrap);
+        assert_eq!(emu.exec(0x00EE), Err(ExecError::StackUnderflow));
+    }
+
+    #[test]
+    fn vip_stack_grows_into_ram_when_wrapping() {
+        // The depth doesn't stop calls, the stack keeps growing down
+        let mut emu = chip8(Layout::Vip, StackDepth::Limited(12), StackPolicy::Wrap);
+        for k in 0..13 {
+            call(&mut emu, 0x200 + k * 2).unwrap();
+        }
+        assert_eq!(emu.get_stack().len(), 13);
+        assert_eq!(emu.get_memory()[0xEB6..0xEB8], [0x02, 0x18]);
+
+        // Returning with an empty stack reads whatever lies above it
+        let mut emu = chip8(Layout::Vip, StackDepth::Limited(12), StackPolicy::Wrap);
+        emu.memory.load(0xED0, 0x03);
+        emu.memory.load(0xED1, 0x45);
+        assert_eq!(emu.exec(0x00EE), Ok(()));
+        assert_eq!(emu.get_pc(), 0x345);
+    }
+}
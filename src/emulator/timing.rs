@@ -0,0 +1,185 @@
+/// 1802 machine cycles per second on the COSMAC VIP (1.7609 MHz clock, 8 clock periods per cycle)
+pub const VIP_CYCLES_PER_SECOND: u32 = 220_113;
+/// Machine cycles the interpreter's interrupt routine takes each frame, around the display DMA
+pub const VIP_INTERRUPT_CYCLES: u32 = 46;
+/// Machine cycles the 1861 display stalls the 1802 for each frame: 8 DMA cycles on each of the 128 lines shown
+pub const VIP_DMA_CYCLES: u32 = 128 * 8;
+/// Machine cycles left to CHIP-8 instructions in each 60 Hz frame
+pub const VIP_FRAME_CYCLES: u32 = VIP_CYCLES_PER_SECOND / 60 - VIP_INTERRUPT_CYCLES - VIP_DMA_CYCLES;
+
+// Machine cycles of the interpreter loop fetching and decoding any instruction
+const VIP_FETCH_CYCLES: u32 = 40;
+
+/// How long instructions take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// Every instruction takes 1 / freq seconds
+    #[default]
+    Fixed,
+    /// Instructions cost the machine cycles the COSMAC VIP interpreter spends on them (see `vip_cycles`),
+    /// frames have the cycles the display leaves and sprites are drawn after waiting for its interrupt
+    Vip,
+}
+
+impl Timing {
+    pub const NAMES: [&'static str; 2] = ["fixed", "vip"];
+
+    /// Returns the timing with a given name
+    pub fn from_name(name: &str) -> Option<Timing> {
+        match name {
+            "fixed" => Some(Timing::Fixed),
+            "vip" => Some(Timing::Vip),
+            _ => None
+        }
+    }
+}
+
+/// Machine cycles the COSMAC VIP interpreter spends on an instruction, fetch included, `vx` being the
+/// value of its X register before it runs and `skipped` whether a skip instruction skipped.
+/// Costs are those Laurence Scotford counted in the interpreter listing ("Chip-8 on the COSMAC VIP",
+/// laurencescotford.net), as tabulated in Jackson Sommerich's "Chip-8 Instruction Scheduling and
+/// Frequency". Sprites are split in two by `vip_sprite_cycles`, this returns their sum
+pub fn vip_cycles(instr: u16, vx: u8, skipped: bool) -> u32 {
+    let x = ((instr >> 8) & 0xF) as u32;
+    let skip = |cycles: u32| if skipped { cycles + 4 } else { cycles };
+    let cost = match ((instr >> 12) & 0xF, instr & 0xFF) {
+        (0x0, 0xE0) => 24 + 3054, // Clears the 256 bytes of the framebuffer
+        (0x0, 0xEE) => 10,
+        (0x0, _) => 26, // Calling the machine code, whose own cost isn't counted
+        (0x1, _) => 12,
+        (0x2, _) => 26,
+        (0x3, _) | (0x4, _) => skip(10),
+        (0x5, _) | (0x9, _) => skip(14),
+        (0x6, _) => 6,
+        (0x7, _) => 10,
+        (0x8, yn) if yn & 0xF == 0 => 12,
+        (0x8, _) => 44, // Runs the ALU operation as machine code built on the fly
+        (0xA, _) => 12,
+        (0xB, nn) => if vx as u32 + nn as u32 > 0xFF { 24 } else { 22 }, // V0 + NNN crossing a page
+        (0xC, _) => 36,
+        (0xD, _) => {
+            let (prepare, draw) = vip_sprite_cycles(instr, vx);
+            return prepare + draw;
+        },
+        (0xE, _) => skip(14),
+        (0xF, 0x07) | (0xF, 0x15) | (0xF, 0x18) => 10,
+        (0xF, 0x0A) => 18, // Each poll of the keypad while waiting
+        (0xF, 0x1E) => 12,
+        (0xF, 0x29) => 16,
+        (0xF, 0x33) => 84 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u32, // Counts each digit down by subtracting
+        (0xF, 0x55) | (0xF, 0x65) => 14 + 14 * (x + 1),
+        _ => 10
+    };
+    VIP_FETCH_CYCLES + cost
+}
+
+/// Machine cycles of a DXYN on the COSMAC VIP, `vx` being the X coordinate: the sprite is prepared
+/// (each row shifted to the pixel it starts on) before the interpreter waits for the display
+/// interrupt, then drawn into the framebuffer after it
+pub fn vip_sprite_cycles(instr: u16, vx: u8) -> (u32, u32) {
+    let n = (instr & 0xF) as u32;
+    let shift = (vx & 7) as u32;
+    (VIP_FETCH_CYCLES + 68 + n * (46 + 20 * shift), 4 + n * 26)
+}
+
+/// Counts the time taken by instructions and tells when 60 Hz frames end
+#[derive(Debug, Clone)]
+pub struct Clock {
+    timing: Timing,
+    frame_cycles: u32, // Cycles in a frame
+    cycles: u32, // Cycles into the current frame
+}
+
+impl Clock {
+    /// Returns a clock at the start of a frame, `freq` is the instructions per second of fixed timing
+    pub fn new(timing: Timing, freq: u32) -> Self {
+        let frame_cycles = match timing {
+            Timing::Fixed => (freq / 60).max(1),
+            Timing::Vip => VIP_FRAME_CYCLES,
+        };
+        Self { timing, frame_cycles, cycles: 0 }
+    }
+
+    /// Returns the clock cycles per second
+    pub fn frequency(&self) -> u32 {
+        match self.timing {
+            Timing::Fixed => self.frame_cycles * 60,
+            Timing::Vip => VIP_CYCLES_PER_SECOND,
+        }
+    }
+
+    /// Advances past an instruction, `vx` being the value of its X register before it runs and `skipped`
+    /// whether it skipped the next one. Returns the cycles elapsed and whether a frame ended (at most one per instruction)
+    pub fn advance(&mut self, instr: u16, vx: u8, skipped: bool) -> (u32, bool) {
+        if self.timing == Timing::Fixed {
+            self.cycles += 1;
+            if self.cycles < self.frame_cycles { return (1, false); }
+            self.cycles = 0;
+            return (1, true);
+        }
+
+        if instr & 0xF000 == 0xD000 { // Waits for the display interrupt, then draws in the next frame
+            let (prepare, draw) = vip_sprite_cycles(instr, vx);
+            let wait = self.frame_cycles.saturating_sub(self.cycles + prepare);
+            self.cycles = draw.min(self.frame_cycles - 1);
+            return (prepare + wait + draw, true);
+        }
+        let cost = vip_cycles(instr, vx, skipped);
+        self.cycles += cost;
+        if self.cycles < self.frame_cycles { return (cost, false); }
+        self.cycles -= self.frame_cycles;
+        self.cycles = self.cycles.min(self.frame_cycles - 1); // A long instruction can't skip frames
+        (cost, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vip_instruction_cycles() {
+        assert_eq!(vip_cycles(0x6012, 0, false), 46);
+        assert_eq!(vip_cycles(0x1200, 0, false), 52);
+        assert_eq!(vip_cycles(0x2300, 0, false), 66);
+        assert_eq!(vip_cycles(0x8124, 0, false), 84);
+        assert_eq!(vip_cycles(0x8120, 0, false), 52);
+        assert_eq!(vip_cycles(0x00E0, 0, false), 3118);
+    }
+
+    #[test]
+    fn vip_skips_cost_more_when_taken() {
+        assert_eq!(vip_cycles(0x3012, 0x12, true), 54);
+        assert_eq!(vip_cycles(0x3012, 0x00, false), 50);
+        assert_eq!(vip_cycles(0x5010, 0, true), 58);
+        assert_eq!(vip_cycles(0xE09E, 0, false), 54);
+    }
+
+    #[test]
+    fn vip_data_dependent_cycles() {
+        // One subtraction per unit of each digit: 2 + 5 + 5
+        assert_eq!(vip_cycles(0xF033, 255, false), 40 + 84 + 16 * 12);
+        assert_eq!(vip_cycles(0xF033, 0, false), 40 + 84);
+        // Registers V0 to VX
+        assert_eq!(vip_cycles(0xF355, 0, false), 40 + 14 + 14 * 4);
+        // Sprites shifted to pixels within a byte cost 20 cycles per bit and row
+        assert_eq!(vip_sprite_cycles(0xD015, 8), (40 + 68 + 5 * 46, 4 + 5 * 26));
+        assert_eq!(vip_sprite_cycles(0xD015, 11), (40 + 68 + 5 * (46 + 60), 4 + 5 * 26));
+    }
+
+    #[test]
+    fn vip_frames() {
+        assert_eq!(VIP_FRAME_CYCLES, 3668 - 46 - 1024);
+        let mut clock = Clock::new(Timing::Vip, 700);
+        assert_eq!(clock.advance(0x6000, 0, false), (46, false));
+        // Sprites wait for the interrupt ending the frame, their drawing counts in the next one
+        let (prepare, draw) = vip_sprite_cycles(0xD011, 0);
+        assert_eq!(clock.advance(0xD011, 0, false), (VIP_FRAME_CYCLES - 46 + draw, true));
+        assert!(prepare < VIP_FRAME_CYCLES - 46);
+        let mut frames = 0;
+        for _ in 0..(VIP_FRAME_CYCLES - draw) / 46 + 1 {
+            if clock.advance(0x6000, 0, false).1 { frames += 1; }
+        }
+        assert_eq!(frames, 1);
+    }
+}
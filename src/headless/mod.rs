@@ -0,0 +1,162 @@
+use std::fmt;
+
+use crate::disassembler::disassemble;
+use crate::emulator::{Bus, Chip8, Clock, ExecError, NoObserver, Observer, Ram, VReg};
+
+/// Deterministic keypad input fed to a headless run
+#[derive(Debug, Clone)]
+pub enum Input {
+    /// No key is ever pressed
+    Idle,
+    /// Presses every key in turn (0 to F), each held then released for the given number of frames
+    Sweep(u64),
+    /// Holds a set of keys starting at a frame, until the next entry (sorted by frame)
+    Script(Vec<(u64, [bool; 16])>),
+}
+
+impl Default for Input {
+    /// Sweeps through the keys, holding each one for 10 frames
+    fn default() -> Self {
+        Input::Sweep(10)
+    }
+}
+
+impl Input {
+    /// Parses an input script. Each line holds a frame number followed by the
+    /// keys (hex) held from that frame on, a frame alone releases every key:
+    /// ```text
+    /// # Start the game then move left
+    /// 60 5
+    /// 70
+    /// 120 4 6
+    /// ```
+    pub fn parse_script(text: &str) -> Result<Self, String> {
+        let mut entries = Vec::<(u64, [bool; 16])>::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim(); // Strip comments
+            if line.is_empty() { continue; }
+
+            let mut words = line.split_whitespace();
+            let frame = words.next().unwrap().parse::<u64>()
+                .map_err(|_| format!("Line {}: invalid frame number", n + 1))?;
+            let mut keys = [false; 16];
+            for word in words {
+                match u8::from_str_radix(word, 16) {
+                    Ok(k) if k < 16 => keys[k as usize] = true,
+                    _ => return Err(format!("Line {}: invalid key \"{}\"", n + 1, word))
+                }
+            }
+            entries.push((frame, keys));
+        }
+        entries.sort_by_key(|e| e.0);
+        Ok(Input::Script(entries))
+    }
+
+    // Returns the keys held down during a frame
+    fn keys_at(&self, frame: u64) -> [bool; 16] {
+        let mut keys = [false; 16];
+        match self {
+            Input::Idle => (),
+            Input::Sweep(hold) => {
+                let hold = (*hold).max(1);
+                let step = frame / hold;
+                if step.is_multiple_of(2) { keys[((step / 2) % 16) as usize] = true; } // Release between two keys
+            },
+            Input::Script(entries) => {
+                if let Some((_, held)) = entries.iter().take_while(|e| e.0 <= frame).last() {
+                    keys = *held;
+                }
+            }
+        }
+        keys
+    }
+}
+
+/// An instruction executed by the runner
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub pc: u16, // Address the instruction was fetched from
+    pub instr: u16,
+    pub error: Option<ExecError>, // Why the instruction failed
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04X}\t0x{:04X} -> {}", self.pc, self.instr, disassemble(self.instr))?;
+        match self.error {
+            Some(e) => write!(f, " ({})", e),
+            None => Ok(())
+        }
+    }
+}
+
+/// Runs a CHIP-8 without display, sound or throttling.
+/// Timers are decremented at the end of each frame of the emulator's timing, like in the SDL main loop
+pub struct Runner<O: Observer = NoObserver, B: Bus = Ram> {
+    emu: Chip8<O, B>,
+    input: Input,
+    clock: Clock,
+    cycle: u64, // Instructions executed
+    frame: u64, // 60Hz frames elapsed
+    frame_start: bool, // No instruction ran in the current frame yet
+}
+
+impl<O: Observer, B: Bus> Runner<O, B> {
+    /// Returns a new runner around an emulator
+    pub fn new(emu: Chip8<O, B>, input: Input) -> Self {
+        Self {
+            clock: emu.clock(),
+            emu,
+            input,
+            cycle: 0,
+            frame: 0,
+            frame_start: true,
+        }
+    }
+
+    /// Fetches and executes one instruction
+    pub fn step(&mut self) -> Result<Step, Step> {
+        // Apply input at the beginning of each frame
+        if self.frame_start {
+            self.frame_start = false;
+            let held = self.input.keys_at(self.frame);
+            for (key, &pressed) in held.iter().enumerate() {
+                self.emu.update_key(key as u8, pressed).unwrap(); // Keys 0 to F are valid
+            }
+        }
+
+        let pc = self.emu.get_pc();
+        let instr = self.emu.fetch().map_err(|e| Step { pc, instr: 0, error: Some(e) })?; // Nothing to fetch past memory
+        let vx = self.emu.get_reg(VReg::from_nibble((instr >> 8) as u8));
+        if let Err(e) = self.emu.exec(instr) { return Err(Step { pc, instr, error: Some(e) }); }
+        let step = Step { pc, instr, error: None };
+
+        self.cycle += 1;
+        if self.clock.advance(instr, vx, self.emu.skipped_from(pc)).1 { // 60Hz Timers
+            self.emu.decr_timers();
+            self.frame += 1;
+            self.frame_start = true;
+        }
+        Ok(step)
+    }
+
+    /// Returns the emulator
+    pub fn get_emu(&self) -> &Chip8<O, B> {
+        &self.emu
+    }
+
+    /// Returns the emulator mutably
+    pub fn get_emu_mut(&mut self) -> &mut Chip8<O, B> {
+        &mut self.emu
+    }
+
+    /// Returns the number of instructions executed
+    pub fn get_cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Returns the number of 60Hz frames elapsed
+    pub fn get_frame(&self) -> u64 {
+        self.frame
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::analysis::{instr_size, Analysis};
+use crate::database::{sha1_hex, RomInfo};
+use crate::detect::detect;
+use crate::disassembler::{disassemble, opcode_class, OpcodeClass};
+
+/// Summary of a ROM for catalogs
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub size: usize,
+    pub address: u16, // Load address
+    pub sha1: String,
+    pub crc32: String,
+    pub title: Option<String>, // From the rom database
+    pub platform: String, // Detected
+    pub confidence: f64, // From 0 to 1
+    pub quirks: Vec<&'static str>,
+    pub classes: BTreeMap<&'static str, usize>, // Opcode class -> Reachable instructions
+    pub instructions: Vec<String>, // Distinct mnemonics reachable
+    pub subroutines: usize,
+    pub code_bytes: usize, // Reachable from the entry point
+    pub data_bytes: usize, // Everything else
+}
+
+impl Report {
+    /// Analyses a ROM loaded at `base`, along with its database entry if known
+    pub fn new(rom: &[u8], base: u16, info: Option<&RomInfo>) -> Self {
+        let analysis = Analysis::new(rom, base);
+        let detection = detect(rom, base);
+
+        let mut classes = BTreeMap::new();
+        let mut instructions = Vec::new();
+        let mut covered = vec![false; rom.len()];
+        for (&addr, &instr) in &analysis.instructions {
+            *classes.entry(opcode_class(instr).name()).or_insert(0) += 1;
+            let asm = disassemble(instr);
+            let mnemonic = asm.split_whitespace().next().unwrap_or("").to_string();
+            if opcode_class(instr) != OpcodeClass::Unknown && !instructions.contains(&mnemonic) {
+                instructions.push(mnemonic);
+            }
+            // Overlapping instructions share bytes, those read past the end of the rom have none
+            let start = addr.wrapping_sub(base) as usize;
+            for offset in start..start + instr_size(instr) as usize {
+                if let Some(byte) = covered.get_mut(offset) {
+                    *byte = true;
+                }
+            }
+        }
+        let code_bytes = covered.iter().filter(|b| **b).count();
+        instructions.sort();
+
+        Self {
+            size: rom.len(),
+            address: base,
+            sha1: sha1_hex(rom),
+            crc32: format!("{:08x}", crc32(rom)),
+            title: info.map(|i| i.title.clone()),
+            platform: detection.platform.name().to_string(),
+            confidence: detection.confidence,
+            quirks: detection.quirks.enabled(),
+            classes,
+            instructions,
+            subroutines: analysis.subroutines.len(),
+            code_bytes,
+            data_bytes: rom.len().saturating_sub(code_bytes),
+        }
+    }
+
+    /// Returns the report as JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap() // Only strings and numbers
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = |n: usize| n as f64 / self.size.max(1) as f64 * 100.0;
+        if let Some(title) = &self.title {
+            writeln!(f, "Title:\t\t{}", title)?;
+        }
+        writeln!(f, "Size:\t\t{} bytes at 0x{:03X}", self.size, self.address)?;
+        writeln!(f, "SHA-1:\t\t{}", self.sha1)?;
+        writeln!(f, "CRC32:\t\t{}", self.crc32)?;
+        writeln!(f, "Platform:\t{} ({:.0}% confidence)", self.platform, self.confidence * 100.0)?;
+        writeln!(f, "Quirks:\t\t{}", if self.quirks.is_empty() { "none".to_string() } else { self.quirks.join(", ") })?;
+        writeln!(f, "Code:\t\t{} bytes ({:.1}%)", self.code_bytes, percent(self.code_bytes))?;
+        writeln!(f, "Data:\t\t{} bytes ({:.1}%)", self.data_bytes, percent(self.data_bytes))?;
+        writeln!(f, "Subroutines:\t{}", self.subroutines)?;
+        writeln!(f, "Instructions:\t{}", self.instructions.join(" "))?;
+        writeln!(f, "Classes:")?;
+        let total: usize = self.classes.values().sum();
+        for (class, count) in &self.classes {
+            let bar = "#".repeat((count * 40).div_ceil(total.max(1)));
+            writeln!(f, "  {:<8}{:>6}  {}", class, count, bar)?;
+        }
+        Ok(())
+    }
+}
+
+// CRC-32 (IEEE), as printed by most rom catalogs
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_bytes_past_the_end() {
+        // JP 0x201 lands on the middle of the jump, the instruction there is read past the end
+        let report = Report::new(&[0x12, 0x01, 0x00], 0x200, None);
+        assert_eq!(report.code_bytes, 3);
+        assert_eq!(report.data_bytes, 0);
+    }
+
+    #[test]
+    fn overlapping_code_counted_once() {
+        // JP 0x203, JP 0x202 there, then 0x202 and 0x204 run on: 8 bytes of instructions over 6
+        let report = Report::new(&[0x12, 0x03, 0xAA, 0x12, 0x02, 0x12], 0x200, None);
+        assert_eq!(report.code_bytes, 6);
+        assert_eq!(report.data_bytes, 0);
+    }
+}
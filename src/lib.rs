@@ -1,5 +1,25 @@
 pub mod emulator;
 pub mod disassembler;
+pub mod headless;
+pub mod bench;
+pub mod tracer;
+pub mod profiler;
+pub mod coverage;
+pub mod sanitizer;
+pub mod platform;
+pub mod analysis;
+pub mod linter;
+pub mod detect;
+pub mod database;
+pub mod cartridge;
+pub mod octo;
+pub mod loader;
+pub mod converter;
+pub mod info;
+pub mod config;
+pub mod machine;
+pub mod vip;
+pub mod worker;
 
 use sdl2::{pixels::Color, render::Canvas};
 use sdl2::video::Window;
@@ -8,7 +28,8 @@ use sdl2::keyboard::Keycode;
 use sdl2::event::Event;
 use sdl2::rect::*;
 
-use emulator::Chip8;
+use emulator::{Bus, Chip8, ExecError, Framebuffer, Observer};
+use machine::Machine;
 
 /// Displays a CHIP-8 onto a canvas (can overflow if pixel_size is too big)
 pub fn display_chip8(ch8display: [[bool; 64]; 32], canvas: &mut Canvas<Window>, pixel_size: u32, white: Color, black: Color) -> Result<(), String>{
@@ -28,48 +49,96 @@ pub fn display_chip8(ch8display: [[bool; 64]; 32], canvas: &mut Canvas<Window>,
     Ok(())
 }
 
+/// Displays a framebuffer onto a canvas, filling the background then all the pixels turned on in one call
+pub fn draw_framebuffer(framebuffer: &Framebuffer, canvas: &mut Canvas<Window>, pixel_size: u32, white: Color, black: Color) -> Result<(), String> {
+    canvas.set_draw_color(black);
+    canvas.clear();
+    let rects: Vec<Rect> = framebuffer.pixels()
+        .map(|(x, y)| Rect::new(x as i32 * pixel_size as i32, y as i32 * pixel_size as i32, pixel_size, pixel_size))
+        .collect();
+    canvas.set_draw_color(white);
+    canvas.fill_rects(&rects)
+}
+
 /// Updates CHIP-8 keystates from an SDL EventPump
-pub fn update_keys(ch8: &mut Chip8, events: &mut EventPump) -> Result<(), ()> {
+pub fn update_keys<O: Observer, B: Bus>(ch8: &mut Chip8<O, B>, events: &mut EventPump) -> Result<(), ()> {
     for event in events.poll_iter() {
-        match event {
-            Event::KeyDown { keycode: Some(Keycode::Num1), .. } => { ch8.update_key(0, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::Num2), .. } => { ch8.update_key(1, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::Num3), .. } => { ch8.update_key(2, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::Num4), .. } => { ch8.update_key(3, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::Q), .. } => { ch8.update_key(4, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::W), .. } => { ch8.update_key(5, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::E), .. } => { ch8.update_key(6, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::R), .. } => { ch8.update_key(7, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::A), .. } => { ch8.update_key(8, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::S), .. } => { ch8.update_key(9, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::D), .. } => { ch8.update_key(0xA, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::F), .. } => { ch8.update_key(0xB, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::Y), .. } => { ch8.update_key(0xC, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::X), .. } => { ch8.update_key(0xD, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::C), .. } => { ch8.update_key(0xE, true)?; },
-            Event::KeyDown { keycode: Some(Keycode::V), .. } => { ch8.update_key(0xF, true)?; },
+        update_key_event(ch8, &event).map_err(|_| ())?;
+    }
+    Ok(())
+}
+
+/// Updates CHIP-8 keystates from a single SDL event, ignores non key events
+pub fn update_key_event<O: Observer, B: Bus>(ch8: &mut Chip8<O, B>, event: &Event) -> Result<(), ExecError> {
+    Keymap::default().update(ch8, event)
+}
+
+/// Keyboard layout, maps SDL keys to the CHIP-8 keypad
+pub struct Keymap {
+    bindings: Vec<(Keycode, u8)>,
+}
 
-            
-            Event::KeyUp { keycode: Some(Keycode::Num1), .. } => { ch8.update_key(0, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::Num2), .. } => { ch8.update_key(1, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::Num3), .. } => { ch8.update_key(2, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::Num4), .. } => { ch8.update_key(3, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::Q), .. } => { ch8.update_key(4, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::W), .. } => { ch8.update_key(5, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::E), .. } => { ch8.update_key(6, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::R), .. } => { ch8.update_key(7, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::A), .. } => { ch8.update_key(8, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::S), .. } => { ch8.update_key(9, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::D), .. } => { ch8.update_key(0xA, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::F), .. } => { ch8.update_key(0xB, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::Y), .. } => { ch8.update_key(0xC, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::X), .. } => { ch8.update_key(0xD, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::C), .. } => { ch8.update_key(0xE, false)?; },
-            Event::KeyUp { keycode: Some(Keycode::V), .. } => { ch8.update_key(0xF, false)?; },
-            _ => ()
+impl Default for Keymap {
+    /// Left side of a QWERTZ keyboard (1234 / QWER / ASDF / YXCV)
+    fn default() -> Self {
+        Self {
+            bindings: vec![
+                (Keycode::Num1, 0), (Keycode::Num2, 1), (Keycode::Num3, 2), (Keycode::Num4, 3),
+                (Keycode::Q, 4), (Keycode::W, 5), (Keycode::E, 6), (Keycode::R, 7),
+                (Keycode::A, 8), (Keycode::S, 9), (Keycode::D, 0xA), (Keycode::F, 0xB),
+                (Keycode::Y, 0xC), (Keycode::X, 0xD), (Keycode::C, 0xE), (Keycode::V, 0xF),
+            ]
         }
     }
-    Ok(())
+}
+
+impl Keymap {
+    /// Adds a binding, keeping the existing ones
+    pub fn bind(mut self, key: Keycode, ch8_key: u8) -> Self {
+        self.bindings.push((key, ch8_key));
+        self
+    }
+
+    /// Adds a binding, replacing the existing ones of the key
+    pub fn rebind(mut self, key: Keycode, ch8_key: u8) -> Self {
+        self.bindings.retain(|(k, _)| *k != key);
+        self.bind(key, ch8_key)
+    }
+
+    /// Binds the actions of a game (as named in the ROM database) to the arrows, space and shift
+    pub fn bind_actions<'a>(mut self, actions: impl IntoIterator<Item = (&'a String, &'a u8)>) -> Self {
+        for (action, &ch8_key) in actions {
+            let key = match action.as_str() {
+                "up" => Keycode::Up,
+                "down" => Keycode::Down,
+                "left" => Keycode::Left,
+                "right" => Keycode::Right,
+                "a" => Keycode::Space,
+                "b" => Keycode::LShift,
+                _ => continue
+            };
+            self = self.bind(key, ch8_key);
+        }
+        self
+    }
+
+    /// Updates the keypad of a machine from a single SDL event, ignores non key events
+    pub fn update<M: Machine>(&self, ch8: &mut M, event: &Event) -> Result<(), ExecError> {
+        for (ch8_key, pressed) in self.translate(event) {
+            ch8.update_key(ch8_key, pressed)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the keypad keys pressed or released by an SDL event
+    pub fn translate(&self, event: &Event) -> Vec<(u8, bool)> {
+        let (keycode, pressed) = match event {
+            Event::KeyDown { keycode: Some(k), .. } => (k, true),
+            Event::KeyUp { keycode: Some(k), .. } => (k, false),
+            _ => return Vec::new()
+        };
+        self.bindings.iter().filter(|(k, _)| k == keycode).map(|(_, ch8_key)| (*ch8_key, pressed)).collect()
+    }
 }
 
 pub fn get_default_font() -> Vec<u8> {
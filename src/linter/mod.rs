@@ -0,0 +1,219 @@
+use std::fmt;
+
+use crate::analysis::{instr_size, is_skip, Analysis};
+use crate::disassembler::{disassemble, opcode_class, OpcodeClass};
+use crate::platform::Platform;
+
+/// How bad a lint is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+/// Problem found in a ROM
+#[derive(Debug, Clone)]
+pub struct Lint {
+    pub addr: u16,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        write!(f, "0x{:04X}\t{}\t{}", self.addr, severity, self.message)
+    }
+}
+
+/// Checks a ROM without running it, returns the lints sorted by address
+pub fn lint(rom: &[u8], base: u16, platform: Platform) -> Vec<Lint> {
+    let a = Analysis::new(rom, base);
+    let mut lints = Vec::<Lint>::new();
+    let mut push = |addr: u16, severity: Severity, message: String| lints.push(Lint { addr, severity, message });
+
+    for (&addr, &instr) in &a.instructions {
+        // Opcodes the target doesn't know about
+        if !platform.supports(instr) {
+            let message = if opcode_class(instr) == OpcodeClass::Unknown {
+                format!("invalid instruction 0x{:04X}", instr)
+            } else {
+                format!("{} is not supported on {}", disassemble(instr), platform.name())
+            };
+            push(addr, Severity::Error, message);
+        }
+
+        // Skipping a long instruction only skips its first half outside of XO-CHIP
+        if is_skip(instr) && platform != Platform::XoChip {
+            if let Some(0xF000) = a.read(addr + 2) {
+                push(addr, Severity::Warning, format!("{} skips over a 4 byte instruction, only XO-CHIP handles it", disassemble(instr)));
+            }
+        }
+
+        // Sprites read past the end of the ROM, with I set just before
+        if instr & 0xF000 == 0xD000 {
+            if instr & 0xF == 0 && platform == Platform::Chip8 {
+                push(addr, Severity::Warning, format!("{} draws nothing on chip8, 16x16 sprites need schip", disassemble(instr)));
+            }
+            let rows = match instr & 0xF { 0 if platform == Platform::Chip8 => 0, 0 => 32, n => n };
+            if let Some(i) = known_i(&a, addr) {
+                if i >= a.base && i + rows > a.end() {
+                    push(addr, Severity::Warning, format!("sprite at 0x{:03X} reads {} bytes past the end of the rom", i, i + rows - a.end()));
+                }
+            }
+        }
+    }
+
+    // Control flow landing inside another instruction
+    for &(from, to) in &a.edges {
+        if let Some(start) = a.instruction_around(to) {
+            push(from, Severity::Error, format!("jumps into the middle of the instruction at 0x{:03X}", start));
+        }
+        if to < a.base || to >= a.end() {
+            push(from, Severity::Warning, format!("jumps outside of the rom to 0x{:03X}", to));
+        }
+    }
+
+    // Calls and returns
+    for &sub in &a.subroutines {
+        let returns = a.local_reach(sub).iter().any(|addr| a.instructions[addr] == 0x00EE);
+        if !returns && sub >= a.base && sub < a.end() {
+            push(sub, Severity::Warning, "subroutine never returns".to_string());
+        }
+    }
+    for addr in a.local_reach(a.base) {
+        if a.instructions[&addr] == 0x00EE {
+            push(addr, Severity::Error, "return outside of any subroutine".to_string());
+        }
+    }
+    for &addr in &a.dynamic_jumps {
+        push(addr, Severity::Warning, "computed jump, code past it wasn't analyzed".to_string());
+    }
+
+    // Code no path leads to. Regions holding sprites (pointed by ANNN) are data
+    for (start, end) in unreached_regions(&a) {
+        if a.i_loads.values().any(|&i| i >= start && i < end) { continue; }
+        let words: Vec<u16> = (start..end).step_by(2).filter_map(|addr| a.read(addr)).collect();
+        let valid = words.iter().filter(|&&w| platform.supports(w)).count();
+        if words.len() >= 2 && valid * 4 >= words.len() * 3 {
+            push(start, Severity::Warning, format!("unreachable code up to 0x{:03X} ({} instructions)", end - 1, words.len()));
+        }
+    }
+
+    lints.sort_by_key(|l| (l.addr, l.severity));
+    lints
+}
+
+// Returns the value of I at an instruction when it was loaded earlier in the same straight line of code
+fn known_i(a: &Analysis, addr: u16) -> Option<u16> {
+    let mut prev = addr;
+    loop {
+        if a.edges.iter().any(|e| e.1 == prev) { return None; } // Other paths join here
+        let (&start, &instr) = a.instructions.range(..prev).next_back()?;
+        if start + instr_size(instr) != prev { return None; } // Not contiguous
+        match instr & 0xF000 {
+            0xA000 => return Some(instr & 0xFFF),
+            0x1000 | 0x2000 | 0xB000 => return None,
+            0xF000 if matches!(instr & 0xFF, 0x1E | 0x29 | 0x30 | 0x55 | 0x65) => return None, // Changes I
+            _ if instr == 0x00EE || instr == 0xF000 => return None,
+            _ => ()
+        }
+        prev = start;
+    }
+}
+
+// Returns the ROM ranges (start, end) not covered by reachable instructions
+fn unreached_regions(a: &Analysis) -> Vec<(u16, u16)> {
+    let mut regions = Vec::new();
+    let mut start = None;
+    for addr in a.base..a.end() {
+        match (a.is_code(addr), start) {
+            (false, None) => start = Some(addr),
+            (true, Some(s)) => { regions.push((s, addr)); start = None; },
+            _ => ()
+        }
+    }
+    if let Some(s) = start { regions.push((s, a.end())); }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_sprite_warns_on_chip8() {
+        let rom = [0xD0, 0x10, 0x12, 0x02]; // DRW V0, V1, 0 then loop
+        let lints = lint(&rom, 0x200, Platform::Chip8);
+        assert!(lints.iter().all(|l| l.severity == Severity::Warning));
+        assert!(lints.iter().any(|l| l.addr == 0x200 && l.message.contains("draws nothing")));
+        assert!(lint(&rom, 0x200, Platform::SuperChip).iter().all(|l| l.addr != 0x200));
+    }
+
+    fn program(instrs: &[u16]) -> Vec<u8> {
+        instrs.iter().flat_map(|i| i.to_be_bytes()).collect()
+    }
+
+    // Returns the messages of the lints at an address
+    fn at(lints: &[Lint], addr: u16) -> Vec<&str> {
+        lints.iter().filter(|l| l.addr == addr).map(|l| l.message.as_str()).collect()
+    }
+
+    #[test]
+    fn skipping_long_loads() {
+        // SE V0, 0 over LD I, 0x0300 (4 bytes)
+        let rom = program(&[0x3000, 0xF000, 0x0300, 0x1206]);
+        let lints = lint(&rom, 0x200, Platform::XoChip);
+        assert!(lints.is_empty(), "{:?}", lints);
+        let lints = lint(&rom, 0x200, Platform::SuperChip);
+        assert_eq!(at(&lints, 0x200), ["SKEQ V0, 00 skips over a 4 byte instruction, only XO-CHIP handles it"]);
+    }
+
+    #[test]
+    fn unreachable_code() {
+        let rom = program(&[0x1200, 0x6001, 0x7001, 0x00E0]);
+        let lints = lint(&rom, 0x200, Platform::Chip8);
+        assert_eq!(at(&lints, 0x202), ["unreachable code up to 0x207 (3 instructions)"]);
+
+        // Unless I points there
+        let rom = program(&[0xA204, 0x1202, 0x6001, 0x7001]);
+        assert!(lint(&rom, 0x200, Platform::Chip8).is_empty());
+    }
+
+    #[test]
+    fn jump_into_an_instruction() {
+        let rom = program(&[0xF000, 0x0300, 0x1202]);
+        let lints = lint(&rom, 0x200, Platform::XoChip);
+        assert_eq!(at(&lints, 0x204), ["jumps into the middle of the instruction at 0x200"]);
+        assert_eq!(lints[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn calls_and_returns() {
+        // The subroutine at 0x204 loops forever
+        let rom = program(&[0x2204, 0x1202, 0x1204]);
+        let lints = lint(&rom, 0x200, Platform::Chip8);
+        assert_eq!(at(&lints, 0x204), ["subroutine never returns"]);
+
+        let rom = program(&[0x2204, 0x00EE, 0x00EE]);
+        let lints = lint(&rom, 0x200, Platform::Chip8);
+        assert_eq!(at(&lints, 0x202), ["return outside of any subroutine"]);
+        assert!(at(&lints, 0x204).is_empty());
+    }
+
+    #[test]
+    fn sprite_past_the_end() {
+        let mut rom = program(&[0xA206, 0xD015, 0x1204]);
+        rom.push(0xFF);
+        let lints = lint(&rom, 0x200, Platform::Chip8);
+        assert_eq!(at(&lints, 0x202), ["sprite at 0x206 reads 4 bytes past the end of the rom"]);
+
+        // I isn't known when another path joins in
+        let mut rom = program(&[0xA206, 0xD015, 0x1202]);
+        rom.push(0xFF);
+        assert!(at(&lint(&rom, 0x200, Platform::Chip8), 0x202).is_empty());
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::BTreeMap;
+
+use crate::emulator::MEMORY_SIZE;
+
+/// Where programs are loaded and start unless told otherwise
+pub const DEFAULT_ADDRESS: usize = 0x200;
+
+/// Ways a rom can be stored on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Raw, // Plain binary
+    HexDump, // Output of `xxd` or `od -A x -x` / `od -A x -t x1`
+    IntelHex,
+    HexText, // Hex digits only, like `xxd -p`
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [Format::Raw, Format::HexDump, Format::IntelHex, Format::HexText];
+
+    /// Returns the name used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Raw => "raw",
+            Format::HexDump => "dump",
+            Format::IntelHex => "ihex",
+            Format::HexText => "hex",
+        }
+    }
+
+    /// Returns the format with a given name
+    pub fn from_name(name: &str) -> Option<Format> {
+        Format::ALL.into_iter().find(|f| f.name() == name)
+    }
+
+    /// Guesses the format of a file from its contents
+    pub fn detect(bytes: &[u8]) -> Format {
+        let text = match std::str::from_utf8(bytes) {
+            Ok(t) if t.chars().all(|c| c.is_ascii_graphic() || c.is_ascii_whitespace()) => t,
+            _ => return Format::Raw
+        };
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty()).peekable();
+        let first = match lines.peek() {
+            Some(l) => *l,
+            None => return Format::Raw
+        };
+        if lines.all(|l| l.starts_with(':')) {
+            return Format::IntelHex;
+        }
+        // Dumps start lines with an offset, either followed by a colon (xxd) or at least 6 digits long (od, hexdump)
+        let offset = first.split_whitespace().next().unwrap_or("");
+        let digits = offset.trim_end_matches(':');
+        if digits.chars().all(|c| c.is_ascii_hexdigit()) && (offset.ends_with(':') || digits.len() >= 6) && first.contains(' ') {
+            return Format::HexDump;
+        }
+        // Hex text is split into tokens, a raw rom can happen to be made of hex digits only
+        let separated = text.contains(|c: char| c.is_ascii_whitespace() || c == ',');
+        if separated && parse_hex_text(text).is_ok() {
+            return Format::HexText;
+        }
+        Format::Raw
+    }
+}
+
+/// A program ready to be put in memory
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub data: Vec<u8>,
+    pub address: usize, // Load address, also the entry point
+}
+
+/// Reads roms in any of the supported formats
+#[derive(Debug, Clone, Default)]
+pub struct Loader {
+    format: Option<Format>, // Detected when None
+    address: Option<usize>, // Taken from the file (Intel HEX) or DEFAULT_ADDRESS when None
+}
+
+impl Loader {
+    /// Returns a loader detecting the format
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forces the format instead of detecting it
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Sets the load and entry address (e.g. 0x600 for ETI-660 programs)
+    pub fn with_address(mut self, address: usize) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Decodes a file into a rom, checking it fits in memory
+    pub fn load(&self, bytes: &[u8]) -> Result<Rom, String> {
+        let format = self.format.unwrap_or_else(|| Format::detect(bytes));
+        let text = || std::str::from_utf8(bytes).map_err(|_| format!("A {} rom must be text", format.name()));
+        let (data, origin) = match format {
+            Format::Raw => (bytes.to_vec(), None),
+            Format::HexDump => (parse_dump(text()?)?, None),
+            Format::IntelHex => {
+                let (data, origin) = parse_intel_hex(text()?)?;
+                (data, Some(origin))
+            },
+            Format::HexText => (parse_hex_text(text()?)?, None),
+        };
+        // Intel HEX addresses are absolute unless they start below the program area,
+        // in which case they are offsets from the load address
+        let address = match (self.address, origin) {
+            (Some(address), Some(origin)) if origin < DEFAULT_ADDRESS => address + origin,
+            (Some(address), _) => address,
+            (None, Some(origin)) if origin < DEFAULT_ADDRESS => DEFAULT_ADDRESS + origin,
+            (None, Some(origin)) => origin,
+            (None, None) => DEFAULT_ADDRESS,
+        };
+        check_fits(&data, address)?;
+        Ok(Rom { data, address })
+    }
+}
+
+/// Checks a program can be loaded at an address without being cut
+pub fn check_fits(data: &[u8], address: usize) -> Result<(), String> {
+    if data.is_empty() {
+        return Err("Rom is empty".to_string());
+    }
+    if address >= MEMORY_SIZE || data.len() > MEMORY_SIZE - address {
+        return Err(format!("Rom is {} bytes but only {} fit in memory from 0x{:03X}",
+            data.len(), MEMORY_SIZE.saturating_sub(address), address));
+    }
+    Ok(())
+}
+
+// Reads a string of hex digits as bytes
+fn hex_bytes(digits: &str, line: usize) -> Result<Vec<u8>, String> {
+    if !digits.len().is_multiple_of(2) {
+        return Err(format!("Line {}: odd number of hex digits", line));
+    }
+    (0..digits.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("Line {}: invalid hex \"{}\"", line, &digits[i..i + 2])))
+        .collect()
+}
+
+// Reads whitespace or comma separated hex digits, with optional 0x prefixes
+fn parse_hex_text(text: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        for token in line.split(|c: char| c.is_ascii_whitespace() || c == ',').filter(|t| !t.is_empty()) {
+            data.extend(hex_bytes(token.trim_start_matches("0x"), n + 1)?);
+        }
+    }
+    Ok(data)
+}
+
+// Reads an xxd dump (big endian groups, ascii column) or an od dump (hex offsets,
+// little endian 16 bit words or single bytes, a last line holding the length)
+fn parse_dump(text: &str) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    let mut length = None;
+    for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let (offset, rest) = line.trim().split_once(|c: char| c.is_ascii_whitespace()).unwrap_or((line.trim(), ""));
+        let xxd = offset.ends_with(':');
+        let offset = usize::from_str_radix(offset.trim_end_matches(':'), 16).map_err(|_| format!("Line {}: invalid offset \"{}\"", n + 1, offset))?;
+        if offset != data.len() {
+            if rest.is_empty() { // od ends with the total length
+                length = Some(offset);
+                continue;
+            }
+            return Err(format!("Line {}: expected offset {:X} but found {:X}", n + 1, data.len(), offset));
+        }
+        if xxd {
+            let groups = rest.split("  ").next().unwrap(); // Drop the ascii column
+            for group in groups.split_whitespace() {
+                data.extend(hex_bytes(group, n + 1)?);
+            }
+        } else {
+            for group in rest.split_whitespace() {
+                let bytes = hex_bytes(group, n + 1)?;
+                match bytes.len() {
+                    1 => data.extend(bytes),
+                    2 => data.extend([bytes[1], bytes[0]]), // Words are printed little endian
+                    _ => return Err(format!("Line {}: unexpected group \"{}\"", n + 1, group)),
+                }
+            }
+        }
+    }
+    if let Some(length) = length {
+        if length > data.len() {
+            return Err(format!("Dump claims {} bytes but holds {}", length, data.len()));
+        }
+        data.truncate(length); // od pads odd sized files
+    }
+    Ok(data)
+}
+
+// Reads Intel HEX records, returning the data from the lowest address along with that address
+fn parse_intel_hex(text: &str) -> Result<(Vec<u8>, usize), String> {
+    let mut chunks = BTreeMap::<usize, Vec<u8>>::new();
+    let mut base = 0; // Set by extended address records
+    for (n, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let record = line.trim().strip_prefix(':').ok_or(format!("Line {}: records start with ':'", n + 1))?;
+        let bytes = hex_bytes(record, n + 1)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(format!("Line {}: wrong record length", n + 1));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(format!("Line {}: wrong checksum", n + 1));
+        }
+        let address = (bytes[1] as usize) << 8 | bytes[2] as usize;
+        let payload = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0x00 => { chunks.insert(base + address, payload.to_vec()); },
+            0x01 => break,
+            0x02 if payload.len() == 2 => base = ((payload[0] as usize) << 8 | payload[1] as usize) << 4,
+            0x04 if payload.len() == 2 => base = ((payload[0] as usize) << 8 | payload[1] as usize) << 16,
+            0x03 | 0x05 => (), // Start addresses, the load address is the entry point
+            t => return Err(format!("Line {}: unsupported record type {:02X}", n + 1, t)),
+        }
+    }
+    let origin = match chunks.keys().next() {
+        Some(origin) => *origin,
+        None => return Err("No data records".to_string())
+    };
+    let mut data = Vec::new();
+    for (address, chunk) in chunks {
+        let start = address - origin;
+        if start + chunk.len() > MEMORY_SIZE {
+            return Err(format!("Data at 0x{:X} doesn't fit in memory", address));
+        }
+        if data.len() < start + chunk.len() {
+            data.resize(start + chunk.len(), 0); // Gaps are zeroed
+        }
+        data[start..start + chunk.len()].copy_from_slice(&chunk);
+    }
+    Ok((data, origin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IBM_LOGO: &[u8] = include_bytes!("../../roms/ibmlogo.ch8");
+
+    #[test]
+    fn raw() {
+        let rom = Loader::new().load(IBM_LOGO).unwrap();
+        assert_eq!((rom.data.as_slice(), rom.address), (IBM_LOGO, DEFAULT_ADDRESS));
+        // Hex digits alone aren't hex text
+        assert_eq!(Format::detect(b"ABCD"), Format::Raw);
+        assert_eq!(Loader::new().load(b"ABCD").unwrap().data, b"ABCD");
+        assert_eq!(Format::detect(b"ABC D"), Format::Raw); // Odd digits in a token
+    }
+
+    #[test]
+    fn hex_text() {
+        assert_eq!(Format::detect(b"00e0 1200\n"), Format::HexText);
+        assert_eq!(Loader::new().load(b"00e0 1200\n").unwrap().data, [0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(Loader::new().load(b"0x00, 0xE0,\n0x12, 0x00").unwrap().data, [0x00, 0xE0, 0x12, 0x00]);
+        assert!(Loader::new().with_format(Format::HexText).load(b"00e 0").is_err());
+    }
+
+    #[test]
+    fn xxd_dump() {
+        let dump = "00000000: 00e0 a22a 600c 6108 d01f 7009 a239 d01f  ...*`.a...p..9..\n\
+                    00000010: a248 7008                                .Hp.\n";
+        assert_eq!(Format::detect(dump.as_bytes()), Format::HexDump);
+        assert_eq!(Loader::new().load(dump.as_bytes()).unwrap().data, &IBM_LOGO[..20]);
+    }
+
+    #[test]
+    fn od_dumps() {
+        let bytes = "000000 00 e0 a2 2a 60 0c 61 08 d0 1f 70 09 a2 39 d0 1f\n000010 a2 48 70 08\n000014\n";
+        assert_eq!(Format::detect(bytes.as_bytes()), Format::HexDump);
+        assert_eq!(Loader::new().load(bytes.as_bytes()).unwrap().data, &IBM_LOGO[..20]);
+
+        // Little endian words, padded to an even length
+        let words = "000000 e000 2aa2 0c60 0861 1fd0 0970 39a2 1fd0\n000010 48a2 0070\n000013\n";
+        assert_eq!(Loader::new().load(words.as_bytes()).unwrap().data, &IBM_LOGO[..19]);
+
+        let gap = "000000 e000\n000010 48a2\n";
+        assert!(Loader::new().load(gap.as_bytes()).unwrap_err().contains("expected offset 2"));
+    }
+
+    #[test]
+    fn hexdump_of_ibm_logo() {
+        let dump = include_bytes!("../../roms/bytes");
+        assert_eq!(Format::detect(dump), Format::HexDump);
+        assert_eq!(Loader::new().load(dump).unwrap().data, IBM_LOGO);
+    }
+
+    #[test]
+    fn intel_hex() {
+        // Two records with a gap between them, at an absolute address
+        let hex = ":0402000000E0120008\n:01020800AA4B\n:00000001FF\n";
+        assert_eq!(Format::detect(hex.as_bytes()), Format::IntelHex);
+        let rom = Loader::new().load(hex.as_bytes()).unwrap();
+        assert_eq!(rom.address, 0x200);
+        assert_eq!(rom.data, [0x00, 0xE0, 0x12, 0x00, 0, 0, 0, 0, 0xAA]);
+
+        // Addresses below the program area are offsets from the load address
+        let relative = ":0200000060019D\n:00000001FF\n";
+        assert_eq!(Loader::new().load(relative.as_bytes()).unwrap().address, 0x200);
+        assert_eq!(Loader::new().with_address(0x600).load(relative.as_bytes()).unwrap().address, 0x600);
+    }
+
+    #[test]
+    fn intel_hex_errors() {
+        let loader = Loader::new().with_format(Format::IntelHex);
+        assert_eq!(loader.load(b":0402000000E0120009\n").unwrap_err(), "Line 1: wrong checksum");
+        assert_eq!(loader.load(b":0502000000E0120008\n").unwrap_err(), "Line 1: wrong record length");
+        assert_eq!(loader.load(b"0402000000E0120008\n").unwrap_err(), "Line 1: records start with ':'");
+        assert_eq!(loader.load(b":00000001FF\n").unwrap_err(), "No data records");
+        // Extended addresses past the end of memory
+        assert!(loader.load(b":020000040001F9\n:0200000060019D\n").is_err());
+    }
+
+    #[test]
+    fn fits_in_memory() {
+        assert!(check_fits(&[0; 0xE00], 0x200).is_ok());
+        assert!(check_fits(&[0; 1], 0xFFF).is_ok());
+        assert_eq!(check_fits(&[0; 0xE01], 0x200).unwrap_err(), "Rom is 3585 bytes but only 3584 fit in memory from 0x200");
+        assert!(check_fits(&[0; 1], 0x1000).is_err());
+        assert_eq!(check_fits(&[], 0x200).unwrap_err(), "Rom is empty");
+    }
+}
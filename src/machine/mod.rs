@@ -0,0 +1,86 @@
+use crate::emulator::{Bus, Chip8, ExecError, Framebuffer, Observer, VReg};
+
+/// What a frontend needs from an emulated machine: a 64x32 display, the hex keypad and a buzzer
+pub trait Machine {
+    /// Runs the machine for one 60 Hz frame
+    fn run_frame(&mut self) -> Result<(), String>;
+
+    /// Returns the display
+    fn framebuffer(&self) -> &Framebuffer;
+
+    /// Marks the display as drawn, until it changes again
+    fn clear_dirty(&mut self);
+
+    /// Presses or releases a key of the hex keypad, fails past key F
+    fn update_key(&mut self, key: u8, val: bool) -> Result<(), ExecError>;
+
+    /// Returns true while the buzzer sounds
+    fn is_beeping(&self) -> bool;
+}
+
+impl<O: Observer, B: Bus> Machine for Chip8<O, B> {
+    /// Runs instructions until the clock ends the frame, then decrements the timers
+    fn run_frame(&mut self) -> Result<(), String> {
+        loop {
+            let pc = self.get_pc();
+            let instr = self.fetch().map_err(|e| format!("Couldn't fetch an instruction at 0x{:04X}: {}", pc, e))?;
+            let vx = self.get_reg(VReg::from_nibble((instr >> 8) as u8));
+            self.exec(instr).map_err(|e| format!("Failed to execute 0x{:04X} at 0x{:04X}: {}", instr, pc, e))?;
+            let skipped = self.skipped_from(pc);
+            if self.frame_clock_mut().advance(instr, vx, skipped).1 { break; }
+        }
+        self.decr_timers();
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> &Framebuffer {
+        Chip8::framebuffer(self)
+    }
+
+    fn clear_dirty(&mut self) {
+        self.take_dirty();
+    }
+
+    fn update_key(&mut self, key: u8, val: bool) -> Result<(), ExecError> {
+        Chip8::update_key(self, key, val).map_err(|_| ExecError::InvalidKey(key))
+    }
+
+    fn is_beeping(&self) -> bool {
+        self.get_timers().1 > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{AddressPolicy, Timing};
+
+    #[test]
+    fn fetch_past_the_end_is_reported() {
+        let mut emu = Chip8::new().set_address_policy(AddressPolicy::Fault)
+            .load_program(vec![0x1F, 0xFF]).unwrap(); // JP 0xFFF
+        assert_eq!(emu.run_frame(), Err("Couldn't fetch an instruction at 0x0FFF: address 0x1000 is past the end of memory".to_string()));
+    }
+
+    #[test]
+    fn vip_cycles_carried_between_frames() {
+        // LD V1, 1 then ADD I, V1 in a loop: frames don't end on the same instruction each time
+        let program = [0x6101, 0xF11E, 0x1202];
+        let mut emu = Chip8::new().set_timing(Timing::Vip)
+            .load_program(program.iter().flat_map(|i: &u16| i.to_be_bytes()).collect()).unwrap();
+        let start = emu.get_i();
+        for _ in 0..10 {
+            emu.run_frame().unwrap();
+        }
+
+        // The same instructions through a single clock
+        let mut clock = emu.clock();
+        let (mut frames, mut adds, mut pc) = (0, 0, 0);
+        while frames < 10 {
+            if program[pc] == 0xF11E { adds += 1; }
+            if clock.advance(program[pc], 0, false).1 { frames += 1; }
+            pc = if pc == 2 { 1 } else { pc + 1 };
+        }
+        assert_eq!(emu.get_i() - start, adds);
+    }
+}
@@ -12,39 +12,892 @@ use std::thread;
 use std::time::Duration;
 
 use std::path::Path;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use tracer::{TraceFilter, Tracer};
+use profiler::Profiler;
+use headless::{Input, Runner};
+use coverage::{Access, Coverage};
+use sanitizer::{SanitizeMode, Sanitizer};
+use platform::Platform;
+use linter::Severity;
+use detect::detect;
+use emulator::{AddressPolicy, Clock, ExecError, Framebuffer, Layout, MappedBus, NoObserver, Quirks, SeededRandom, VipRandom, StackDepth, StackPolicy, Timing, VReg, MEMORY_SIZE};
+use database::{Database, RomInfo};
+use config::{Config, Settings};
+use analysis::Analysis;
+use loader::{Format, Loader, Rom};
+use converter::Target;
+use machine::Machine;
+use vip::Vip;
+use worker::Emulation;
+use std::path::PathBuf;
 
 use clap;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 /// CHIP-8 Emulator running with SDL2
 #[derive(Parser, Debug)]
 struct Args {
-    /// Path to the target rom
-    #[clap(short, long)]
-    rom: String,
+    /// Path to the target rom, shorthand for the run subcommand
+    #[clap(short, long, hide = true)]
+    rom: Option<String>,
+
+    #[clap(flatten)]
+    global: Global,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-#[allow(non_snake_case)]
+/// Options shared by every subcommand
+#[derive(clap::Args, Debug, Clone)]
+struct Global {
+    /// Platform the rom targets (chip8, schip, xochip), detected from the rom by default
+    #[clap(short, long, global = true)]
+    platform: Option<String>,
 
-fn main() -> Result<(), ()> {
-    let args = Args::parse();
-    
+    /// Comma separated quirks to turn on (shift, loadstore, jump, vfreset, wrap) or "none", replaces the platform's
+    #[clap(short, long, global = true)]
+    quirks: Option<String>,
+
+    /// Instructions executed per second, 700 unless the rom is known
+    #[clap(short, long, global = true)]
+    speed: Option<u32>,
+
+    /// Pixel colour (hex RRGGBB)
+    #[clap(long, global = true, value_name = "RRGGBB")]
+    foreground: Option<String>,
+
+    /// Background colour (hex RRGGBB)
+    #[clap(long, global = true, value_name = "RRGGBB")]
+    background: Option<String>,
+
+    /// Window pixels per CHIP-8 pixel, 10 by default
+    #[clap(long, global = true)]
+    pixel_size: Option<u32>,
+
+    /// Configuration file, defaults to config.toml in the configuration directory
+    #[clap(long, global = true)]
+    config: Option<String>,
+
+    /// Rom database (chip-8-database programs.json format) overriding the bundled one,
+    /// defaults to database.json in the configuration directory
+    #[clap(long, global = true)]
+    database: Option<String>,
+
+    /// Format of the rom file, detected from its contents by default
+    #[clap(long, global = true, possible_values = ["raw", "dump", "ihex", "hex"])]
+    format: Option<String>,
+
+    /// What happens when the program counter or I goes past the end of memory
+    #[clap(long, global = true, possible_values = AddressPolicy::NAMES, default_value = "fault")]
+    memory: String,
+
+    /// Where the stack and display live: apart from memory, or in RAM at 0xEA0 and 0xF00 like on the COSMAC VIP
+    #[clap(long, global = true, possible_values = Layout::NAMES, default_value = "separate")]
+    layout: String,
+
+    /// How long instructions take: 1 / speed seconds each, or the machine cycles the COSMAC VIP interpreter spends on them
+    /// (speed is then ignored)
+    #[clap(long, global = true, possible_values = Timing::NAMES, default_value = "fixed")]
+    timing: String,
+
+    /// Seed of the random numbers, so that runs can be reproduced
+    #[clap(long, global = true)]
+    seed: Option<u64>,
+
+    /// Random numbers from a seeded generator, or from the COSMAC VIP interpreter's routine
+    /// (which reads the interpreter image given by --interpreter)
+    #[clap(long, global = true, possible_values = ["seeded", "vip"], default_value = "seeded")]
+    random: String,
+
+    /// CHIP-8 interpreter image of the COSMAC VIP, loaded at 0x0000 by --vip and read by --random vip
+    #[clap(long, global = true)]
+    interpreter: Option<String>,
+
+    /// Nested calls allowed (a number or "unlimited"), 12 for chip8 and 16 otherwise
+    #[clap(long, global = true, value_name = "DEPTH")]
+    stack_depth: Option<String>,
+
+    /// What happens on calls with a full stack or returns with an empty one
+    #[clap(long, global = true, possible_values = StackPolicy::NAMES, default_value = "fault")]
+    stack: String,
+
+    /// Make the interpreter and font area below 0x200 read-only, writes there fail
+    #[clap(long, global = true)]
+    protect: bool,
+
+    /// Hex address the rom is loaded at and started from (e.g. 600 for ETI-660 programs), 200 by default
+    #[clap(long, global = true)]
+    address: Option<String>,
+}
+
+impl Global {
+    // Settings given on the command line
+    fn settings(&self) -> Settings {
+        Settings {
+            pixel_size: self.pixel_size,
+            foreground: self.foreground.clone(),
+            background: self.background.clone(),
+            speed: self.speed,
+            platform: self.platform.clone(),
+            quirks: self.quirks.clone(),
+            ..Settings::default()
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a rom in a window, F5 resets and F6 pauses
+    Run {
+        /// Path to the target rom
+        rom: String,
+
+        /// Write an execution trace (JSON Lines) to a file, F9 toggles tracing at runtime
+        #[clap(long)]
+        trace: Option<String>,
+
+        /// Only trace instructions fetched within a hex address range (e.g. 200-2FF), can be repeated
+        #[clap(long, value_name = "START-END")]
+        trace_range: Vec<String>,
+
+        /// Only trace an opcode class (system, flow, skip, load, arith, logic, random, draw, key, timer, memory), can be repeated
+        #[clap(long, value_name = "CLASS")]
+        trace_class: Vec<String>,
+
+        /// Report undefined or suspicious ROM behaviour, then keep running (warn) or stop (halt)
+        #[clap(long, possible_values = ["warn", "halt"])]
+        sanitize: Option<String>,
+
+        /// Emulate the COSMAC VIP hardware instead, running the --interpreter image
+        #[clap(long)]
+        vip: bool,
+
+        /// Monitor ROM image the emulated COSMAC VIP boots (implies --vip)
+        #[clap(long)]
+        monitor: Option<String>,
+    },
+    /// Disassemble a rom
+    Disasm {
+        /// Path to the target rom
+        rom: String,
+
+        /// Only list instructions reachable from the entry point, skipping data
+        #[clap(short, long)]
+        code: bool,
+    },
+    /// Describe a rom: size, hashes, platform, database entry and what its code is made of
+    Info {
+        /// Path to the target rom
+        rom: String,
+
+        /// Print the report as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Run a rom headless and write an execution trace (JSON Lines)
+    Trace {
+        /// Path to the target rom
+        rom: String,
+
+        /// Trace file
+        #[clap(short, long, default_value = "trace.jsonl")]
+        output: String,
+
+        /// Number of 60Hz frames to run
+        #[clap(short, long, default_value = "600")]
+        frames: u64,
+
+        /// Input script to replay instead of sweeping through the keys
+        #[clap(short, long)]
+        input: Option<String>,
+
+        /// Only trace instructions fetched within a hex address range (e.g. 200-2FF), can be repeated
+        #[clap(long, value_name = "START-END")]
+        range: Vec<String>,
+
+        /// Only trace an opcode class (system, flow, skip, load, arith, logic, random, draw, key, timer, memory), can be repeated
+        #[clap(long, value_name = "CLASS")]
+        class: Vec<String>,
+    },
+    /// Run a rom headless and unthrottled, reporting emulation throughput
+    Bench {
+        /// Path to the target rom
+        rom: String,
+
+        /// Number of instructions to execute
+        #[clap(short, long, default_value = "10000000")]
+        instructions: u64,
+    },
+    /// Run a rom headless and report where cycles are spent
+    Profile {
+        /// Path to the target rom
+        rom: String,
+
+        /// Number of 60Hz frames to run
+        #[clap(short, long, default_value = "3600")]
+        frames: u64,
+
+        /// Number of rows shown per table
+        #[clap(short, long, default_value = "20")]
+        top: usize,
+
+        /// Write folded call stacks (for flamegraph tools) to a file
+        #[clap(long)]
+        folded: Option<String>,
+    },
+    /// Run a rom headless and report which bytes get executed, read or written
+    Coverage {
+        /// Path to the target rom
+        rom: String,
+
+        /// Number of 60Hz frames to run
+        #[clap(short, long, default_value = "3600")]
+        frames: u64,
+
+        /// Input script to replay instead of sweeping through the keys
+        #[clap(short, long)]
+        input: Option<String>,
+
+        /// Print an annotated listing of the rom
+        #[clap(short, long)]
+        listing: bool,
+
+        /// Print a heatmap of the whole memory
+        #[clap(long)]
+        heatmap: bool,
+    },
+    /// Check a rom for mistakes without running it, against the platform it targets
+    Lint {
+        /// Path to the target rom
+        rom: String,
+    },
+    /// Convert a rom to Intel HEX, a hex dump, a Rust or C byte array, or an Octo cartridge
+    Convert {
+        /// Path to the source rom
+        rom: String,
+
+        /// Output file, standard output by default
+        #[clap(short, long)]
+        output: Option<String>,
+
+        /// Output format, guessed from the output file extension by default
+        #[clap(short, long, possible_values = ["ihex", "dump", "rust", "c", "octo"])]
+        to: Option<String>,
+
+        /// Name of the array in source files, the rom file name by default
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the effective settings as TOML, for a rom if given
+    Dump {
+        /// Path to a rom
+        rom: Option<String>,
+    },
+}
+
+// Builds the rom loader from the command line
+fn rom_loader(global: &Global) -> Result<Loader, ()> {
+    let mut loader = Loader::new();
+    if let Some(format) = global.format.as_deref().and_then(Format::from_name) {
+        loader = loader.with_format(format);
+    }
+    if let Some(address) = &global.address {
+        let address = usize::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| println!("Invalid load address \"{}\" !", address))?;
+        loader = loader.with_address(address);
+    }
+    Ok(loader)
+}
+
+// Reads a rom file
+fn read_rom(path: &str, loader: &Loader) -> Result<Rom, ()> {
+    read_program(path, loader).map(|(rom, _)| rom)
+}
+
+// Reads a rom file or an Octo cartridge, along with the options it embeds
+fn read_program(path: &str, loader: &Loader) -> Result<(Rom, Option<cartridge::Options>), ()> {
     // Check if file exists
-    if !Path::new(&args.rom).is_file() {
+    if !Path::new(path).is_file() {
         println!("Provided path is not a file !");
         return Err(())
     }
-     
-    // Read instructions from rom
-    let bytes = fs::read(args.rom).unwrap();
+    let bytes = fs::read(path).map_err(|e| println!("Couldn't read rom: {}", e))?;
+    if !cartridge::is_cartridge(&bytes) {
+        let rom = loader.load(&bytes).map_err(|e| println!("Couldn't load rom: {}", e))?;
+        return Ok((rom, None));
+    }
+    let cartridge = cartridge::parse(&bytes).map_err(|e| println!("Couldn't load cartridge: {}", e))?;
+    let rom = loader.clone().with_format(Format::Raw).load(&cartridge.program).map_err(|e| println!("Couldn't load cartridge: {}", e))?;
+    Ok((rom, Some(cartridge.options)))
+}
+
+// Settings a rom runs with, by priority: the command line, the rom's section of the configuration,
+// the cartridge, the rom database, then the configuration defaults
+fn effective_settings(path: &str, rom: &Rom, options: Option<&cartridge::Options>, info: Option<&RomInfo>, global: &Global) -> Result<Settings, ()> {
+    let config = load_config(global.config.as_deref())?;
+    let mut known = Settings::default();
+    let quirks_list = |quirks: Quirks| {
+        let names = quirks.enabled();
+        if names.is_empty() { "none".to_string() } else { names.join(",") }
+    };
+    let hex = |c: [u8; 3]| format!("{:02X}{:02X}{:02X}", c[0], c[1], c[2]);
+    if let Some(info) = info {
+        known.platform = info.platform.map(|p| p.name().to_string());
+        known.speed = info.tickrate.map(|t| t * 60);
+        known.quirks = info.quirks.map(quirks_list);
+        if let Some((bg, fg)) = info.palette {
+            known.background = Some(hex(bg));
+            known.foreground = Some(hex(fg));
+        }
+    }
+    if let Some(options) = options {
+        let base = info.and_then(|i| i.quirks).unwrap_or_default();
+        known.speed = options.tickrate.map(|t| t * 60).or(known.speed);
+        known.quirks = Some(quirks_list(options.quirks(base)));
+        if let Some((bg, fg)) = options.palette() {
+            known.background = Some(hex(bg));
+            known.foreground = Some(hex(fg));
+        }
+    }
+    let file_name = Path::new(path).file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    Ok(config.defaults.clone()
+        .merge(&known)
+        .merge(&config.overrides_for(&database::sha1_hex(&rom.data), &file_name))
+        .merge(&global.settings()))
+}
+
+// Loads a rom along with the settings it runs with and prepares the window to show it; verbose prints what was found
+fn prepare(path: &str, global: &Global, verbose: bool) -> Result<(Rom, Settings, Window), ()> {
+    let (rom, options) = read_program(path, &rom_loader(global)?)?;
+    let info = load_database(global.database.as_deref())?.lookup(&rom.data);
+    let settings = effective_settings(path, &rom, options.as_ref(), info.as_ref(), global)?;
+
+    let mut window = Window::default();
+    if let Some(info) = &info {
+        if verbose {
+            println!("{}{}", info.title, if info.authors.is_empty() { String::new() } else { format!(" by {}", info.authors.join(", ")) });
+        }
+        window.title = info.title.clone();
+        window.keymap = Keymap::default().bind_actions(&info.keys);
+    }
+    if let Some(options) = &options {
+        window.keymap = window.keymap.bind_actions(&options.keys);
+    }
+    for (key, ch8_key) in &settings.keys {
+        let keycode = Keycode::from_name(key).ok_or_else(|| println!("Unknown keyboard key \"{}\" !", key))?;
+        let ch8_key = u8::from_str_radix(ch8_key, 16).ok().filter(|k| *k < 16).ok_or_else(|| println!("Invalid CHIP-8 key \"{}\" ! (0-F)", ch8_key))?;
+        window.keymap = window.keymap.rebind(keycode, ch8_key);
+    }
+    if let Some(size) = settings.pixel_size { window.pixel_size = size; }
+    if let Some(color) = &settings.foreground { window.foreground = parse_color(color)?; }
+    if let Some(color) = &settings.background { window.background = parse_color(color)?; }
+    Ok((rom, settings, window))
+}
+
+// Loads a rom and prepares the emulator and window to run it; verbose prints what was found
+fn setup(path: &str, global: &Global, verbose: bool) -> Result<(Chip8<NoObserver, MappedBus>, Window, Rom), ()> {
+    let (rom, settings, window) = prepare(path, global, verbose)?;
+    let mut emu = Chip8::new().load_font(get_default_font());
+    if let Some(speed) = settings.speed { emu = emu.set_freq(speed); }
+    let quirks = select_quirks(&rom, settings.platform.as_deref(), settings.quirks.as_deref(), verbose)?;
+    let policy = AddressPolicy::from_name(&global.memory).unwrap(); // Checked by clap
+    let mut bus = MappedBus::new();
+    if global.protect {
+        bus = bus.read_only(0x000..rom.address.min(0x200) as u16); // Interpreter and font
+    }
+    let layout = Layout::from_name(&global.layout).unwrap(); // Checked by clap
+    let depth = match (&global.stack_depth, &settings.platform) {
+        (Some(depth), _) => StackDepth::from_name(depth).ok_or_else(|| println!("Invalid stack depth \"{}\" ! (a number or unlimited)", depth))?,
+        (None, Some(name)) => parse_platform(name)?.stack_depth(),
+        (None, None) => StackDepth::default(),
+    };
+    let stack_policy = StackPolicy::from_name(&global.stack).unwrap(); // Checked by clap
+    let timing = Timing::from_name(&global.timing).unwrap(); // Checked by clap
+    let mut emu = emu.set_timing(timing).set_quirks(quirks).set_address_policy(policy).set_layout(layout).set_stack(depth, stack_policy).with_bus(bus)
+        .load_program_at(rom.data.clone(), rom.address).map_err(|e| println!("Couldn't load rom: {}", e))?;
+    let seed = global.seed.unwrap_or_else(|| {
+        let seed = rand::random();
+        println!("Random seed: {} (--seed {} replays this run)", seed, seed);
+        seed
+    });
+    emu = match global.random.as_str() {
+        "vip" => {
+            // The routine picks bytes from the second page of the interpreter, 0x100 to 0x1FF
+            let path = global.interpreter.as_ref().ok_or_else(|| println!("--random vip needs the interpreter image (--interpreter) !"))?;
+            let image = fs::read(path).map_err(|e| println!("Couldn't read {}: {}", path, e))?;
+            let page = image.get(0x100..0x200).ok_or_else(|| println!("{} is too short for --random vip, the interpreter is 512 bytes !", path))?;
+            emu.set_random(VipRandom::new(page.try_into().unwrap(), seed as u8)) // 256 bytes
+        },
+        _ => emu.set_random(SeededRandom::new(seed)),
+    };
+    Ok((emu, window, rom))
+}
+
+// Loads the configuration file, which doesn't have to exist
+fn load_config(path: Option<&str>) -> Result<Config, ()> {
+    let path = match (path, Config::path()) {
+        (Some(p), _) => PathBuf::from(p),
+        (None, Some(p)) if p.is_file() => p,
+        (None, _) => return Ok(Config::default())
+    };
+    let text = fs::read_to_string(&path).map_err(|e| println!("Couldn't read configuration {}: {}", path.display(), e))?;
+    Config::from_toml(&text).map_err(|e| println!("Invalid configuration {}: {}", path.display(), e))
+}
+
+// Reads a hex RRGGBB colour
+fn parse_color(color: &str) -> Result<Color, ()> {
+    let hex = color.trim_start_matches('#');
+    match u32::from_str_radix(hex, 16) {
+        Ok(c) if hex.len() == 6 => Ok(Color::RGB((c >> 16) as u8, (c >> 8) as u8, c as u8)),
+        _ => {
+            println!("Invalid colour \"{}\" ! (RRGGBB)", color);
+            Err(())
+        }
+    }
+}
+
+// Reads an input script, or sweeps through the keys without one
+fn read_input(path: Option<String>) -> Result<Input, ()> {
+    match path {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| println!("Couldn't read input script: {}", e))?;
+            Input::parse_script(&text).map_err(|e| println!("Invalid input script: {}", e))
+        },
+        None => Ok(Input::default())
+    }
+}
+
+// Builds the trace filter from the command line
+fn trace_filter(ranges: &[String], classes: &[String]) -> Result<TraceFilter, ()> {
+    let mut filter = TraceFilter::new();
+    for range in ranges {
+        let bounds = range.split_once('-')
+            .and_then(|(s, e)| Some((u16::from_str_radix(s, 16).ok()?, u16::from_str_radix(e, 16).ok()?)));
+        match bounds {
+            Some((start, end)) => filter = filter.with_range(start, end),
+            None => {
+                println!("Invalid trace range \"{}\" !", range);
+                return Err(())
+            }
+        }
+    }
+    for class in classes {
+        match OpcodeClass::from_name(class) {
+            Some(c) => filter = filter.with_class(c),
+            None => {
+                println!("Unknown opcode class \"{}\" !", class);
+                return Err(())
+            }
+        }
+    }
+    Ok(filter)
+}
+
+// Reads a platform name
+fn parse_platform(name: &str) -> Result<Platform, ()> {
+    Platform::from_name(name).ok_or_else(|| println!("Unknown platform \"{}\" ! (chip8, schip, xochip)", name))
+}
+
+// Picks the quirks to run a rom with: the list if given, else the platform's, else detected ones
+fn select_quirks(rom: &Rom, platform: Option<&str>, quirks: Option<&str>, verbose: bool) -> Result<Quirks, ()> {
+    let mut selected = match (platform, quirks) {
+        (Some(name), _) => parse_platform(name)?.quirks(),
+        (None, Some(_)) => Quirks::default(),
+        (None, None) => {
+            let detection = detect(&rom.data, rom.address as u16);
+            if verbose { print!("{}", detection); }
+            detection.quirks
+        }
+    };
+    if let Some(list) = quirks {
+        selected = Quirks::default();
+        for name in list.split(',').filter(|n| !n.is_empty() && *n != "none") {
+            selected.set(name.trim(), true).map_err(|e| println!("{}", e))?;
+        }
+    }
+    Ok(selected)
+}
+
+// Loads the bundled rom database along with a local override file
+fn load_database(path: Option<&str>) -> Result<Database, ()> {
+    let default_path = dirs::config_dir().map(|d| d.join("chip8emu").join("database.json"));
+    let path = match (path, default_path) {
+        (Some(p), _) => PathBuf::from(p),
+        (None, Some(p)) if p.is_file() => p,
+        (None, _) => return Ok(Database::bundled())
+    };
+    let text = fs::read_to_string(&path).map_err(|e| println!("Couldn't read database {}: {}", path.display(), e))?;
+    let local = Database::from_json(&text).map_err(|e| println!("Invalid database {}: {}", path.display(), e))?;
+    Ok(Database::bundled().with_overrides(local))
+}
+
+// How the emulator is presented in the SDL window
+struct Window {
+    title: String,
+    pixel_size: u32,
+    foreground: Color,
+    background: Color,
+    keymap: Keymap,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self {
+            title: "CHIP-8".to_string(),
+            pixel_size: 10,
+            foreground: Color::RGB(0xAA, 0xB3, 0xB0), // Define black and white pixel color
+            background: Color::RGB(0x29, 0x2C, 0x35),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+// Opens a trace file
+fn open_trace(path: &str, filter: TraceFilter) -> Result<Tracer<BufWriter<File>>, ()> {
+    match File::create(path) {
+        Ok(f) => Ok(Tracer::new(BufWriter::new(f), filter)),
+        Err(e) => {
+            println!("Couldn't create trace file: {}", e);
+            Err(())
+        }
+    }
+}
+
+fn main() -> Result<(), ()> {
+    let args = Args::parse();
+    let global = &args.global;
+
+    let command = match (args.command, args.rom) {
+        (Some(command), _) => command,
+        (None, Some(rom)) => Command::Run { rom, trace: None, trace_range: vec![], trace_class: vec![], sanitize: None, vip: false, monitor: None },
+        (None, None) => {
+            println!("No rom provided ! (see --help for the subcommands)");
+            return Err(())
+        }
+    };
+
+    match command {
+        Command::Run { rom, trace, trace_range, trace_class, sanitize, vip, monitor } => {
+            if vip || monitor.is_some() {
+                if trace.is_some() || sanitize.is_some() {
+                    println!("Tracing and sanitizing aren't available on the COSMAC VIP hardware !");
+                    return Err(())
+                }
+                if monitor.is_none() && global.interpreter.is_none() {
+                    println!("The COSMAC VIP needs a monitor (--monitor) or a CHIP-8 interpreter image (--interpreter) to run !");
+                    return Err(())
+                }
+                let (rom, _, window) = prepare(&rom, global, true)?;
+                let read = |path: Option<String>| match path {
+                    Some(path) => fs::read(&path).map_err(|e| println!("Couldn't read {}: {}", path, e)),
+                    None => Ok(Vec::new())
+                };
+                let (monitor, interpreter) = (read(monitor)?, read(global.interpreter.clone())?);
+                let make = move || Vip::new(monitor.clone())
+                    .load(0x000, &interpreter)
+                    .and_then(|vip| vip.load(rom.address, &rom.data))
+                    .map_err(|e| format!("Couldn't load rom: {}", e));
+                run_window(Emulation::spawn(make), window, |_| None)?;
+                return Ok(());
+            }
+            let filter = trace_filter(&trace_range, &trace_class)?;
+            let tracer = match &trace {
+                Some(path) => Some(open_trace(path, filter.clone())?),
+                None => None
+            };
+            let (emu, window, loaded) = setup(&rom, global, true)?;
+            let sanitized = move |emu: Chip8<NoObserver, MappedBus>, loaded: Rom| {
+                let sanitizer = sanitize.as_ref().map(|mode| {
+                    let mode = if mode == "halt" { SanitizeMode::Halt } else { SanitizeMode::Warn };
+                    Sanitizer::new(mode, emu.get_i())
+                        .with_initialized(0x050, get_default_font().len())
+                        .with_initialized(loaded.address, loaded.data.len())
+                });
+                emu.with_observer(sanitizer)
+            };
+            let mut first = Some(Session::new(sanitized(emu, loaded), tracer, filter.clone()));
+            let global = global.clone();
+            // The first session was set up above, resets load the rom again without a trace
+            let make = move || match first.take() {
+                Some(session) => Ok(session),
+                None => {
+                    let (emu, _, loaded) = setup(&rom, &global, false).map_err(|_| "Couldn't reset".to_string())?;
+                    Ok(Session::new(sanitized(emu, loaded), None, filter.clone()))
+                }
+            };
+            let toggle_trace = |key| match key {
+                Keycode::F9 => Some(worker::Command::Apply(Box::new(|s: &mut Session| s.toggle_trace()))),
+                _ => None
+            };
+            if let Some(mut session) = run_window(Emulation::spawn(make), window, toggle_trace)? {
+                if let Some(t) = session.tracer.as_mut() { t.flush().map_err(|e| println!("Couldn't write trace: {}", e))?; }
+            }
+            Ok(())
+        },
+        Command::Disasm { rom, code } => {
+            let rom = read_rom(&rom, &rom_loader(global)?)?;
+            if code {
+                let analysis = Analysis::new(&rom.data, rom.address as u16);
+                for (addr, instr) in &analysis.instructions {
+                    println!("0x{:04X}\t0x{:04X} -> {}", addr, instr, disassemble(*instr));
+                }
+            } else {
+                println!("{}", disassemble_at(&pair_bytes(&rom.data), rom.address));
+            }
+            Ok(())
+        },
+        Command::Info { rom: path, json } => {
+            let rom = read_rom(&path, &rom_loader(global)?)?;
+            let info = load_database(global.database.as_deref())?.lookup(&rom.data);
+            let report = info::Report::new(&rom.data, rom.address as u16, info.as_ref());
+            if json { println!("{}", report.to_json()); } else { print!("{}", report); }
+            Ok(())
+        },
+        Command::Trace { rom, output, frames, input, range, class } => {
+            let mut tracer = open_trace(&output, trace_filter(&range, &class)?)?;
+            let (emu, _, _) = setup(&rom, global, false)?;
+            let mut runner = Runner::new(emu, read_input(input)?);
+            while runner.get_frame() < frames {
+                let emu = runner.get_emu();
+                let pc = emu.get_pc();
+                let instr = match (emu.peek(pc as usize), emu.peek(pc as usize + 1)) {
+                    (Some(b1), Some(b2)) => (b1 as u16) << 8 | b2 as u16,
+                    _ => {
+                        println!("Program counter past the end of memory");
+                        break
+                    }
+                };
+                tracer.begin(emu, runner.get_frame(), runner.get_cycle(), pc, instr);
+                if let Err(step) = runner.step() {
+                    println!("Stopped on {}", step);
+                    break;
+                }
+                tracer.end(runner.get_emu()).map_err(|e| println!("Couldn't write trace: {}", e))?;
+            }
+            tracer.flush().map_err(|e| println!("Couldn't write trace: {}", e))
+        },
+        Command::Bench { rom, instructions } => {
+            let (emu, _, _) = setup(&rom, global, false)?;
+            print!("{}", bench::run(emu, instructions));
+            Ok(())
+        },
+        Command::Profile { rom, frames, top, folded } => {
+            let (emu, _, _) = setup(&rom, global, false)?;
+            let mut profiler = Profiler::new(emu.get_pc());
+            let mut runner = Runner::new(emu, Input::default());
+            while runner.get_frame() < frames {
+                match runner.step() {
+                    Ok(step) => profiler.record(step.pc, step.instr),
+                    Err(step) => {
+                        println!("Stopped on {}", step);
+                        break;
+                    }
+                }
+            }
+            print!("{}", profiler.report(top));
+            if let Some(path) = folded {
+                let mut out = File::create(path).map(BufWriter::new).map_err(|e| println!("Couldn't create folded stacks file: {}", e))?;
+                profiler.write_folded(&mut out).map_err(|e| println!("Couldn't write folded stacks: {}", e))?;
+            }
+            Ok(())
+        },
+        Command::Coverage { rom, frames, input, listing, heatmap } => {
+            let (emu, _, rom) = setup(&rom, global, false)?;
+            let (rom_start, rom_end) = (rom.address, rom.address + rom.data.len());
+            let mut runner = Runner::new(emu.with_observer(Coverage::new()), read_input(input)?);
+            while runner.get_frame() < frames {
+                if let Err(step) = runner.step() {
+                    println!("Stopped on {}", step);
+                    break;
+                }
+            }
+            let coverage = runner.get_emu().observer();
+
+            let size = (rom_end - rom_start).max(1);
+            println!("Executed:\t{} / {} bytes ({:.1}%)", coverage.count(rom_start, rom_end, Access::Executed), size,
+                coverage.count(rom_start, rom_end, Access::Executed) as f64 / size as f64 * 100.0);
+            println!("Read:\t\t{} bytes", coverage.count(0, MEMORY_SIZE, Access::Read));
+            println!("Written:\t{} bytes", coverage.count(0, MEMORY_SIZE, Access::Written));
+            if listing {
+                println!();
+                print!("{}", coverage.listing(&runner.get_emu().get_memory(), rom_start, rom_end));
+            }
+            if heatmap {
+                println!();
+                print!("{}", coverage.heatmap());
+            }
+            Ok(())
+        },
+        Command::Lint { rom: path } => {
+            let (rom, options) = read_program(&path, &rom_loader(global)?)?;
+            let info = load_database(global.database.as_deref())?.lookup(&rom.data);
+            let settings = effective_settings(&path, &rom, options.as_ref(), info.as_ref(), global)?;
+            let platform = match &settings.platform {
+                Some(name) => parse_platform(name)?,
+                None => detect(&rom.data, rom.address as u16).platform
+            };
+            let lints = linter::lint(&rom.data, rom.address as u16, platform);
+            for l in &lints {
+                println!("{}", l);
+            }
+            let errors = lints.iter().filter(|l| l.severity == Severity::Error).count();
+            println!("{} error(s), {} warning(s)", errors, lints.len() - errors);
+            if errors > 0 { Err(()) } else { Ok(()) }
+        },
+        Command::Config { action: ConfigAction::Dump { rom } } => {
+            let settings = match rom {
+                Some(path) => {
+                    let (rom, options) = read_program(&path, &rom_loader(global)?)?;
+                    let info = load_database(global.database.as_deref())?.lookup(&rom.data);
+                    effective_settings(&path, &rom, options.as_ref(), info.as_ref(), global)?
+                },
+                None => load_config(global.config.as_deref())?.defaults.merge(&global.settings())
+            };
+            if let Some(path) = global.config.clone().map(PathBuf::from).or_else(Config::path) {
+                println!("# {}", path.display());
+            }
+            print!("{}", settings.to_toml());
+            Ok(())
+        },
+        Command::Convert { rom: path, output, to, name } => {
+            let target = match (to.as_deref(), output.as_deref()) {
+                (Some(to), _) => Target::from_name(to),
+                (None, Some(output)) => Path::new(output).extension().and_then(|e| Target::from_extension(&e.to_string_lossy())),
+                (None, None) => None
+            };
+            let target = target.ok_or_else(|| println!("Couldn't guess the output format, use --to !"))?;
+            if target == Target::Cartridge && output.is_none() {
+                println!("Cartridges are images, use --output !");
+                return Err(())
+            }
+            let (rom, options) = read_program(&path, &rom_loader(global)?)?;
+
+            // Cartridges carry how to run the rom, from the source cartridge or what we know about it
+            let database = load_database(global.database.as_deref())?;
+            let options = options.unwrap_or_else(|| {
+                let info = database.lookup(&rom.data);
+                let quirks = info.as_ref().and_then(|i| i.quirks).unwrap_or_else(|| detect(&rom.data, rom.address as u16).quirks);
+                let mut options = cartridge::Options::from_quirks(quirks);
+                if let Some(info) = info {
+                    options.tickrate = info.tickrate;
+                    options.keys = info.keys.into_iter().collect();
+                    if let Some((bg, fg)) = info.palette { options = options.with_palette(bg, fg); }
+                }
+                options
+            });
+            let name = name.unwrap_or_else(|| Path::new(&path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default());
+            let converted = converter::convert(&rom, target, &name, options).map_err(|e| println!("Couldn't convert rom: {}", e))?;
+            match output {
+                Some(output) => fs::write(&output, converted).map_err(|e| println!("Couldn't write {}: {}", output, e)),
+                None => std::io::stdout().write_all(&converted).map_err(|e| println!("Couldn't write output: {}", e))
+            }
+        },
+    }
+}
+
+// A CHIP-8 run in a window, with the sanitizer and tracer watching each instruction
+struct Session {
+    emu: Chip8<Option<Sanitizer>, MappedBus>,
+    tracer: Option<Tracer<BufWriter<File>>>,
+    filter: TraceFilter, // For traces started at runtime
+    clock: Clock, // Tells when to decrement timers
+    total_cycles: u64, // For the trace
+    frame: u64,
+}
+
+impl Session {
+    fn new(emu: Chip8<Option<Sanitizer>, MappedBus>, tracer: Option<Tracer<BufWriter<File>>>, filter: TraceFilter) -> Self {
+        Self { clock: emu.clock(), emu, tracer, filter, total_cycles: 0, frame: 0 }
+    }
 
-    let pixel_size = 10;
+    // Pauses or resumes tracing, starting a trace in trace.jsonl if there is none
+    fn toggle_trace(&mut self) {
+        match self.tracer.as_mut() {
+            Some(t) => { t.toggle(); },
+            None => match open_trace("trace.jsonl", self.filter.clone()) {
+                Ok(t) => self.tracer = Some(t),
+                Err(_) => return
+            }
+        }
+        println!("Tracing {}", if self.tracer.as_ref().unwrap().is_enabled() { "enabled" } else { "disabled" });
+    }
+}
+
+impl Machine for Session {
+    fn run_frame(&mut self) -> Result<(), String> {
+        loop {
+            // Fetch
+            let emu = &mut self.emu;
+            let pc = emu.get_pc();
+            let instr = emu.fetch().map_err(|e| format!("Couldn't fetch an instruction at 0x{:04X}: {} !", pc, e))?;
+            if let Some(t) = self.tracer.as_mut() { t.begin(emu, self.frame, self.total_cycles, pc, instr); }
 
-    let mut emu = Chip8::new().load_program(bytes); // Create emulator
+            // Execute
+            let vx = emu.get_reg(VReg::from_nibble((instr >> 8) as u8));
+            let result = emu.exec(instr);
+            if let Some(sanitizer) = emu.observer_mut() {
+                for issue in sanitizer.take_issues() {
+                    println!("{}", issue);
+                }
+                if sanitizer.is_halted() {
+                    return Err("Halted by the sanitizer".to_string());
+                }
+            }
+            result.map_err(|e| format!("Failed to execute instruction 0x{:04X}: {} !", instr, e))?;
+            if let Some(t) = self.tracer.as_mut() {
+                t.end(emu).map_err(|e| format!("Couldn't write trace: {}", e))?;
+            }
+
+            self.total_cycles += 1;
+            if self.clock.advance(instr, vx, emu.skipped_from(pc)).1 { // 60Hz Timers
+                emu.decr_timers();
+                self.frame += 1;
+                return Ok(());
+            }
+        }
+    }
+
+    fn framebuffer(&self) -> &Framebuffer {
+        self.emu.framebuffer()
+    }
+
+    fn clear_dirty(&mut self) {
+        self.emu.take_dirty();
+    }
+
+    fn update_key(&mut self, key: u8, val: bool) -> Result<(), ExecError> {
+        self.emu.update_key(key, val).map_err(|_| ExecError::InvalidKey(key))
+    }
+
+    fn is_beeping(&self) -> bool {
+        Machine::is_beeping(&self.emu)
+    }
+}
+
+// Shows an emulation running on its own thread in a window until it is closed, then returns the machine.
+// F5 resets, F6 pauses and resumes, `on_key` turns other keys into commands
+fn run_window<M, F>(emulation: Emulation<M>, settings: Window, on_key: F) -> Result<Option<M>, ()>
+where
+    M: Machine + Send + 'static,
+    F: Fn(Keycode) -> Option<worker::Command<M>>,
+{
+    let pixel_size = settings.pixel_size;
 
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem.window("CHIP-8", pixel_size*64, pixel_size*32)
+    let window = video_subsystem.window(&settings.title, pixel_size*64, pixel_size*32)
         .position_centered()
         .build()
         .unwrap();
@@ -53,65 +906,52 @@ fn main() -> Result<(), ()> {
  
     let mut canvas = window.into_canvas().build().unwrap(); // To draw onto
 
-    let WHITE = Color::RGB(0xAA, 0xB3, 0xB0); // Define black and white pixel color
-    let BLACK = Color::RGB(0x29, 0x2C, 0x35);
-
-    let mut cycles = 0; // Count number of cycles to decrement timers
-    
-    // Execute instructions
+    let mut paused = false;
     'main: loop {
-        // Fetch
-        let instr = emu.fetch();
-        println!("0x{:04X} -> {}", instr, disassemble(instr));
-        
-        // Update key states if key instruction
-        update_keys(&mut emu, &mut event_pump).expect("Failed to update key registers");
-        /* // Cause input lag
-        match Chip8::decode_to_nibbles(instr) {
-            (0xE, _, 0x9, 0xE) |
-            (0xE, _, 0xA, 0x1) |
-            (0xF, _, 0x0, 0xA) => {  }
-            (_, _, _, _) => ()
-        }
-        */
-        
-        // Execute
-        match emu.exec(instr) {
-            Err(_) => panic!("Failed to execute instruction 0x{:04X} !", instr),
-            _ => ()
-        }
-
-        // PLAY BEEPING SOUND IF SOUND TIMER IS ABOVE 0
-        
-        // Update display if instruction is clear screen or draw
-        match Chip8::decode_to_nibbles(instr) {
-            (0xD, _, _, _) | (0x0, 0x0, 0xE, 0x0) => {
-                display_chip8(emu.get_display(), &mut canvas, pixel_size, WHITE, BLACK).unwrap();
-                canvas.present();
-            },
-            (_, _, _, _) => ()
-        }
-
-        // Poll SDL Events
+        // Poll SDL Events and send key states
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..} | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'main
                 },
-                _ => {}
+                Event::KeyDown { keycode: Some(Keycode::F5), repeat: false, .. } => {
+                    paused = false;
+                    emulation.send(worker::Command::Reset);
+                },
+                Event::KeyDown { keycode: Some(Keycode::F6), repeat: false, .. } => {
+                    paused = !paused;
+                    emulation.send(if paused { worker::Command::Pause } else { worker::Command::Resume });
+                },
+                _ => {
+                    if let Event::KeyDown { keycode: Some(key), repeat: false, .. } = event {
+                        if let Some(command) = on_key(key) {
+                            emulation.send(command);
+                            continue;
+                        }
+                    }
+                    for (key, pressed) in settings.keymap.translate(&event) {
+                        emulation.send(worker::Command::Key(key, pressed));
+                    }
+                }
+            }
+        }
+
+        // Only draw the latest frame, presenting can't slow the emulation down
+        let mut latest = None;
+        for event in emulation.events() {
+            match event {
+                worker::Event::Frame { display, .. } => latest = display.or(latest), // PLAY BEEPING SOUND WHILE BEEPING
+                worker::Event::Stopped(message) => {
+                    println!("{}", message);
+                    paused = true;
+                }
             }
         }
-        cycles += 1;
-        if cycles >= emu.get_freq() / 60 { // 60Hz Timers
-            emu.decr_timers();
-            cycles = 0; // Reset cpu cycles
+        if let Some(display) = latest {
+            draw_framebuffer(&display, &mut canvas, pixel_size, settings.foreground, settings.background).unwrap();
+            canvas.present();
         }
-        thread::sleep(Duration::new(0, {
-            (1 as f64 / emu.get_freq() as f64 * 1_000_000_000f64).ceil() as u32
-            // Take 1 second, divide it by cpu frequency to get the time to wait in seconds,
-            // multiply by 10^9 to convert it to nanosecond, ceil it, and finally cast it to u32.
-            // (Casts may cause runtime errors if too big)
-        }));
+        thread::sleep(Duration::from_millis(1));
     }
-    Ok(())
+    Ok(emulation.quit())
 }
@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+
+/// Address Octo programs are assembled for
+const START: usize = 0x200;
+
+/// Macro expansions before giving up on a macro that keeps invoking itself
+const MAX_EXPANSIONS: usize = 0x10000;
+
+/// Assembles Octo source into a program loaded at 0x200.
+/// Labels, constants, aliases, the CHIP-8, SUPER-CHIP and XO-CHIP statements and the
+/// control structures (if / begin / else / end, loop / while / again) are understood.
+/// Macros are expanded where a statement starts. `:calc` expressions have no operator
+/// precedence and group from the right, their results are rounded down to integers.
+/// `:stringmode`, `:string` and `:assert` are not supported, such programs are rejected
+pub fn assemble(source: &str) -> Result<Vec<u8>, String> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap(); // Strip comments
+        tokens.extend(line.split_whitespace().map(|t| (t, n + 1)));
+    }
+    let mut assembler = Assembler {
+        tokens,
+        pos: 0,
+        rom: vec![0, 0], // Jump to main, dropped if main comes first
+        here: 2,
+        main_jump: true,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+    };
+    while assembler.pos < assembler.tokens.len() {
+        assembler.statement()?;
+    }
+    assembler.finish()
+}
+
+// Where an address is written once its label is defined
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+    Short(usize), // NNN of the instruction at an offset
+    Long(usize), // 16 bits at an offset
+    Unpack(usize, u8), // v0 := nibble and high bits, v1 := low byte
+}
+
+// Open control structure, with the offsets of the jumps to patch when it ends
+enum Block {
+    Begin(usize),
+    Else(usize),
+    Loop(usize, Vec<usize>), // Start, jumps out of the loop from while
+}
+
+// Macro definition, its body is spliced in place of each invocation with the arguments replaced
+#[derive(Clone)]
+struct Macro<'a> {
+    args: Vec<&'a str>,
+    body: Vec<&'a str>,
+}
+
+// Operand of a statement
+enum Value {
+    Reg(u8),
+    Num(i32),
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(&'a str, usize)>, // Token, line
+    pos: usize,
+    rom: Vec<u8>, // From 0x200
+    here: usize, // Offset the next byte goes to
+    main_jump: bool, // The rom starts with a jump to main
+    labels: HashMap<&'a str, usize>, // Name -> Address
+    constants: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, u8>, // Name -> Register
+    macros: HashMap<&'a str, Macro<'a>>,
+    expansions: usize,
+    fixups: Vec<(&'a str, Fixup, usize)>, // Label, where, line
+    blocks: Vec<(Block, usize)>, // Structure, line
+}
+
+impl<'a> Assembler<'a> {
+    // Returns the next token, fails at the end of the source
+    fn next(&mut self) -> Result<&'a str, String> {
+        let line = self.tokens.last().map_or(1, |t| t.1);
+        let token = self.tokens.get(self.pos).ok_or(format!("Line {}: unexpected end of the program", line))?.0;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    // Returns the next token without consuming it
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|t| t.0)
+    }
+
+    // Consumes the next token if it is the one expected
+    fn accept(&mut self, token: &str) -> bool {
+        if self.peek() == Some(token) { self.pos += 1; true } else { false }
+    }
+
+    // Consumes the next token, failing if it isn't the one expected
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        let found = self.next()?;
+        if found != token { return Err(self.error(format!("expected \"{}\", found \"{}\"", token, found))); }
+        Ok(())
+    }
+
+    // Formats an error about the last token read
+    fn error(&self, message: String) -> String {
+        let line = self.tokens.get(self.pos.saturating_sub(1)).map_or(1, |t| t.1);
+        format!("Line {}: {}", line, message)
+    }
+
+    // Writes a byte at the current address
+    fn byte(&mut self, val: u8) -> Result<(), String> {
+        if START + self.here >= 0x10000 { return Err(self.error("program is past the end of memory".to_string())); }
+        if self.rom.len() <= self.here { self.rom.resize(self.here + 1, 0); }
+        self.rom[self.here] = val;
+        self.here += 1;
+        Ok(())
+    }
+
+    // Writes an instruction at the current address
+    fn op(&mut self, op: u16) -> Result<(), String> {
+        self.byte((op >> 8) as u8)?;
+        self.byte(op as u8)
+    }
+
+    // Returns the register a token names, if any
+    fn register(&self, token: &str) -> Option<u8> {
+        if let Some(r) = self.aliases.get(token) { return Some(*r); }
+        let digit = token.strip_prefix('v').or_else(|| token.strip_prefix('V'))?;
+        if digit.len() != 1 { return None; }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    // Reads a register
+    fn reg(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register(token).ok_or_else(|| self.error(format!("\"{}\" isn't a register", token)))
+    }
+
+    // Returns the number a literal or constant stands for, or a label already defined
+    fn number(&self, token: &str) -> Option<i32> {
+        if let Some(val) = self.constants.get(token) { return Some(*val); }
+        if let Some(addr) = self.labels.get(token) { return Some(*addr as i32); }
+        let (negative, digits) = match token.strip_prefix('-') {
+            Some(t) => (true, t),
+            None => (false, token)
+        };
+        let val = match digits {
+            t if t.starts_with("0x") || t.starts_with("0X") => i32::from_str_radix(&t[2..], 16).ok()?,
+            t if t.starts_with("0b") || t.starts_with("0B") => i32::from_str_radix(&t[2..], 2).ok()?,
+            t => t.parse::<i32>().ok()?
+        };
+        Some(if negative { -val } else { val })
+    }
+
+    // Reads a number within a range
+    fn num(&mut self, range: std::ops::RangeInclusive<i32>) -> Result<i32, String> {
+        let token = self.next()?;
+        let val = self.number(token).ok_or_else(|| self.error(format!("\"{}\" isn't a number", token)))?;
+        if !range.contains(&val) { return Err(self.error(format!("{} is out of range", token))); }
+        Ok(val)
+    }
+
+    // Reads a byte, signed or not
+    fn imm(&mut self) -> Result<u8, String> {
+        Ok(self.num(-128..=255)? as u8)
+    }
+
+    // Reads a register or a byte
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek().and_then(|t| self.register(t)) {
+            Some(r) => { self.pos += 1; Ok(Value::Reg(r)) },
+            None => Ok(Value::Num(self.imm()? as i32))
+        }
+    }
+
+    // Reads an address, which may be a label defined further on, and patches it in
+    fn address(&mut self, fixup: Fixup) -> Result<(), String> {
+        let token = self.next()?;
+        let line = self.tokens[self.pos - 1].1;
+        match self.number(token) {
+            Some(addr) => self.patch(fixup, addr, line),
+            None if self.register(token).is_none() => {
+                self.fixups.push((token, fixup, line));
+                Ok(())
+            },
+            None => Err(self.error(format!("expected an address, found register \"{}\"", token)))
+        }
+    }
+
+    // Writes an address into the rom
+    fn patch(&mut self, fixup: Fixup, addr: i32, line: usize) -> Result<(), String> {
+        let max = if let Fixup::Long(_) = fixup { 0xFFFF } else { 0xFFF };
+        if !(0..=max).contains(&addr) { return Err(format!("Line {}: address 0x{:X} is out of range", line, addr)); }
+        let addr = addr as u16;
+        match fixup {
+            Fixup::Short(at) => {
+                self.rom[at] = self.rom[at] & 0xF0 | (addr >> 8) as u8;
+                self.rom[at + 1] = addr as u8;
+            },
+            Fixup::Long(at) => self.rom[at..at + 2].copy_from_slice(&addr.to_be_bytes()),
+            Fixup::Unpack(at, nibble) => {
+                self.rom[at + 1] = nibble << 4 | (addr >> 8) as u8;
+                self.rom[at + 3] = addr as u8;
+            },
+        }
+        Ok(())
+    }
+
+    // Emits an instruction taking an address (jump, call, i := ...)
+    fn op_address(&mut self, op: u16) -> Result<(), String> {
+        let at = self.here;
+        self.op(op)?;
+        self.address(Fixup::Short(at))
+    }
+
+    // Emits a jump to be patched later, returns its offset
+    fn jump_placeholder(&mut self) -> Result<usize, String> {
+        let at = self.here;
+        self.op(0x1000)?;
+        Ok(at)
+    }
+
+    // Points a jump at the current address
+    fn land(&mut self, at: usize) -> Result<(), String> {
+        let line = self.tokens[self.pos - 1].1;
+        self.patch(Fixup::Short(at), (START + self.here) as i32, line)
+    }
+
+    // Defines a label at an address
+    fn define(&mut self, name: &'a str, addr: usize) -> Result<(), String> {
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return Err(self.error(format!("\"{}\" is already defined", name)));
+        }
+        if self.register(name).is_some() || self.number(name).is_some() {
+            return Err(self.error(format!("\"{}\" can't be used as a name", name)));
+        }
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    // Reads the tokens up to the "}" closing an opening "{" already read
+    fn braced(&mut self) -> Result<Vec<&'a str>, String> {
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                "}" if depth == 0 => return Ok(tokens),
+                "}" => depth -= 1,
+                "{" => depth += 1,
+                _ => ()
+            }
+            tokens.push(self.tokens[self.pos - 1].0);
+        }
+    }
+
+    // Replaces a macro invocation by the body of the macro, with its arguments
+    fn expand(&mut self, name: &'a str) -> Result<(), String> {
+        let line = self.tokens[self.pos - 1].1;
+        let m = self.macros[name].clone();
+        let mut args = HashMap::new();
+        for arg in m.args {
+            args.insert(arg, self.next()?);
+        }
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(self.error(format!("macro \"{}\" expands forever", name)));
+        }
+        let body = m.body.iter().map(|t| (*args.get(t).unwrap_or(t), line));
+        self.tokens.splice(self.pos..self.pos, body);
+        Ok(())
+    }
+
+    // Evaluates a :calc expression, binary operators grouping from the right
+    fn calc(&mut self) -> Result<f64, String> {
+        let lhs = self.calc_term()?;
+        let op = match self.peek() {
+            Some(op @ ("+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "pow" | "min" | "max" |
+                "<" | ">" | "<=" | ">=" | "==" | "!=")) => op,
+            _ => return Ok(lhs)
+        };
+        self.pos += 1;
+        let rhs = self.calc()?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        Ok(match op {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" if rhs == 0.0 => return Err(self.error("division by zero".to_string())),
+            "/" => lhs / rhs,
+            "%" if b == 0 => return Err(self.error("division by zero".to_string())),
+            "%" => (a % b) as f64,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as i32 as f64,
+            ">" => (lhs > rhs) as i32 as f64,
+            "<=" => (lhs <= rhs) as i32 as f64,
+            ">=" => (lhs >= rhs) as i32 as f64,
+            "==" => (lhs == rhs) as i32 as f64,
+            _ => (lhs != rhs) as i32 as f64,
+        })
+    }
+
+    // Evaluates a number, a name defined earlier, a parenthesized expression or a unary operator
+    fn calc_term(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        Ok(match token {
+            "(" => {
+                let val = self.calc()?;
+                self.expect(")")?;
+                val
+            },
+            "-" => -self.calc_term()?,
+            "~" => !(self.calc_term()? as i64) as f64,
+            "!" => (self.calc_term()? == 0.0) as i32 as f64,
+            "floor" => self.calc_term()?.floor(),
+            "ceil" => self.calc_term()?.ceil(),
+            "abs" => self.calc_term()?.abs(),
+            "sqrt" => self.calc_term()?.sqrt(),
+            "sin" => self.calc_term()?.sin(),
+            "cos" => self.calc_term()?.cos(),
+            "@" => { // Byte of the program at an address
+                let addr = self.calc_term()? as usize;
+                *self.rom.get(addr.wrapping_sub(START)).unwrap_or(&0) as f64
+            },
+            "HERE" => (START + self.here) as f64,
+            "PI" => std::f64::consts::PI,
+            _ => self.number(token).ok_or_else(|| self.error(format!("\"{}\" isn't a number or a name defined before", token)))? as f64
+        })
+    }
+
+    // Compiles a condition so that the next instruction only runs when it holds (or doesn't, if negated)
+    fn condition(&mut self, negated: bool) -> Result<(), String> {
+        let x = self.reg()? as u16;
+        let mut cmp = self.next()?;
+        if negated {
+            cmp = match cmp {
+                "==" => "!=", "!=" => "==",
+                "<" => ">=", ">=" => "<",
+                ">" => "<=", "<=" => ">",
+                "key" => "-key", "-key" => "key",
+                _ => cmp
+            };
+        }
+        match cmp {
+            "key" => return self.op(0xE0A1 | x << 8), // Skips unless pressed
+            "-key" => return self.op(0xE09E | x << 8),
+            _ => ()
+        }
+        let rhs = self.value()?;
+        match (cmp, rhs) {
+            ("==", Value::Num(n)) => self.op(0x4000 | x << 8 | n as u16),
+            ("==", Value::Reg(y)) => self.op(0x9000 | x << 8 | (y as u16) << 4),
+            ("!=", Value::Num(n)) => self.op(0x3000 | x << 8 | n as u16),
+            ("!=", Value::Reg(y)) => self.op(0x5000 | x << 8 | (y as u16) << 4),
+            ("<" | ">=" | ">" | "<=", rhs) => {
+                // VF := rhs, then a subtraction leaves the comparison in the flag
+                match rhs {
+                    Value::Num(n) => self.op(0x6F00 | n as u16)?,
+                    Value::Reg(y) => self.op(0x8F00 | (y as u16) << 4)?,
+                }
+                match cmp {
+                    "<" | ">=" => self.op(0x8F07 | x << 4)?, // VF = X - rhs, flag set if X >= rhs
+                    _ => self.op(0x8F05 | x << 4)?, // VF = rhs - X, flag set if rhs >= X
+                }
+                self.op(if cmp == "<" || cmp == ">" { 0x4F00 } else { 0x3F00 })
+            },
+            _ => Err(self.error(format!("\"{}\" isn't a comparison", cmp)))
+        }
+    }
+
+    // Compiles a statement starting with a register
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let x = x as u16;
+        let operator = self.next()?;
+        let source = self.peek().unwrap_or("");
+        match (operator, source) {
+            (":=", "key") => { self.pos += 1; return self.op(0xF00A | x << 8); },
+            (":=", "delay") => { self.pos += 1; return self.op(0xF007 | x << 8); },
+            (":=", "random") => {
+                self.pos += 1;
+                let mask = self.imm()? as u16;
+                return self.op(0xC000 | x << 8 | mask);
+            },
+            _ => ()
+        }
+        match (operator, self.value()?) {
+            (":=", Value::Num(n)) => self.op(0x6000 | x << 8 | n as u16),
+            ("+=", Value::Num(n)) => self.op(0x7000 | x << 8 | n as u16),
+            ("-=", Value::Num(n)) => self.op(0x7000 | x << 8 | (n as u8).wrapping_neg() as u16),
+            (operator, Value::Reg(y)) => {
+                let n = match operator {
+                    ":=" => 0x0, "|=" => 0x1, "&=" => 0x2, "^=" => 0x3, "+=" => 0x4,
+                    "-=" => 0x5, ">>=" => 0x6, "=-" => 0x7, "<<=" => 0xE,
+                    _ => return Err(self.error(format!("unknown operator \"{}\"", operator)))
+                };
+                self.op(0x8000 | x << 8 | (y as u16) << 4 | n)
+            },
+            (operator, Value::Num(_)) => Err(self.error(format!("\"{}\" needs a register on its right", operator)))
+        }
+    }
+
+    // Compiles a statement
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+        match token {
+            ":" => {
+                let name = self.next()?;
+                if name == "main" && self.main_jump && self.here == 2 && self.rom.len() == 2 {
+                    // Main comes first, no need to jump to it
+                    self.rom.clear();
+                    self.here = 0;
+                    self.main_jump = false;
+                }
+                self.define(name, START + self.here)
+            },
+            ":const" => {
+                let name = self.next()?;
+                let val = self.num(-0x8000..=0xFFFF)?;
+                if self.labels.contains_key(name) || self.constants.insert(name, val).is_some() {
+                    return Err(self.error(format!("\"{}\" is already defined", name)));
+                }
+                Ok(())
+            },
+            ":alias" => {
+                let name = self.next()?;
+                let reg = self.reg()?;
+                self.aliases.insert(name, reg);
+                Ok(())
+            },
+            ":org" => {
+                self.here = (self.num(START as i32..=0xFFFF)? as usize) - START;
+                Ok(())
+            },
+            ":byte" => {
+                let val = self.imm()?;
+                self.byte(val)
+            },
+            ":pointer" => {
+                let at = self.here;
+                self.op(0)?;
+                self.address(Fixup::Long(at))
+            },
+            ":call" => self.op_address(0x2000),
+            ":next" => { // Names the byte after the first one of the next instruction, to modify it
+                let name = self.next()?;
+                self.define(name, START + self.here + 1)
+            },
+            ":unpack" => {
+                let nibble = self.num(0..=0xF)? as u8;
+                let at = self.here;
+                self.op(0x6000)?; // v0 := nibble and high bits of the address
+                self.op(0x6100)?; // v1 := low byte
+                self.address(Fixup::Unpack(at, nibble))
+            },
+            ":breakpoint" => self.next().map(|_| ()),
+            ":monitor" => { self.next()?; self.next().map(|_| ()) },
+            ":macro" => {
+                let name = self.next()?;
+                let mut args = Vec::new();
+                loop {
+                    match self.next()? {
+                        "{" => break,
+                        arg => args.push(arg),
+                    }
+                }
+                let body = self.braced()?;
+                self.macros.insert(name, Macro { args, body });
+                Ok(())
+            },
+            ":calc" => {
+                let name = self.next()?;
+                self.expect("{")?;
+                let val = self.calc()?;
+                self.expect("}")?;
+                if self.labels.contains_key(name) { // Constants can be calculated again
+                    return Err(self.error(format!("\"{}\" is already defined", name)));
+                }
+                self.constants.insert(name, val.floor() as i32);
+                Ok(())
+            },
+            ":assert" | ":string" | ":stringmode" | "{" | "}" =>
+                Err(self.error(format!("{} isn't supported, only Octo programs without strings or assertions can be assembled", token))),
+
+            "clear" => self.op(0x00E0),
+            "return" | ";" => self.op(0x00EE),
+            "exit" => self.op(0x00FD),
+            "lores" => self.op(0x00FE),
+            "hires" => self.op(0x00FF),
+            "scroll-left" => self.op(0x00FC),
+            "scroll-right" => self.op(0x00FB),
+            "scroll-down" => { let n = self.num(0..=0xF)? as u16; self.op(0x00C0 | n) },
+            "scroll-up" => { let n = self.num(0..=0xF)? as u16; self.op(0x00D0 | n) },
+            "audio" => self.op(0xF002),
+            "plane" => { let n = self.num(0..=0xF)? as u16; self.op(0xF001 | n << 8) },
+            "native" => self.op_address(0x0000),
+            "jump" => self.op_address(0x1000),
+            "jump0" => self.op_address(0xB000),
+            "bcd" => { let x = self.reg()? as u16; self.op(0xF033 | x << 8) },
+            "saveflags" => { let x = self.reg()? as u16; self.op(0xF075 | x << 8) },
+            "loadflags" => { let x = self.reg()? as u16; self.op(0xF085 | x << 8) },
+            "save" | "load" => {
+                let x = self.reg()? as u16;
+                if self.accept("-") { // XO-CHIP register range
+                    let y = self.reg()? as u16;
+                    return self.op(if token == "save" { 0x5002 } else { 0x5003 } | x << 8 | y << 4);
+                }
+                self.op(if token == "save" { 0xF055 } else { 0xF065 } | x << 8)
+            },
+            "sprite" => {
+                let (x, y) = (self.reg()? as u16, self.reg()? as u16);
+                let n = self.num(0..=0xF)? as u16;
+                self.op(0xD000 | x << 8 | y << 4 | n)
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.reg()? as u16;
+                self.op(match token { "delay" => 0xF015, "buzzer" => 0xF018, _ => 0xF03A } | x << 8)
+            },
+            "i" => {
+                if self.accept("+=") {
+                    let x = self.reg()? as u16;
+                    return self.op(0xF01E | x << 8);
+                }
+                self.expect(":=")?;
+                if self.accept("hex") {
+                    let x = self.reg()? as u16;
+                    return self.op(0xF029 | x << 8);
+                }
+                if self.accept("bighex") {
+                    let x = self.reg()? as u16;
+                    return self.op(0xF030 | x << 8);
+                }
+                if self.accept("long") {
+                    self.op(0xF000)?;
+                    let at = self.here;
+                    self.op(0)?;
+                    return self.address(Fixup::Long(at));
+                }
+                self.op_address(0xA000)
+            },
+
+            "if" => {
+                let start = self.pos;
+                // Find out the form of the statement before compiling the condition
+                let form = self.tokens[self.pos..].iter().map(|t| t.0).find(|t| *t == "then" || *t == "begin");
+                match form {
+                    Some("then") => {
+                        self.condition(false)?;
+                        self.expect("then")
+                    },
+                    Some(_) => {
+                        self.condition(true)?;
+                        self.expect("begin")?;
+                        let at = self.jump_placeholder()?;
+                        self.blocks.push((Block::Begin(at), self.tokens[start].1));
+                        Ok(())
+                    },
+                    None => Err(self.error("if without then or begin".to_string()))
+                }
+            },
+            "else" => match self.blocks.pop() {
+                Some((Block::Begin(at), line)) => {
+                    let out = self.jump_placeholder()?;
+                    self.land(at)?;
+                    self.blocks.push((Block::Else(out), line));
+                    Ok(())
+                },
+                _ => Err(self.error("else without if ... begin".to_string()))
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::Begin(at), _)) | Some((Block::Else(at), _)) => self.land(at),
+                _ => Err(self.error("end without if ... begin".to_string()))
+            },
+            "loop" => {
+                let line = self.tokens[self.pos - 1].1;
+                self.blocks.push((Block::Loop(self.here, Vec::new()), line));
+                Ok(())
+            },
+            "while" => {
+                self.condition(true)?;
+                let at = self.jump_placeholder()?;
+                match self.blocks.iter_mut().rev().find(|b| matches!(b.0, Block::Loop(..))) {
+                    Some((Block::Loop(_, exits), _)) => { exits.push(at); Ok(()) },
+                    _ => Err(self.error("while outside of a loop".to_string()))
+                }
+            },
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop(start, exits), _)) => {
+                    self.op(0x1000 | (START + start) as u16)?;
+                    for at in exits {
+                        self.land(at)?;
+                    }
+                    Ok(())
+                },
+                _ => Err(self.error("again without loop".to_string()))
+            },
+
+            _ => {
+                if self.macros.contains_key(token) {
+                    return self.expand(token);
+                }
+                if let Some(x) = self.register(token) {
+                    return self.register_statement(x);
+                }
+                if let Some(val) = self.number(token).filter(|_| !self.labels.contains_key(token)) {
+                    if !(-128..=255).contains(&val) { return Err(self.error(format!("{} isn't a byte", token))); }
+                    return self.byte(val as u8);
+                }
+                self.pos -= 1; // A subroutine call by name
+                self.op_address(0x2000)
+            }
+        }
+    }
+
+    // Resolves the labels used before being defined, returns the program
+    fn finish(mut self) -> Result<Vec<u8>, String> {
+        if let Some((_, line)) = self.blocks.last() {
+            return Err(format!("Line {}: structure isn't closed", line));
+        }
+        for (name, fixup, line) in std::mem::take(&mut self.fixups) {
+            let addr = *self.labels.get(name).ok_or(format!("Line {}: \"{}\" is never defined", line, name))?;
+            self.patch(fixup, addr as i32, line)?;
+        }
+        if self.main_jump {
+            let main = *self.labels.get("main").ok_or("The program has no main label")?;
+            self.patch(Fixup::Short(0), main as i32, 1)?;
+            self.rom[0] |= 0x10;
+        }
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn main_first() {
+        assert_eq!(assemble(": main\n0x12 0x00 # raw bytes\n").unwrap(), [0x12, 0x00]);
+    }
+
+    #[test]
+    fn jump_to_main() {
+        let program = assemble(": sub v0 += 1 ;\n: main sub jump main").unwrap();
+        assert_eq!(program, [0x12, 0x06, 0x70, 0x01, 0x00, 0xEE, 0x22, 0x02, 0x12, 0x06]);
+    }
+
+    #[test]
+    fn statements() {
+        let source = "
+            :alias x v3
+            :const speed 4
+            : main
+                clear
+                x := speed  v1 := random 0x0F  v2 := key  v4 := delay
+                x += -1  x -= 1  x += v1  x -= v1  x =- v1  x |= v1  x &= v1  x ^= v1  x >>= v1  x <<= v1
+                i := smile  i := hex x  i := bighex x  i += x  i := long smile
+                delay := x  buzzer := x  bcd x  save x  load x  save v1 - v2  sprite v1 v2 8
+                if x == 3 then exit
+            : smile 0b10000001
+        ";
+        let program = assemble(source).unwrap();
+        let words: Vec<u16> = program.chunks(2).map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)])).collect();
+        assert_eq!(words, [
+            0x00E0,
+            0x6304, 0xC10F, 0xF20A, 0xF407,
+            0x73FF, 0x73FF, 0x8314, 0x8315, 0x8317, 0x8311, 0x8312, 0x8313, 0x8316, 0x831E,
+            0xA23C, 0xF329, 0xF330, 0xF31E, 0xF000, 0x023C,
+            0xF315, 0xF318, 0xF333, 0xF355, 0xF365, 0x5122, 0xD128,
+            0x4303, 0x00FD,
+            0x8100,
+        ]);
+    }
+
+    #[test]
+    fn structures() {
+        let source = "
+            : main
+            loop
+                v0 += 1
+                while v0 != 10
+                if v0 < v1 begin v2 := 1 else v2 := 2 end
+            again
+        ";
+        let words: Vec<u16> = assemble(source).unwrap().chunks(2).map(|w| u16::from_be_bytes([w[0], w[1]])).collect();
+        assert_eq!(words, [
+            0x7001,
+            0x400A, 0x1216, // while: leave the loop once v0 == 10
+            0x8F10, 0x8F07, 0x3F00, 0x1212, // v0 < v1 is false when v0 >= v1 (flag set)
+            0x6201, 0x1214,
+            0x6202,
+            0x1200,
+        ]);
+    }
+
+    // Assembles a program into words
+    fn words(source: &str) -> Vec<u16> {
+        assemble(source).unwrap().chunks(2).map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)])).collect()
+    }
+
+    #[test]
+    fn directives() {
+        let source = "
+            : main
+                jump later
+            :next target v0 := 0  # Self modifying code rewrites the byte of 0x00
+                :unpack 0xA data
+                :call later
+                :breakpoint here
+                :monitor data 2
+            : later
+                i := long data
+            :org 0x300
+            : data -1 0xFF :pointer later :byte 7
+        ";
+        assert_eq!(words(source), [
+            0x120A,
+            0x6000,
+            0x60A3, 0x6100,
+            0x220A,
+            0xF000, 0x0300,
+        ].into_iter().chain([0; 0x79]).chain([0xFFFF, 0x020A, 0x0700]).collect::<Vec<_>>());
+        assert_eq!(assemble(": main :next target v0 := 5 i := target").unwrap(), [0x60, 0x05, 0xA2, 0x01]);
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(words(": main hires lores scroll-down 4 scroll-up 2 scroll-left scroll-right exit plane 3 audio pitch := v1 saveflags v2 loadflags v3 native 0x123"), [
+            0x00FF, 0x00FE, 0x00C4, 0x00D2, 0x00FC, 0x00FB, 0x00FD, 0xF301, 0xF002, 0xF13A, 0xF275, 0xF385, 0x0123,
+        ]);
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(words(": main if v1 key then jump0 0x300 if v1 -key then ; if v1 != v2 then ; if v1 > 4 then ; if v1 <= v2 then ;"), [
+            0xE1A1, 0xB300,
+            0xE19E, 0x00EE,
+            0x5120, 0x00EE,
+            0x6F04, 0x8F15, 0x4F00, 0x00EE,
+            0x8F20, 0x8F15, 0x3F00, 0x00EE,
+        ]);
+    }
+
+    #[test]
+    fn macros() {
+        let source = "
+            :macro add-twice reg n { reg += n reg += n }
+            :macro both { add-twice v1 2 add-twice v2 3 }
+            : main both
+        ";
+        assert_eq!(words(source), [0x7102, 0x7102, 0x7203, 0x7203]);
+
+        // Arguments can be anything, even a nested :calc
+        let source = "
+            :const counter 0
+            :macro count { :calc counter { counter + 1 } }
+            : main count count v0 := counter
+        ";
+        assert_eq!(words(source), [0x6002]);
+
+        let err = assemble(":macro forever { forever }\n: main forever").unwrap_err();
+        assert_eq!(err, "Line 2: macro \"forever\" expands forever");
+    }
+
+    #[test]
+    fn calc() {
+        let source = "
+            :const width 64
+            : main
+            :calc half { width / 2 }
+            :calc right-to-left { 10 - 4 - 3 }
+            :calc grouped { ( 10 - 4 ) - 3 }
+            :calc mixed { 1 + 2 * 3 }
+            :calc bits { 0xF0 & 0x3C | 0x01 }
+            :calc inverted { ~ 0xF0 & 0xFF }
+            :calc here { HERE + 1 }
+            :calc rounded { floor 7 / 2 } # Unary operators only take the term after them, 3.5 is rounded down
+            v0 := half v1 := right-to-left v2 := grouped v3 := mixed v4 := bits v7 := inverted
+            i := here
+            : data 0x42 0x00
+            :calc byte { @ data }
+            v5 := byte v6 := rounded
+        ";
+        assert_eq!(words(source), [0x6020, 0x6109, 0x6203, 0x6307, 0x6430, 0x670F, 0xA201, 0x4200, 0x6542, 0x6603]);
+
+        assert_eq!(assemble(": main :calc x { 1 / 0 }").unwrap_err(), "Line 1: division by zero");
+        assert_eq!(assemble(": main :calc x { later }").unwrap_err(), "Line 1: \"later\" isn't a number or a name defined before");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(assemble(": main\n:stringmode text \"ABC\" { :byte VALUE }").unwrap_err().starts_with("Line 2:"));
+        assert!(assemble(": main\n:macro unclosed { v0 := 1").unwrap_err().starts_with("Line 2: unexpected end"));
+        assert_eq!(assemble(": main : main").unwrap_err(), "Line 1: \"main\" is already defined");
+        assert_eq!(assemble(": main v0 := 256").unwrap_err(), "Line 1: 256 is out of range");
+        assert_eq!(assemble(": main v0 += v1 else").unwrap_err(), "Line 1: else without if ... begin");
+        assert_eq!(assemble(": main jump nowhere").unwrap_err(), "Line 1: \"nowhere\" is never defined");
+        assert!(assemble(": main loop v0 += 1").is_err());
+        assert!(assemble("v0 := 1").is_err()); // No main
+    }
+}
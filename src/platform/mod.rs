@@ -0,0 +1,94 @@
+use crate::emulator::{Chip8, Quirks, StackDepth};
+
+/// Family of interpreters a ROM can target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Platform {
+    Chip8, // Original COSMAC VIP interpreter
+    SuperChip, // SUPER-CHIP 1.1 on the HP 48
+    XoChip, // Octo's XO-CHIP extensions
+}
+
+impl Platform {
+    /// All platforms, from the most restrictive
+    pub const ALL: [Platform; 3] = [Platform::Chip8, Platform::SuperChip, Platform::XoChip];
+
+    /// Returns the name used on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    /// Returns the platform with a given name
+    pub fn from_name(name: &str) -> Option<Platform> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    /// Returns the quirks of the reference interpreter of the platform
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks {
+                shift_vy: true,
+                load_store_increment_i: true,
+                jump_vx: false,
+                vf_reset: true,
+                wrap_sprites: false,
+            },
+            Platform::SuperChip => Quirks {
+                shift_vy: false,
+                load_store_increment_i: false,
+                jump_vx: true,
+                vf_reset: false,
+                wrap_sprites: false,
+            },
+            Platform::XoChip => Quirks {
+                shift_vy: true,
+                load_store_increment_i: true,
+                jump_vx: false,
+                vf_reset: false,
+                wrap_sprites: true,
+            },
+        }
+    }
+
+    /// Returns how many nested calls the reference interpreter allows
+    pub fn stack_depth(&self) -> StackDepth {
+        match self {
+            Platform::Chip8 => StackDepth::Limited(12),
+            Platform::SuperChip | Platform::XoChip => StackDepth::Limited(16),
+        }
+    }
+
+    /// Returns true if the platform defines an instruction.
+    /// 0NNN machine code calls are only accepted on CHIP-8
+    pub fn supports(&self, instr: u16) -> bool {
+        let schip = *self != Platform::Chip8;
+        let xochip = *self == Platform::XoChip;
+        match Chip8::decode_to_nibbles(instr) {
+            (0x0, 0x0, 0xE, 0x0) | (0x0, 0x0, 0xE, 0xE) => true,
+            (0x0, 0x0, 0xC, _) | (0x0, 0x0, 0xF, 0xB) | (0x0, 0x0, 0xF, 0xC) |
+            (0x0, 0x0, 0xF, 0xD) | (0x0, 0x0, 0xF, 0xE) | (0x0, 0x0, 0xF, 0xF) => schip, // Scrolling, exit, resolution
+            (0x0, 0x0, 0xD, _) => xochip, // Scroll up
+            (0x0, _, _, _) => *self == Platform::Chip8,
+            (0x1, _, _, _) | (0x2, _, _, _) | (0x3, _, _, _) | (0x4, _, _, _) => true,
+            (0x5, _, _, 0x0) => true,
+            (0x5, _, _, 0x2) | (0x5, _, _, 0x3) => xochip, // Register range store / load
+            (0x6, _, _, _) | (0x7, _, _, _) => true,
+            (0x8, _, _, 0x0..=0x7) | (0x8, _, _, 0xE) => true,
+            (0x9, _, _, 0x0) => true,
+            (0xA, _, _, _) | (0xB, _, _, _) | (0xC, _, _, _) => true,
+            (0xD, _, _, _) => true, // DXY0 draws a 16x16 sprite on SUPER-CHIP, nothing on CHIP-8
+            (0xE, _, 0x9, 0xE) | (0xE, _, 0xA, 0x1) => true,
+            (0xF, 0x0, 0x0, 0x0) | (0xF, 0x0, 0x0, 0x2) => xochip, // Long I load, audio pattern
+            (0xF, _, 0x0, 0x1) => xochip, // Plane selection
+            (0xF, _, 0x0, 0x7) | (0xF, _, 0x0, 0xA) | (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) |
+            (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0xF, _, 0x3, 0x3) | (0xF, _, 0x5, 0x5) |
+            (0xF, _, 0x6, 0x5) => true,
+            (0xF, _, 0x3, 0x0) | (0xF, _, 0x7, 0x5) | (0xF, _, 0x8, 0x5) => schip, // Big font, flags
+            (0xF, _, 0x3, 0xA) => xochip, // Pitch
+            _ => false
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::disassembler::disassemble;
+
+/// Per-subroutine totals
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    pub self_cycles: u64, // Cycles spent in the subroutine itself
+    pub total_cycles: u64, // Cycles spent in the subroutine and its callees
+}
+
+/// Counts executions per address and attributes cycles to subroutines.
+/// Calls are followed by mirroring the `Chip8` stack: JSR pushes its target, RTS pops it
+pub struct Profiler {
+    entry: u16, // Address the program starts at
+    counts: HashMap<u16, (u16, u64)>, // Address -> (Instruction, Executions)
+    stack: Vec<u16>, // Entry points of the subroutines currently running
+    subroutines: HashMap<Option<u16>, SubroutineStats>, // None is the root of the call tree, even when a subroutine shares its address
+    folded: HashMap<Vec<u16>, u64>, // Call path -> Self cycles
+    cycles: u64,
+}
+
+impl Profiler {
+    /// Returns a new profiler for a program starting at an address
+    pub fn new(entry: u16) -> Self {
+        Self {
+            entry,
+            counts: HashMap::new(),
+            stack: Vec::new(),
+            subroutines: HashMap::new(),
+            folded: HashMap::new(),
+            cycles: 0,
+        }
+    }
+
+    /// Records an instruction that has been executed
+    pub fn record(&mut self, pc: u16, instr: u16) {
+        self.cycles += 1;
+        self.counts.entry(pc).or_insert((instr, 0)).1 += 1;
+
+        // Attribute the cycle to the current subroutine and its callers
+        let current = self.stack.last().copied();
+        self.subroutines.entry(current).or_default().self_cycles += 1;
+        let mut seen = Vec::<Option<u16>>::with_capacity(self.stack.len() + 1);
+        for sub in std::iter::once(None).chain(self.stack.iter().copied().map(Some)) {
+            if !seen.contains(&sub) { // Recursive subroutines are only counted once
+                self.subroutines.entry(sub).or_default().total_cycles += 1;
+                seen.push(sub);
+            }
+        }
+        match self.folded.get_mut(&self.stack) {
+            Some(v) => *v += 1,
+            None => { self.folded.insert(self.stack.clone(), 1); }
+        }
+
+        // Follow calls and returns
+        match instr & 0xF000 {
+            0x2000 => { // JSR NNN
+                let target = instr & 0xFFF;
+                self.subroutines.entry(Some(target)).or_default().calls += 1;
+                self.stack.push(target);
+            },
+            _ if instr == 0x00EE => { self.stack.pop(); }, // RTS
+            _ => ()
+        }
+    }
+
+    /// Returns the number of instructions recorded
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Returns the most executed addresses with their instruction and execution count
+    pub fn hot_spots(&self) -> Vec<(u16, u16, u64)> {
+        let mut spots: Vec<(u16, u16, u64)> = self.counts.iter().map(|(&a, &(i, c))| (a, i, c)).collect();
+        spots.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        spots
+    }
+
+    /// Returns the subroutines sorted by total cycles, the program outside of any call as None
+    pub fn subroutines(&self) -> Vec<(Option<u16>, SubroutineStats)> {
+        let mut subs: Vec<(Option<u16>, SubroutineStats)> = self.subroutines.iter().map(|(&a, &s)| (a, s)).collect();
+        subs.sort_by(|a, b| b.1.total_cycles.cmp(&a.1.total_cycles).then(a.0.cmp(&b.0)));
+        subs
+    }
+
+    // Names a frame of the call tree
+    fn frame_name(frame: Option<u16>) -> String {
+        match frame {
+            Some(addr) => format!("sub_{:03X}", addr),
+            None => "main".to_string()
+        }
+    }
+
+    /// Writes the call stacks in the folded format used by flamegraph tools (`main;sub_2A0;sub_300 1234`)
+    pub fn write_folded<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut lines: Vec<String> = self.folded.iter().map(|(path, cycles)| {
+            let names: Vec<String> = std::iter::once(None).chain(path.iter().copied().map(Some))
+                .map(Profiler::frame_name)
+                .collect();
+            format!("{} {}", names.join(";"), cycles)
+        }).collect();
+        lines.sort();
+        for line in lines {
+            writeln!(out, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a printable report limited to a number of rows per table
+    pub fn report(&self, top: usize) -> Report<'_> {
+        Report { profiler: self, top }
+    }
+}
+
+/// Text report of a profiler
+pub struct Report<'a> {
+    profiler: &'a Profiler,
+    top: usize,
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let p = self.profiler;
+        let percent = |c: u64| c as f64 / p.cycles.max(1) as f64 * 100.0;
+
+        writeln!(f, "Entry:\t0x{:03X}", p.entry)?;
+        writeln!(f, "Cycles:\t{}", p.cycles)?;
+        writeln!(f)?;
+        writeln!(f, "Hot spots")?;
+        writeln!(f, "Address\tCount\t\tShare\tInstruction")?;
+        for (addr, instr, count) in p.hot_spots().into_iter().take(self.top) {
+            writeln!(f, "0x{:04X}\t{:<8}\t{:5.1}%\t0x{:04X} -> {}", addr, count, percent(count), instr, disassemble(instr))?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Subroutines")?;
+        writeln!(f, "Name\t\tCalls\t\tSelf\t\tTotal")?;
+        for (addr, s) in p.subroutines().into_iter().take(self.top) {
+            writeln!(f, "{:<8}\t{:<8}\t{:<8} {:5.1}%\t{:<8} {:5.1}%",
+                Profiler::frame_name(addr), s.calls, s.self_cycles, percent(s.self_cycles), s.total_cycles, percent(s.total_cycles))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn call_to_entry_is_not_main() {
+        let mut profiler = Profiler::new(0x200);
+        profiler.record(0x202, 0x2200); // JSR 0x200
+        profiler.record(0x200, 0x00EE); // RTS
+        let subs = profiler.subroutines();
+        let main = subs.iter().find(|(a, _)| a.is_none()).unwrap().1;
+        let sub = subs.iter().find(|(a, _)| *a == Some(0x200)).unwrap().1;
+        assert_eq!((main.calls, main.self_cycles, main.total_cycles), (0, 1, 2));
+        assert_eq!((sub.calls, sub.self_cycles, sub.total_cycles), (1, 1, 1));
+
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded).unwrap();
+        assert_eq!(String::from_utf8(folded).unwrap(), "main 1\nmain;sub_200 1\n");
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::disassembler::disassemble;
+use crate::emulator::{Chip8, Observer, VReg, MEMORY_SIZE};
+
+/// What to do when the sanitizer finds an issue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SanitizeMode {
+    Warn, // Report and keep running
+    Halt, // Report and stop
+}
+
+/// Suspicious behaviour found while running a ROM
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IssueKind {
+    /// Memory that was never loaded nor written has been read or executed
+    UninitializedRead(u16),
+    /// A register that was never written has been read
+    UninitializedRegister(u8),
+    /// An instruction accessed memory past the end through I (I, bytes accessed)
+    IOutOfBounds(u16, u16),
+    /// An instruction wrote into the interpreter / font area below 0x200
+    ReservedWrite(u16),
+    /// Code is executed from bytes previously drawn as a sprite
+    SpriteExecuted(u16),
+    /// PC is not aligned on two bytes
+    OddPc,
+    /// 0NNN machine code call, which can't be emulated
+    MachineCodeCall(u16),
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IssueKind::UninitializedRead(a) => write!(f, "read of never written memory at 0x{:03X}", a),
+            IssueKind::UninitializedRegister(r) => write!(f, "read of never written register V{:X}", r),
+            IssueKind::IOutOfBounds(i, n) => write!(f, "I out of bounds (0x{:03X} + {} bytes)", i, n),
+            IssueKind::ReservedWrite(a) => write!(f, "write into the interpreter area at 0x{:03X}", a),
+            IssueKind::SpriteExecuted(a) => write!(f, "executing sprite data at 0x{:03X}", a),
+            IssueKind::OddPc => write!(f, "PC is not aligned on two bytes"),
+            IssueKind::MachineCodeCall(a) => write!(f, "machine code call to 0x{:03X}", a),
+        }
+    }
+}
+
+/// An issue along with the instruction that caused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Issue {
+    pub pc: u16,
+    pub instr: u16,
+    pub kind: IssueKind,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:04X}\t0x{:04X} -> {}\t{}", self.pc, self.instr, disassemble(self.instr), self.kind)
+    }
+}
+
+/// Observer flagging undefined or suspicious ROM behaviour.
+/// Each issue is only reported once per instruction address
+pub struct Sanitizer {
+    mode: SanitizeMode,
+    initialized: Vec<bool>, // Bytes loaded or written
+    registers: [bool; 16], // Registers written
+    sprites: Vec<bool>, // Bytes read by a draw instruction
+    i: u16, // Mirror of the I register
+    pc: u16, // Current instruction
+    instr: u16,
+    seen: HashSet<Issue>,
+    issues: Vec<Issue>, // Not yet taken
+    halted: bool,
+}
+
+impl Sanitizer {
+    /// Returns a new sanitizer, `i` being the initial value of the I register
+    pub fn new(mode: SanitizeMode, i: u16) -> Self {
+        Self {
+            mode,
+            initialized: vec![false; MEMORY_SIZE],
+            registers: [false; 16],
+            sprites: vec![false; MEMORY_SIZE],
+            i,
+            pc: 0,
+            instr: 0,
+            seen: HashSet::new(),
+            issues: Vec::new(),
+            halted: false,
+        }
+    }
+
+    /// Marks a memory range as loaded (program, font...)
+    pub fn with_initialized(mut self, start: usize, len: usize) -> Self {
+        for b in self.initialized.iter_mut().skip(start).take(len) {
+            *b = true;
+        }
+        self
+    }
+
+    /// Returns and forgets the issues found since the last call
+    pub fn take_issues(&mut self) -> Vec<Issue> {
+        std::mem::take(&mut self.issues)
+    }
+
+    /// Returns true if an issue has been found in halt mode
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Records an issue caused by the current instruction
+    fn report(&mut self, kind: IssueKind) {
+        let issue = Issue { pc: self.pc, instr: self.instr, kind };
+        if self.seen.insert(issue) {
+            self.issues.push(issue);
+            if self.mode == SanitizeMode::Halt { self.halted = true; }
+        }
+    }
+
+    // Checks a byte about to be read or executed
+    fn check_initialized(&mut self, addr: u16) {
+        if !self.initialized.get(addr as usize).copied().unwrap_or(true) {
+            self.report(IssueKind::UninitializedRead(addr));
+        }
+    }
+}
+
+impl Observer for Sanitizer {
+    fn on_fetch(&mut self, pc: u16, instr: u16) {
+        self.pc = pc;
+        self.instr = instr;
+
+        if pc % 2 == 1 { self.report(IssueKind::OddPc); }
+        for addr in [pc, pc + 1] {
+            self.check_initialized(addr);
+            if self.sprites.get(addr as usize).copied().unwrap_or(false) {
+                self.report(IssueKind::SpriteExecuted(addr));
+            }
+        }
+
+        // Registers read. Shifts and BNNN read VX or VY depending on the quirks, they're left out
+        let (x, y) = ((instr >> 8) as usize & 0xF, (instr >> 4) as usize & 0xF);
+        let reads: Vec<usize> = match Chip8::decode_to_nibbles(instr) {
+            (0x3, ..) | (0x4, ..) | (0x7, ..) | (0xE, ..) => vec![x],
+            (0x5, _, _, 0x0) | (0x9, _, _, 0x0) | (0xD, ..) => vec![x, y],
+            (0x8, _, _, 0x0) => vec![y],
+            (0x8, _, _, 0x1..=0x5) | (0x8, _, _, 0x7) => vec![x, y],
+            (0xF, _, 0x1, 0x5) | (0xF, _, 0x1, 0x8) | (0xF, _, 0x1, 0xE) | (0xF, _, 0x2, 0x9) | (0xF, _, 0x3, 0x3) => vec![x],
+            (0xF, _, 0x5, 0x5) => (0..=x).collect(),
+            _ => Vec::new()
+        };
+        for r in reads {
+            if !self.registers[r] { self.report(IssueKind::UninitializedRegister(r as u8)); }
+        }
+
+        // Memory accessed through I
+        let len = match Chip8::decode_to_nibbles(instr) {
+            (0x0, _, _, _) if instr != 0x00E0 && instr != 0x00EE => {
+                self.report(IssueKind::MachineCodeCall(instr & 0xFFF));
+                0
+            },
+            (0xD, _, _, n) => n as u16,
+            (0xF, _, 0x3, 0x3) => 3,
+            (0xF, x, 0x5, 0x5) | (0xF, x, 0x6, 0x5) => x as u16 + 1,
+            _ => 0
+        };
+        if len > 0 && self.i as usize + len as usize > MEMORY_SIZE {
+            self.report(IssueKind::IOutOfBounds(self.i, len));
+        }
+    }
+
+    fn on_mem_read(&mut self, addr: u16, _val: u8) {
+        self.check_initialized(addr);
+        if self.instr & 0xF000 == 0xD000 {
+            if let Some(b) = self.sprites.get_mut(addr as usize) { *b = true; }
+        }
+    }
+
+    fn on_mem_write(&mut self, addr: u16, _val: u8) {
+        if addr < 0x200 { self.report(IssueKind::ReservedWrite(addr)); }
+        if let Some(b) = self.initialized.get_mut(addr as usize) { *b = true; }
+        if let Some(b) = self.sprites.get_mut(addr as usize) { *b = false; } // New data, maybe code
+    }
+
+    fn on_reg_write(&mut self, reg: VReg, _val: u8) {
+        self.registers[reg.number() as usize] = true;
+    }
+
+    fn on_i_write(&mut self, val: u16) {
+        self.i = val;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::AddressPolicy;
+
+    #[test]
+    fn sprite_past_the_end_reported_before_the_fault() {
+        let emu = Chip8::new().set_address_policy(AddressPolicy::Fault)
+            .load_program(vec![0x60, 0x00, 0xAF, 0xFE, 0xD0, 0x04]).unwrap(); // LD V0, 0, LD I, 0xFFE then DRW V0, V0, 4
+        let mut emu = emu.with_observer(Sanitizer::new(SanitizeMode::Halt, 0x50).with_initialized(0x200, 6));
+        for _ in 0..2 {
+            let instr = emu.fetch().unwrap();
+            assert!(emu.exec(instr).is_ok());
+        }
+        let instr = emu.fetch().unwrap();
+        assert!(emu.exec(instr).is_err());
+        let issues = emu.observer_mut().take_issues();
+        assert_eq!(issues.iter().map(|i| i.kind).collect::<Vec<_>>(), [IssueKind::IOutOfBounds(0xFFE, 4)]);
+        assert!(emu.observer().is_halted());
+    }
+
+    // Runs a program under a sanitizer in warn mode until it fails, returns the issues
+    fn issues(program: &[u16], steps: usize) -> Vec<IssueKind> {
+        let program: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let sanitizer = Sanitizer::new(SanitizeMode::Warn, 0).with_initialized(0x200, program.len());
+        let mut emu = Chip8::new().load_program(program).unwrap().with_observer(sanitizer);
+        for _ in 0..steps {
+            let instr = emu.fetch().unwrap();
+            if emu.exec(instr).is_err() { break; }
+        }
+        assert!(!emu.observer().is_halted());
+        emu.observer_mut().take_issues().iter().map(|i| i.kind).collect()
+    }
+
+    #[test]
+    fn uninitialized_memory() {
+        // LD I, 0x300 then LDR V0: nothing was ever written there
+        assert_eq!(issues(&[0xA300, 0xF065], 2), [IssueKind::UninitializedRead(0x300)]);
+        // Unless it is stored first
+        assert_eq!(issues(&[0x6000, 0xA300, 0xF055, 0xF065], 4), []);
+        // Jumping there executes it, as 0x0000
+        let kinds = issues(&[0x1300], 2);
+        assert_eq!(kinds, [IssueKind::UninitializedRead(0x300), IssueKind::UninitializedRead(0x301), IssueKind::MachineCodeCall(0x000)]);
+    }
+
+    #[test]
+    fn uninitialized_registers() {
+        // ADD V1, 1 then SE V1, V2
+        assert_eq!(issues(&[0x7101, 0x5120], 2), [IssueKind::UninitializedRegister(1), IssueKind::UninitializedRegister(2)]);
+        // LD V2, 5 then STR V0-V2: V0 and V1 were never set
+        assert_eq!(issues(&[0x6205, 0xA300, 0xF255], 3), [IssueKind::UninitializedRegister(0), IssueKind::UninitializedRegister(1)]);
+        // Flags and loads count as writes
+        assert_eq!(issues(&[0x6001, 0x8004, 0xA200, 0xF165, 0x8F10], 5), []);
+    }
+
+    #[test]
+    fn font_is_initialized() {
+        // FONT V0 then DRW V0, V0, 5
+        let program = vec![0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05];
+        let sanitizer = Sanitizer::new(SanitizeMode::Halt, 0).with_initialized(0x050, 80).with_initialized(0x200, program.len());
+        let mut emu = Chip8::new().load_program(program).unwrap().with_observer(sanitizer);
+        for _ in 0..3 {
+            let instr = emu.fetch().unwrap();
+            emu.exec(instr).unwrap();
+        }
+        assert!(emu.observer_mut().take_issues().is_empty());
+    }
+
+    #[test]
+    fn suspicious_behaviour() {
+        // STR V0 into 0x100, then jump to an odd address
+        assert_eq!(issues(&[0x6000, 0xA100, 0xF055, 0x1207, 0x0000], 3), [IssueKind::ReservedWrite(0x100)]);
+        let program = [0x6000, 0xA100, 0x1209, 0x0000, 0x0000, 0x1209];
+        assert!(issues(&program, 4).contains(&IssueKind::OddPc));
+        assert_eq!(issues(&[0x0300], 1).first(), Some(&IssueKind::MachineCodeCall(0x300)));
+
+        // Drawing the code at 0x200 as a sprite, then running it again
+        let kinds = issues(&[0x6000, 0xA200, 0xD002, 0x1200], 5);
+        assert_eq!(kinds, [IssueKind::SpriteExecuted(0x200), IssueKind::SpriteExecuted(0x201)]);
+    }
+
+    #[test]
+    fn issues_are_reported_once() {
+        // ADD V1, 1 in a loop, V1 is written the first time around
+        assert_eq!(issues(&[0x7101, 0x1200], 6), [IssueKind::UninitializedRegister(1)]);
+        assert_eq!(issues(&[0x9120, 0x1200], 6), [IssueKind::UninitializedRegister(1), IssueKind::UninitializedRegister(2)]);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+use crate::disassembler::{disassemble, opcode_class, OpcodeClass};
+use crate::emulator::{Bus, Chip8, Observer};
+
+/// Selects which instructions get traced
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    ranges: Vec<(u16, u16)>, // Inclusive PC ranges, empty means everything
+    classes: Vec<OpcodeClass>, // Opcode classes, empty means everything
+}
+
+impl TraceFilter {
+    /// Returns a filter letting every instruction through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only trace instructions fetched within an inclusive address range
+    pub fn with_range(mut self, start: u16, end: u16) -> Self {
+        self.ranges.push((start, end));
+        self
+    }
+
+    /// Only trace instructions of a given class
+    pub fn with_class(mut self, class: OpcodeClass) -> Self {
+        self.classes.push(class);
+        self
+    }
+
+    /// Returns true if the instruction fetched at pc should be traced
+    pub fn accepts(&self, pc: u16, instr: u16) -> bool {
+        let in_range = self.ranges.is_empty() || self.ranges.iter().any(|&(s, e)| (s..=e).contains(&pc));
+        let in_class = self.classes.is_empty() || self.classes.contains(&opcode_class(instr));
+        in_range && in_class
+    }
+}
+
+// State captured before executing a traced instruction
+struct Snapshot {
+    frame: u64,
+    cycle: u64,
+    pc: u16,
+    instr: u16,
+    regs: [u8; 16],
+    i: u16,
+    timers: (u8, u8),
+    memory: Vec<u8>, // Compared afterwards, to see every byte the bus took (stack and display included in the VIP layout)
+}
+
+impl Snapshot {
+    fn take<O: Observer, B: Bus>(emu: &Chip8<O, B>, frame: u64, cycle: u64, pc: u16, instr: u16) -> Self {
+        Self {
+            frame,
+            cycle,
+            pc,
+            instr,
+            regs: emu.get_registers().values(),
+            i: emu.get_i(),
+            timers: emu.get_timers(),
+            memory: emu.get_memory(),
+        }
+    }
+}
+
+/// Writes executed instructions as JSON Lines, one object per instruction:
+/// `{"frame":0,"cycle":3,"pc":516,"opcode":24576,"asm":"MOV V0, 00","changes":{"V0":0}}`.
+/// `frame` and `cycle` count the frames and instructions before the traced one, from 0.
+/// Only registers, timers and memory bytes modified by the instruction appear in `changes`
+pub struct Tracer<W: Write> {
+    out: W,
+    filter: TraceFilter,
+    enabled: bool,
+    pending: Option<Snapshot>,
+}
+
+impl<W: Write> Tracer<W> {
+    /// Returns a new enabled tracer
+    pub fn new(out: W, filter: TraceFilter) -> Self {
+        Self {
+            out,
+            filter,
+            enabled: true,
+            pending: None,
+        }
+    }
+
+    /// Enables or disables tracing
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled { self.pending = None; }
+    }
+
+    /// Flips tracing on or off, returns the new state
+    pub fn toggle(&mut self) -> bool {
+        self.set_enabled(!self.enabled);
+        self.enabled
+    }
+
+    /// Returns true if tracing is on
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// To call after fetching an instruction and before executing it, with the frames and
+    /// instructions elapsed before it
+    pub fn begin<O: Observer, B: Bus>(&mut self, emu: &Chip8<O, B>, frame: u64, cycle: u64, pc: u16, instr: u16) {
+        self.pending = if self.enabled && self.filter.accepts(pc, instr) {
+            Some(Snapshot::take(emu, frame, cycle, pc, instr))
+        } else { None };
+    }
+
+    /// To call after executing the instruction passed to `begin`, writes the trace line
+    pub fn end<O: Observer, B: Bus>(&mut self, emu: &Chip8<O, B>) -> io::Result<()> {
+        let before = match self.pending.take() {
+            Some(s) => s,
+            None => return Ok(())
+        };
+        let after = Snapshot::take(emu, before.frame, before.cycle, before.pc, before.instr);
+
+        let mut changes = Vec::<String>::new();
+        for (r, (b, a)) in before.regs.iter().zip(after.regs.iter()).enumerate() {
+            if b != a { changes.push(format!("\"V{:X}\":{}", r, a)); }
+        }
+        if before.i != after.i { changes.push(format!("\"I\":{}", after.i)); }
+        if before.timers.0 != after.timers.0 { changes.push(format!("\"DT\":{}", after.timers.0)); }
+        if before.timers.1 != after.timers.1 { changes.push(format!("\"ST\":{}", after.timers.1)); }
+        let mut written = String::new();
+        for (addr, (b, a)) in before.memory.iter().zip(after.memory.iter()).enumerate() {
+            if b != a {
+                if !written.is_empty() { written.push(','); }
+                write!(written, "\"{}\":{}", addr, a).unwrap(); // Writing to a String can't fail
+            }
+        }
+        if !written.is_empty() { changes.push(format!("\"mem\":{{{}}}", written)); }
+
+        writeln!(self.out, "{{\"frame\":{},\"cycle\":{},\"pc\":{},\"opcode\":{},\"asm\":\"{}\",\"changes\":{{{}}}}}",
+            before.frame, before.cycle, before.pc, before.instr, disassemble(before.instr), changes.join(","))
+    }
+
+    /// Flushes the underlying writer
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::Layout;
+    use crate::headless::{Input, Runner};
+    use serde_json::Value;
+
+    // Traces some instructions of an emulator the way the trace subcommand does
+    fn trace(emu: Chip8, steps: usize) -> Vec<Value> {
+        let mut runner = Runner::new(emu, Input::default());
+        let mut tracer = Tracer::new(Vec::new(), TraceFilter::new());
+        for _ in 0..steps {
+            let emu = runner.get_emu();
+            let pc = emu.get_pc();
+            let instr = (emu.peek(pc as usize).unwrap() as u16) << 8 | emu.peek(pc as usize + 1).unwrap() as u16;
+            tracer.begin(emu, runner.get_frame(), runner.get_cycle(), pc, instr);
+            runner.step().unwrap();
+            tracer.end(runner.get_emu()).unwrap();
+        }
+        String::from_utf8(tracer.out).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn instructions_counted_from_zero() {
+        // One instruction per frame
+        let emu = Chip8::new().set_freq(60).load_program(vec![0x60, 0x01, 0x61, 0x02]).unwrap();
+        let lines = trace(emu, 2);
+        assert_eq!((&lines[0]["frame"], &lines[0]["cycle"], &lines[0]["pc"]), (&Value::from(0), &Value::from(0), &Value::from(0x200)));
+        assert_eq!((&lines[1]["frame"], &lines[1]["cycle"], &lines[1]["pc"]), (&Value::from(1), &Value::from(1), &Value::from(0x202)));
+        assert_eq!(lines[1]["changes"]["V1"], 2);
+    }
+
+    #[test]
+    fn stack_and_display_writes_in_ram() {
+        // CALL 0x204, then at 0x204: LD I, 0x20A and DRW V0, V0, 1 of 0x80
+        let program = vec![0x22, 0x04, 0x00, 0x00, 0xA2, 0x0A, 0xD0, 0x01, 0x00, 0x00, 0x80];
+        let emu = Chip8::new().set_layout(Layout::Vip).load_program(program).unwrap();
+        let lines = trace(emu, 3);
+        // The return address 0x202 at 0xECE
+        assert_eq!(lines[0]["changes"]["mem"], serde_json::json!({ "3790": 2, "3791": 2 }));
+        // The first byte of the framebuffer at 0xF00
+        assert_eq!(lines[2]["changes"]["mem"], serde_json::json!({ "3840": 0x80 }));
+    }
+
+    #[test]
+    fn filtered_out_instructions_are_skipped() {
+        let emu = Chip8::new().load_program(vec![0x60, 0x01, 0x61, 0x02]).unwrap();
+        let mut tracer = Tracer::new(Vec::new(), TraceFilter::new().with_range(0x202, 0x202));
+        let mut runner = Runner::new(emu, Input::default());
+        for (pc, instr) in [(0x200, 0x6001), (0x202, 0x6102)] {
+            tracer.begin(runner.get_emu(), runner.get_frame(), runner.get_cycle(), pc, instr);
+            runner.step().unwrap();
+            tracer.end(runner.get_emu()).unwrap();
+        }
+        let out = String::from_utf8(tracer.out).unwrap();
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.contains("\"cycle\":1,\"pc\":514"));
+    }
+}
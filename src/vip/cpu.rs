@@ -0,0 +1,464 @@
+/// What the CPU is wired to: memory, the N lines selecting I/O devices and the EF flag inputs
+pub trait Io {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    /// INP 1-7, the byte read is also stored at R(X)
+    fn input(&mut self, port: u8) -> u8;
+    /// OUT 1-7, with the byte at R(X)
+    fn output(&mut self, port: u8, val: u8);
+    /// Returns true if the flag input (1 to 4) is asserted
+    fn flag(&self, n: u8) -> bool;
+}
+
+/// RCA CDP1802 COSMAC processor.
+/// Times are counted in machine cycles (8 clock periods), 2 per instruction and 3 for long branches
+#[derive(Debug, Clone)]
+pub struct Cdp1802 {
+    pub r: [u16; 16], // Scratchpad registers
+    pub p: u8, // Selects the program counter
+    pub x: u8, // Selects the data pointer
+    pub d: u8, // Accumulator
+    pub df: bool, // Carry / no borrow
+    pub t: u8, // X and P saved on interrupts
+    pub ie: bool, // Interrupts enabled
+    pub q: bool, // Output flip-flop
+    idle: bool, // Waiting for DMA or an interrupt
+}
+
+impl Default for Cdp1802 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cdp1802 {
+    /// Returns a processor in its reset state, running from R0 = 0
+    pub fn new() -> Self {
+        Self {
+            r: [0; 16],
+            p: 0,
+            x: 0,
+            d: 0,
+            df: false,
+            t: 0,
+            ie: true,
+            q: false,
+            idle: false,
+        }
+    }
+
+    /// Returns the program counter, R(P)
+    pub fn pc(&self) -> u16 {
+        self.r[self.p as usize]
+    }
+
+    /// Returns true while stopped by IDL
+    pub fn is_idle(&self) -> bool {
+        self.idle
+    }
+
+    /// Transfers the byte at R0 to a device and increments R0, taking one machine cycle
+    pub fn dma_out<I: Io>(&mut self, io: &mut I) -> u8 {
+        let val = io.read(self.r[0]);
+        self.r[0] = self.r[0].wrapping_add(1);
+        self.idle = false;
+        val
+    }
+
+    /// Runs one instruction, or takes the interrupt if `interrupt` is asserted and enabled.
+    /// Returns the machine cycles taken
+    pub fn step<I: Io>(&mut self, io: &mut I, interrupt: bool) -> u32 {
+        if interrupt && self.ie {
+            self.t = self.x << 4 | self.p;
+            self.p = 1;
+            self.x = 2;
+            self.ie = false;
+            self.idle = false;
+            return 1;
+        }
+        if self.idle { return 1; }
+
+        let op = self.fetch(io);
+        let n = (op & 0xF) as usize;
+        let x = self.x as usize;
+        match op >> 4 {
+            0x0 if n == 0 => self.idle = true, // IDL
+            0x0 => self.d = io.read(self.r[n]), // LDN
+            0x1 => self.r[n] = self.r[n].wrapping_add(1), // INC
+            0x2 => self.r[n] = self.r[n].wrapping_sub(1), // DEC
+            0x3 => { // Short branches
+                let cond = match n {
+                    0x0 => true, // BR
+                    0x1 => self.q, // BQ
+                    0x2 => self.d == 0, // BZ
+                    0x3 => self.df, // BDF
+                    0x4..=0x7 => io.flag(n as u8 - 0x3), // B1-B4
+                    0x8 => { self.skip(1); return 2; }, // SKP
+                    0x9 => !self.q, // BNQ
+                    0xA => self.d != 0, // BNZ
+                    0xB => !self.df, // BNF
+                    _ => !io.flag(n as u8 - 0xB), // BN1-BN4
+                };
+                self.short_branch(io, cond);
+            },
+            0x4 => { // LDA
+                self.d = io.read(self.r[n]);
+                self.r[n] = self.r[n].wrapping_add(1);
+            },
+            0x5 => io.write(self.r[n], self.d), // STR
+            0x6 if n == 0 => self.r[x] = self.r[x].wrapping_add(1), // IRX
+            0x6 if n < 8 => { // OUT
+                let val = io.read(self.r[x]);
+                self.r[x] = self.r[x].wrapping_add(1);
+                io.output(n as u8, val);
+            },
+            0x6 => { // INP (0x68 selects no device)
+                self.d = io.input(n as u8 - 8);
+                io.write(self.r[x], self.d);
+            },
+            0x7 => match n {
+                0x0 | 0x1 => { // RET, DIS
+                    let val = io.read(self.r[x]);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                    self.x = val >> 4;
+                    self.p = val & 0xF;
+                    self.ie = n == 0x0;
+                },
+                0x2 => { // LDXA
+                    self.d = io.read(self.r[x]);
+                    self.r[x] = self.r[x].wrapping_add(1);
+                },
+                0x3 => { // STXD
+                    io.write(self.r[x], self.d);
+                    self.r[x] = self.r[x].wrapping_sub(1);
+                },
+                0x4 => { let m = io.read(self.r[x]); self.add(m, self.df) }, // ADC
+                0x5 => { let m = io.read(self.r[x]); self.sub(m, self.d, self.df) }, // SDB
+                0x6 => { // SHRC
+                    let carry = self.df;
+                    self.df = self.d & 0x1 != 0;
+                    self.d = self.d >> 1 | (carry as u8) << 7;
+                },
+                0x7 => { let m = io.read(self.r[x]); self.sub(self.d, m, self.df) }, // SMB
+                0x8 => io.write(self.r[x], self.t), // SAV
+                0x9 => { // MARK
+                    self.t = self.x << 4 | self.p;
+                    io.write(self.r[2], self.t);
+                    self.x = self.p;
+                    self.r[2] = self.r[2].wrapping_sub(1);
+                },
+                0xA => self.q = false, // REQ
+                0xB => self.q = true, // SEQ
+                0xC => { let m = self.fetch(io); self.add(m, self.df) }, // ADCI
+                0xD => { let m = self.fetch(io); self.sub(m, self.d, self.df) }, // SDBI
+                0xE => { // SHLC
+                    let carry = self.df;
+                    self.df = self.d & 0x80 != 0;
+                    self.d = self.d << 1 | carry as u8;
+                },
+                _ => { let m = self.fetch(io); self.sub(self.d, m, self.df) }, // SMBI
+            },
+            0x8 => self.d = self.r[n] as u8, // GLO
+            0x9 => self.d = (self.r[n] >> 8) as u8, // GHI
+            0xA => self.r[n] = self.r[n] & 0xFF00 | self.d as u16, // PLO
+            0xB => self.r[n] = self.r[n] & 0x00FF | (self.d as u16) << 8, // PHI
+            0xC => { // Long branches and skips
+                match n {
+                    0x0 => self.long_branch(io, true), // LBR
+                    0x1 => self.long_branch(io, self.q), // LBQ
+                    0x2 => self.long_branch(io, self.d == 0), // LBZ
+                    0x3 => self.long_branch(io, self.df), // LBDF
+                    0x4 => (), // NOP
+                    0x5 => self.long_skip(!self.q), // LSNQ
+                    0x6 => self.long_skip(self.d != 0), // LSNZ
+                    0x7 => self.long_skip(!self.df), // LSNF
+                    0x8 => self.long_skip(true), // LSKP
+                    0x9 => self.long_branch(io, !self.q), // LBNQ
+                    0xA => self.long_branch(io, self.d != 0), // LBNZ
+                    0xB => self.long_branch(io, !self.df), // LBNF
+                    0xC => self.long_skip(self.ie), // LSIE
+                    0xD => self.long_skip(self.q), // LSQ
+                    0xE => self.long_skip(self.d == 0), // LSZ
+                    _ => self.long_skip(self.df), // LSDF
+                }
+                return 3;
+            },
+            0xD => self.p = n as u8, // SEP
+            0xE => self.x = n as u8, // SEX
+            _ => match n {
+                0x0 => self.d = io.read(self.r[x]), // LDX
+                0x1 => self.d |= io.read(self.r[x]), // OR
+                0x2 => self.d &= io.read(self.r[x]), // AND
+                0x3 => self.d ^= io.read(self.r[x]), // XOR
+                0x4 => { let m = io.read(self.r[x]); self.add(m, false) }, // ADD
+                0x5 => { let m = io.read(self.r[x]); self.sub(m, self.d, true) }, // SD
+                0x6 => { // SHR
+                    self.df = self.d & 0x1 != 0;
+                    self.d >>= 1;
+                },
+                0x7 => { let m = io.read(self.r[x]); self.sub(self.d, m, true) }, // SM
+                0x8 => self.d = self.fetch(io), // LDI
+                0x9 => self.d |= self.fetch(io), // ORI
+                0xA => self.d &= self.fetch(io), // ANI
+                0xB => self.d ^= self.fetch(io), // XRI
+                0xC => { let m = self.fetch(io); self.add(m, false) }, // ADI
+                0xD => { let m = self.fetch(io); self.sub(m, self.d, true) }, // SDI
+                0xE => { // SHL
+                    self.df = self.d & 0x80 != 0;
+                    self.d <<= 1;
+                },
+                _ => { let m = self.fetch(io); self.sub(self.d, m, true) }, // SMI
+            },
+        }
+        2
+    }
+
+    // Reads the byte at R(P) and increments it
+    fn fetch<I: Io>(&mut self, io: &mut I) -> u8 {
+        let p = self.p as usize;
+        let val = io.read(self.r[p]);
+        self.r[p] = self.r[p].wrapping_add(1);
+        val
+    }
+
+    // Skips bytes of the program
+    fn skip(&mut self, len: u16) {
+        let p = self.p as usize;
+        self.r[p] = self.r[p].wrapping_add(len);
+    }
+
+    // Replaces the low byte of R(P) with the next byte if the condition holds, skips it otherwise
+    fn short_branch<I: Io>(&mut self, io: &mut I, cond: bool) {
+        let p = self.p as usize;
+        if cond {
+            let target = io.read(self.r[p]);
+            self.r[p] = self.r[p] & 0xFF00 | target as u16;
+        } else {
+            self.skip(1);
+        }
+    }
+
+    // Replaces R(P) with the next two bytes if the condition holds, skips them otherwise
+    fn long_branch<I: Io>(&mut self, io: &mut I, cond: bool) {
+        let p = self.p as usize;
+        if cond {
+            let (hi, lo) = (io.read(self.r[p]), io.read(self.r[p].wrapping_add(1)));
+            self.r[p] = (hi as u16) << 8 | lo as u16;
+        } else {
+            self.skip(2);
+        }
+    }
+
+    // Skips the next two bytes if the condition holds
+    fn long_skip(&mut self, cond: bool) {
+        if cond { self.skip(2); }
+    }
+
+    // D = D + val + carry, DF set on carry out
+    fn add(&mut self, val: u8, carry: bool) {
+        let sum = self.d as u16 + val as u16 + carry as u16;
+        self.d = sum as u8;
+        self.df = sum > 0xFF;
+    }
+
+    // D = a - b - borrow, where DF (and `no_borrow`) means no borrow
+    fn sub(&mut self, a: u8, b: u8, no_borrow: bool) {
+        let diff = a as i16 - b as i16 - !no_borrow as i16;
+        self.d = diff as u8;
+        self.df = diff >= 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 64 KiB of memory, with settable flags and the bytes sent out
+    struct TestIo {
+        mem: Vec<u8>,
+        flags: [bool; 4],
+        out: Vec<(u8, u8)>,
+    }
+
+    impl Io for TestIo {
+        fn read(&mut self, addr: u16) -> u8 { self.mem[addr as usize] }
+        fn write(&mut self, addr: u16, val: u8) { self.mem[addr as usize] = val; }
+        fn input(&mut self, port: u8) -> u8 { port * 0x10 }
+        fn output(&mut self, port: u8, val: u8) { self.out.push((port, val)); }
+        fn flag(&self, n: u8) -> bool { self.flags[n as usize - 1] }
+    }
+
+    // Returns a processor about to run a program at 0x0000
+    fn with_program(program: &[u8]) -> (Cdp1802, TestIo) {
+        let mut io = TestIo { mem: vec![0; 0x10000], flags: [false; 4], out: Vec::new() };
+        io.mem[..program.len()].copy_from_slice(program);
+        (Cdp1802::new(), io)
+    }
+
+    // Runs some instructions
+    fn run(cpu: &mut Cdp1802, io: &mut TestIo, steps: usize) {
+        for _ in 0..steps {
+            cpu.step(io, false);
+        }
+    }
+
+    #[test]
+    fn sep_and_sex_select_registers() {
+        // LDI 0x10, PLO R3, SEP R3, then at 0x10: SEX R5, LDI 0xAB, STR R5 (through R5 = 0), OUT 4 (at R(X) = R5)
+        let (mut cpu, mut io) = with_program(&[0xF8, 0x10, 0xA3, 0xD3]);
+        io.mem[0x10..0x15].copy_from_slice(&[0xE5, 0xF8, 0xAB, 0x55, 0x64]);
+        cpu.r[5] = 0x100;
+        run(&mut cpu, &mut io, 3);
+        assert_eq!((cpu.p, cpu.pc()), (3, 0x10));
+        assert_eq!(cpu.r[0], 4); // The old program counter stays where it was
+        run(&mut cpu, &mut io, 4);
+        assert_eq!(cpu.x, 5);
+        assert_eq!(io.mem[0x100], 0xAB);
+        assert_eq!(io.out, [(4, 0xAB)]);
+        assert_eq!(cpu.r[5], 0x101); // OUT increments R(X)
+    }
+
+    #[test]
+    fn arithmetic_carry_and_borrow() {
+        // SEX R1 with M(R1) = 0x20: ADD, SD, SM
+        let (mut cpu, mut io) = with_program(&[0xE1, 0xF4, 0xF5, 0xF7, 0xF7]);
+        cpu.r[1] = 0x100;
+        io.mem[0x100] = 0x20;
+        cpu.d = 0xF0;
+        run(&mut cpu, &mut io, 2);
+        assert_eq!((cpu.d, cpu.df), (0x10, true)); // 0xF0 + 0x20 carries out
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x10, true)); // 0x20 - 0x10, no borrow
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0xF0, false)); // 0x10 - 0x20 borrows
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0xD0, true)); // 0xF0 - 0x20, DF only clears on a borrow
+    }
+
+    #[test]
+    fn arithmetic_with_carry_in() {
+        // ADCI 0x01, SMBI 0x01 with DF set, then SMBI 0x01 with DF clear
+        let (mut cpu, mut io) = with_program(&[0x7C, 0x01, 0x7F, 0x01, 0x7F, 0x01]);
+        cpu.d = 0x10;
+        cpu.df = true;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x12, false));
+        cpu.df = true;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x11, true));
+        cpu.df = false;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x0F, true));
+    }
+
+    #[test]
+    fn shifts() {
+        // SHR, SHL, SHRC (RSHR), SHLC (RSHL)
+        let (mut cpu, mut io) = with_program(&[0xF6, 0xFE, 0x76, 0x7E]);
+        cpu.d = 0x81;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x40, true));
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x80, false));
+        cpu.df = true;
+        cpu.d = 0x02;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x81, false)); // The carry comes in at the top
+        run(&mut cpu, &mut io, 1);
+        assert_eq!((cpu.d, cpu.df), (0x02, true)); // And goes out at the top
+    }
+
+    #[test]
+    fn short_branches_stay_in_the_page_of_their_address_byte() {
+        // BR at 0x00FE jumps within page 0, at 0x01FF its address byte is in page 2
+        let (mut cpu, mut io) = with_program(&[]);
+        io.mem[0xFE..0x100].copy_from_slice(&[0x30, 0x40]);
+        cpu.r[0] = 0xFE;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!(cpu.pc(), 0x0040);
+
+        cpu.r[0] = 0x1FF;
+        io.mem[0x1FF] = 0x30;
+        io.mem[0x200] = 0x10;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!(cpu.pc(), 0x0210);
+
+        // Not taken, the address byte is skipped: BZ with D != 0
+        cpu.r[0] = 0x300;
+        io.mem[0x300] = 0x32;
+        cpu.d = 1;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!(cpu.pc(), 0x302);
+    }
+
+    #[test]
+    fn flag_branches_and_long_branches() {
+        // B3 0x10 taken on EF3, then LBR 0x1234 taking 3 cycles
+        let (mut cpu, mut io) = with_program(&[0x36, 0x10]);
+        io.flags[2] = true;
+        assert_eq!(cpu.step(&mut io, false), 2);
+        assert_eq!(cpu.pc(), 0x10);
+        io.mem[0x10..0x13].copy_from_slice(&[0xC0, 0x12, 0x34]);
+        assert_eq!(cpu.step(&mut io, false), 3);
+        assert_eq!(cpu.pc(), 0x1234);
+        // LSNZ skips two bytes when D != 0
+        io.mem[0x1234] = 0xC6;
+        cpu.d = 1;
+        run(&mut cpu, &mut io, 1);
+        assert_eq!(cpu.pc(), 0x1237);
+    }
+
+    #[test]
+    fn interrupt_saves_x_and_p_in_t() {
+        // Running from R5 with X = 3, the routine at R1 saves T then returns with RET
+        let (mut cpu, mut io) = with_program(&[]);
+        cpu.p = 5;
+        cpu.x = 3;
+        cpu.r[1] = 0x100;
+        cpu.r[2] = 0x1FF;
+        cpu.r[5] = 0x50;
+        io.mem[0x100..0x103].copy_from_slice(&[0x22, 0x78, 0x70]); // DEC R2, SAV, RET
+        assert_eq!(cpu.step(&mut io, true), 1);
+        assert_eq!((cpu.t, cpu.p, cpu.x, cpu.ie), (0x35, 1, 2, false));
+
+        // Masked while the routine runs
+        cpu.step(&mut io, true);
+        cpu.step(&mut io, true);
+        assert_eq!((cpu.r[2], io.mem[0x1FE]), (0x1FE, 0x35));
+
+        // Back to the interrupted program with interrupts enabled again
+        cpu.step(&mut io, true);
+        assert_eq!((cpu.p, cpu.x, cpu.ie, cpu.pc()), (5, 3, true, 0x50));
+        assert_eq!(cpu.r[2], 0x1FF);
+    }
+
+    #[test]
+    fn idle_until_interrupt() {
+        let (mut cpu, mut io) = with_program(&[0x00]);
+        run(&mut cpu, &mut io, 3);
+        assert!(cpu.is_idle());
+        assert_eq!(cpu.pc(), 1);
+        cpu.step(&mut io, true);
+        assert!(!cpu.is_idle());
+        assert_eq!(cpu.p, 1);
+    }
+
+    #[test]
+    fn dma_reads_through_r0() {
+        let (mut cpu, mut io) = with_program(&[]);
+        io.mem[0x300..0x302].copy_from_slice(&[0x12, 0x34]);
+        cpu.r[0] = 0x300;
+        assert_eq!(cpu.dma_out(&mut io), 0x12);
+        assert_eq!(cpu.dma_out(&mut io), 0x34);
+        assert_eq!(cpu.r[0], 0x302);
+    }
+
+    #[test]
+    fn input_is_stored_at_r_x() {
+        // SEX R4, INP 3
+        let (mut cpu, mut io) = with_program(&[0xE4, 0x6B]);
+        cpu.r[4] = 0x200;
+        run(&mut cpu, &mut io, 2);
+        assert_eq!((cpu.d, io.mem[0x200]), (0x30, 0x30));
+    }
+}
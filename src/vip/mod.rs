@@ -0,0 +1,271 @@
+use crate::emulator::{ExecError, Framebuffer};
+use crate::machine::Machine;
+
+pub mod cpu;
+
+pub use cpu::{Cdp1802, Io};
+
+/// Bytes of RAM, mirrored through the lower half of the address space
+pub const RAM_SIZE: usize = 0x1000;
+/// Where the monitor ROM sits, mirrored up to 0xFFFF
+pub const ROM_START: u16 = 0x8000;
+
+// CDP1861 video timing, in machine cycles and scanlines
+const CYCLES_PER_LINE: i32 = 14;
+const LINES_PER_FRAME: u32 = 262;
+const INTERRUPT_LINE: u32 = 62; // Held for the 2 lines before the display, 29 cycles before the first DMA
+const FIRST_LINE: u32 = 64;
+const DISPLAY_LINES: u32 = 128;
+const DMA_BYTES: usize = 8; // Per line, 64 pixels
+
+/// Hardware around the CPU: memory, the CDP1861 display and the hex keypad
+struct Board {
+    ram: Vec<u8>,
+    rom: Vec<u8>,
+    rom_at_zero: bool, // After a reset the ROM is read at 0x0000 until A15 goes high
+    display_on: bool,
+    ef1: bool, // Asserted by the 1861 around the start and the end of the display
+    keys: [bool; 16],
+    key_latch: u8, // Key selected by OUT 2, its state is read on EF3
+}
+
+impl Io for Board {
+    fn read(&mut self, addr: u16) -> u8 {
+        if addr >= ROM_START { self.rom_at_zero = false; }
+        if (addr >= ROM_START || self.rom_at_zero) && !self.rom.is_empty() {
+            return self.rom[addr as usize % self.rom.len()];
+        }
+        self.ram[addr as usize % RAM_SIZE]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if addr < ROM_START {
+            self.ram[addr as usize % RAM_SIZE] = val;
+        }
+    }
+
+    fn input(&mut self, port: u8) -> u8 {
+        if port == 1 { self.display_on = true; } // INP 1 turns the display on
+        0xFF // Nothing drives the data bus
+    }
+
+    fn output(&mut self, port: u8, val: u8) {
+        match port {
+            1 => self.display_on = false, // OUT 1 turns the display off
+            2 => self.key_latch = val & 0xF,
+            _ => ()
+        }
+    }
+
+    fn flag(&self, n: u8) -> bool {
+        match n {
+            1 => self.ef1,
+            3 => self.keys[self.key_latch as usize],
+            _ => false // EF2 is the cassette input, EF4 the IN button
+        }
+    }
+}
+
+/// RCA COSMAC VIP: a CDP1802 running a monitor ROM and a CHIP-8 interpreter in RAM,
+/// with its CDP1861 display. Unlike `Chip8`, hybrid roms calling machine code through 0NNN run
+pub struct Vip {
+    cpu: Cdp1802,
+    board: Board,
+    spare: i32, // Cycles owed to (or by) the CPU from the previous line
+    lines: [[u8; DMA_BYTES]; DISPLAY_LINES as usize], // Bytes sent by DMA during the last frame
+    display: Framebuffer, // One in four of the lines, the CHIP-8 interpreter shows each row 4 times
+}
+
+impl Vip {
+    /// Returns a VIP booting from a monitor ROM image (512 bytes on the real machine).
+    /// Without a monitor, execution starts directly at 0x0000 in RAM
+    pub fn new(monitor: Vec<u8>) -> Self {
+        let mut cpu = Cdp1802::new();
+        if monitor.is_empty() {
+            cpu.r[1] = (RAM_SIZE - 1) as u16; // Top of RAM, as the monitor leaves it after sizing memory
+        }
+        Self {
+            cpu,
+            board: Board {
+                ram: vec![0; RAM_SIZE],
+                rom_at_zero: !monitor.is_empty(),
+                rom: monitor,
+                display_on: false,
+                ef1: false,
+                keys: [false; 16],
+                key_latch: 0,
+            },
+            spare: 0,
+            lines: [[0; DMA_BYTES]; DISPLAY_LINES as usize],
+            display: Framebuffer::new(),
+        }
+    }
+
+    /// Loads bytes into RAM (the interpreter at 0x0000, the program at 0x200), fails if they don't fit
+    pub fn load(mut self, address: usize, data: &[u8]) -> Result<Self, String> {
+        if address + data.len() > RAM_SIZE {
+            return Err(format!("0x{:X} bytes at 0x{:03X} don't fit in the 0x{:X} bytes of RAM", data.len(), address, RAM_SIZE));
+        }
+        self.board.ram[address..address + data.len()].copy_from_slice(data);
+        Ok(self)
+    }
+
+    /// Returns the processor
+    pub fn cpu(&self) -> &Cdp1802 {
+        &self.cpu
+    }
+
+    /// Returns the RAM
+    pub fn get_memory(&self) -> &[u8] {
+        &self.board.ram
+    }
+
+    // Runs the CPU for some cycles, carrying overruns to the next call
+    fn run_cycles(&mut self, cycles: i32, interrupt: bool) {
+        self.spare += cycles;
+        while self.spare > 0 {
+            self.spare -= self.cpu.step(&mut self.board, interrupt) as i32;
+        }
+    }
+}
+
+impl Machine for Vip {
+    /// Runs the 262 scanlines of a frame, the display interrupt and DMA included
+    fn run_frame(&mut self) -> Result<(), String> {
+        for line in 0..LINES_PER_FRAME {
+            let on = self.board.display_on;
+            let display = (FIRST_LINE..FIRST_LINE + DISPLAY_LINES).contains(&line);
+            self.board.ef1 = on && ((FIRST_LINE - 4..FIRST_LINE).contains(&line)
+                || (FIRST_LINE + DISPLAY_LINES - 4..FIRST_LINE + DISPLAY_LINES).contains(&line));
+            let interrupt = on && (INTERRUPT_LINE..FIRST_LINE).contains(&line);
+
+            let mut cycles = CYCLES_PER_LINE;
+            if on && display {
+                let row = (line - FIRST_LINE) as usize;
+                for byte in 0..DMA_BYTES {
+                    self.lines[row][byte] = self.cpu.dma_out(&mut self.board);
+                }
+                cycles -= DMA_BYTES as i32;
+            }
+            self.run_cycles(cycles, interrupt);
+        }
+        if !self.board.display_on {
+            self.lines = [[0; DMA_BYTES]; DISPLAY_LINES as usize];
+        }
+        for y in 0..self.display.rows().len() {
+            self.display.set_row(y, u64::from_be_bytes(self.lines[y * 4]));
+        }
+        Ok(())
+    }
+
+    fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    fn clear_dirty(&mut self) {
+        self.display.clear_dirty();
+    }
+
+    fn update_key(&mut self, key: u8, val: bool) -> Result<(), ExecError> {
+        match self.board.keys.get_mut(key as usize) {
+            Some(k) => {
+                *k = val;
+                Ok(())
+            },
+            None => Err(ExecError::InvalidKey(key))
+        }
+    }
+
+    /// The buzzer follows the Q output
+    fn is_beeping(&self) -> bool {
+        self.cpu.q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sets R1 to an interrupt routine pointing R0 at a display page and R2 to a stack, then runs
+    // from R3: display on, loop forever. The routine is laid out like the VIP interpreter's,
+    // its exit (restore D, RET) right before its entry, and lasts until the DMA starts so that
+    // it isn't interrupted again
+    fn display_program(page: u8) -> Vec<u8> {
+        let mut ram = vec![0; 0x200];
+        ram[..0x13].copy_from_slice(&[
+            0xF8, 0x00, 0xB1, 0xB2, 0xB3, // R1, R2, R3 in page 0
+            0xF8, 0x48, 0xA1, // R1 = interrupt entry
+            0xF8, 0xF0, 0xA2, // R2 = stack
+            0xF8, 0x10, 0xA3, 0xD3, // R3 = main, SEP R3
+            0x00, 0xE2, 0x69, 0x30, // SEX R2, INP 1 (display on), BR to itself
+        ]);
+        ram[0x13] = 0x12;
+        ram[0x46..0x57].copy_from_slice(&[
+            0x72, 0x70, // Exit: LDXA, RET
+            0x22, 0x78, 0x22, 0x52, // Entry: save T and D
+            0xF8, page, 0xB0, 0xF8, 0x00, 0xA0, // R0 = display page
+            0xC4, 0xC4, 0xC4, // NOP
+            0x30, 0x46, // BR exit
+        ]);
+        ram
+    }
+
+    #[test]
+    fn display_dma() {
+        let mut vip = Vip::new(Vec::new()).load(0, &display_program(0x01)).unwrap();
+        // Each 8 byte line is sent by DMA, every fourth line is a CHIP-8 row
+        vip.board.ram[0x100..0x108].copy_from_slice(&[0xFF, 0, 0, 0, 0, 0, 0, 0x01]);
+        vip.board.ram[0x120] = 0x80;
+        vip.run_frame().unwrap(); // The display turns on during the first frame
+        vip.run_frame().unwrap();
+        assert_eq!(vip.framebuffer().row(0), 0xFF00_0000_0000_0001);
+        assert_eq!(vip.framebuffer().row(1), 0x8000_0000_0000_0000);
+        assert_eq!(vip.framebuffer().row(2), 0);
+        // The routine left the interrupted program as it was
+        assert_eq!((vip.cpu().p, vip.cpu().x), (3, 2));
+        assert_eq!(vip.cpu().r[2], 0xF0);
+    }
+
+    #[test]
+    fn display_off_is_blank() {
+        let mut program = display_program(0x01);
+        program[0x11] = 0xC4; // NOP instead of INP 1
+        let mut vip = Vip::new(Vec::new()).load(0, &program).unwrap();
+        vip.board.ram[0x100] = 0xFF;
+        vip.run_frame().unwrap();
+        assert_eq!(vip.framebuffer().row(0), 0);
+        assert_eq!(vip.cpu().r[0], 0x0F); // No interrupt, no DMA: R0 is where the program left it
+    }
+
+    #[test]
+    fn monitor_at_zero_after_reset() {
+        // The monitor jumps to its own address, after which RAM shows at 0x0000
+        let monitor = vec![0xC0, 0x80, 0x03, 0x00];
+        let mut vip = Vip::new(monitor).load(0, &[0x7B]).unwrap(); // SEQ in RAM
+        assert_eq!(vip.board.read(0x0000), 0xC0);
+        vip.cpu.step(&mut vip.board, false);
+        assert_eq!(vip.cpu().pc(), 0x8003);
+        vip.cpu.step(&mut vip.board, false); // The first fetch from 0x8000 up latches A15
+        assert_eq!(vip.board.read(0x0000), 0x7B);
+        assert_eq!(vip.board.read(0x8201), 0x80); // Mirrored
+        vip.board.write(0x8000, 0x12);
+        assert_eq!(vip.board.read(0x8000), 0xC0); // Read-only
+    }
+
+    #[test]
+    fn keypad() {
+        let mut vip = Vip::new(Vec::new());
+        vip.update_key(0x5, true).unwrap();
+        assert_eq!(vip.update_key(0x10, true), Err(ExecError::InvalidKey(0x10)));
+        vip.board.output(2, 0x4);
+        assert!(!vip.board.flag(3));
+        vip.board.output(2, 0x5);
+        assert!(vip.board.flag(3));
+    }
+
+    #[test]
+    fn program_must_fit() {
+        assert!(Vip::new(Vec::new()).load(0xF00, &[0; 0x100]).is_ok());
+        assert!(Vip::new(Vec::new()).load(0xF00, &[0; 0x101]).is_err());
+    }
+}
@@ -0,0 +1,193 @@
+use std::sync::mpsc::{self, Receiver, Sender, TryIter};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::emulator::Framebuffer;
+use crate::machine::Machine;
+
+/// Sent by the UI to the emulation thread
+pub enum Command<M> {
+    /// Presses or releases a key of the hex keypad
+    Key(u8, bool),
+    Pause,
+    Resume,
+    /// Replaces the machine by a new one from the factory, and resumes
+    Reset,
+    /// Runs a function on the machine between two frames
+    Apply(Box<dyn FnOnce(&mut M) + Send>),
+    /// Stops the thread, which returns the machine
+    Quit,
+}
+
+/// Sent by the emulation thread to the UI
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A frame was emulated, the display is only sent when it changed
+    Frame { display: Option<Box<Framebuffer>>, beeping: bool },
+    /// The machine stopped on an error (or couldn't be reset) and waits for a reset
+    Stopped(String),
+}
+
+/// A machine emulated at 60 frames per second on its own thread,
+/// so that rendering and window events don't slow it down
+pub struct Emulation<M> {
+    commands: Sender<Command<M>>,
+    events: Receiver<Event>,
+    handle: JoinHandle<Option<M>>,
+}
+
+impl<M: Machine + Send + 'static> Emulation<M> {
+    /// Starts a thread emulating machines built by `make`, which is called again on resets
+    pub fn spawn<F>(mut make: F) -> Self
+    where
+        F: FnMut() -> Result<M, String> + Send + 'static,
+    {
+        let (commands, command_rx) = mpsc::channel();
+        let (event_tx, events) = mpsc::channel();
+        let handle = thread::spawn(move || {
+            let mut machine = make().map_err(|e| { let _ = event_tx.send(Event::Stopped(e)); }).ok();
+            let frame_time = Duration::from_nanos(1_000_000_000 / 60);
+            let mut paused = false;
+            let mut next_frame = Instant::now();
+            // Keys pressed since the last frame, their releases wait for a frame to see the press
+            let mut pressed = [false; 16];
+            let mut released = [false; 16];
+            loop {
+                // Block while there is nothing to emulate
+                let command = if paused || machine.is_none() {
+                    match command_rx.recv() {
+                        Ok(c) => Some(c),
+                        Err(_) => return machine // The UI is gone
+                    }
+                } else {
+                    command_rx.try_recv().ok()
+                };
+                if let Some(command) = command {
+                    match command {
+                        Command::Key(key, down) => if let Some(m) = machine.as_mut() {
+                            let k = key as usize;
+                            if !down && pressed.get(k) == Some(&true) {
+                                released[k] = true; // A tap between two frames
+                            } else {
+                                if down && k < 16 {
+                                    pressed[k] = true;
+                                    released[k] = false;
+                                }
+                                let _ = m.update_key(key, down);
+                            }
+                        },
+                        Command::Pause => paused = true,
+                        Command::Resume => {
+                            paused = false;
+                            next_frame = Instant::now();
+                        },
+                        Command::Reset => {
+                            machine = make().map_err(|e| { let _ = event_tx.send(Event::Stopped(e)); }).ok();
+                            pressed = [false; 16];
+                            released = [false; 16];
+                            paused = false;
+                            next_frame = Instant::now();
+                        },
+                        Command::Apply(f) => if let Some(m) = machine.as_mut() { f(m) },
+                        Command::Quit => return machine,
+                    }
+                    continue; // Handle every pending command before the next frame
+                }
+
+                let m = machine.as_mut().unwrap(); // Checked above
+                let result = m.run_frame();
+                for key in (0..16).filter(|k| released[*k as usize]) {
+                    let _ = m.update_key(key, false);
+                }
+                pressed = [false; 16];
+                released = [false; 16];
+                if let Err(e) = result {
+                    let _ = event_tx.send(Event::Stopped(e));
+                    paused = true;
+                    continue;
+                }
+                let display = m.framebuffer().is_dirty().then(|| Box::new(*m.framebuffer()));
+                m.clear_dirty();
+                if event_tx.send(Event::Frame { display, beeping: m.is_beeping() }).is_err() {
+                    return machine;
+                }
+
+                // Keep a steady pace, without catching up on frames lost to a slow host
+                next_frame += frame_time;
+                let now = Instant::now();
+                if next_frame > now { thread::sleep(next_frame - now); } else { next_frame = now; }
+            }
+        });
+        Self { commands, events, handle }
+    }
+
+    /// Sends a command to the emulation thread
+    pub fn send(&self, command: Command<M>) {
+        let _ = self.commands.send(command); // Nothing to do if the thread is gone
+    }
+
+    /// Returns the events received since the last call, without waiting
+    pub fn events(&self) -> TryIter<'_, Event> {
+        self.events.try_iter()
+    }
+
+    /// Stops the thread and returns the machine, if there was one
+    pub fn quit(self) -> Option<M> {
+        self.send(Command::Quit);
+        self.handle.join().ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::ExecError;
+
+    // Records the state of key 5 on each frame
+    struct Keys {
+        down: bool,
+        frames: Vec<bool>,
+        display: Framebuffer,
+    }
+
+    impl Machine for Keys {
+        fn run_frame(&mut self) -> Result<(), String> {
+            self.frames.push(self.down);
+            Ok(())
+        }
+
+        fn framebuffer(&self) -> &Framebuffer {
+            &self.display
+        }
+
+        fn clear_dirty(&mut self) {
+            self.display.clear_dirty();
+        }
+
+        fn update_key(&mut self, key: u8, val: bool) -> Result<(), ExecError> {
+            if key == 5 { self.down = val; }
+            Ok(())
+        }
+
+        fn is_beeping(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn tap_between_frames_is_seen() {
+        let emulation = Emulation::spawn(|| Ok(Keys { down: false, frames: Vec::new(), display: Framebuffer::new() }));
+        emulation.send(Command::Pause);
+        emulation.send(Command::Key(5, true));
+        emulation.send(Command::Key(5, false));
+        emulation.send(Command::Resume);
+        let mut frames = 0;
+        while frames < 3 {
+            frames += emulation.events().count();
+            thread::sleep(Duration::from_millis(5));
+        }
+        let keys = emulation.quit().unwrap();
+        assert!(keys.frames.iter().any(|d| *d));
+        assert!(!keys.down);
+    }
+}